@@ -0,0 +1,23 @@
+#include <stdio.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+void child_work(int n) {
+    printf("child working on %d\n", n);
+}
+
+int main(int argc, char *argv[]) {
+    pid_t pid = fork();
+    if (pid == 0) {
+        child_work(1);
+        if (argc > 1) {
+            // Replace the child with the program given on the command line
+            execv(argv[1], &argv[1]);
+            perror("execv");
+        }
+        return 0;
+    }
+    waitpid(pid, NULL, 0);
+    printf("parent done\n");
+    return 0;
+}
@@ -0,0 +1,9 @@
+#include <stdio.h>
+
+int greet_count = 0;
+
+int greet(const char *name) {
+    greet_count++;
+    printf("Hello, %s!\n", name);
+    return greet_count;
+}
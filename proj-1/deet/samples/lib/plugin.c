@@ -0,0 +1,7 @@
+#include <stdio.h>
+
+int plugin_run(int value) {
+    int doubled = value * 2;
+    printf("plugin_run(%d) = %d\n", value, doubled);
+    return doubled;
+}
@@ -0,0 +1,41 @@
+#include <stdio.h>
+#include <stdlib.h>
+
+// Built with -O2 (see the Makefile), so variables live in registers and move around
+
+struct pair {
+    long a;
+    long b;
+};
+
+__attribute__((noinline)) long checksum(const long *values, int count) {
+    long sum = 0;
+    for (int i = 0; i < count; i++) {
+        sum = sum * 31 + values[i];
+    }
+    return sum;
+}
+
+__attribute__((noinline)) long report(long total, int scale) {
+    printf("total %ld\n", total * scale);
+    return total + scale;
+}
+
+__attribute__((noinline)) struct pair split(long value) {
+    struct pair p = {value / 1000, value % 1000};
+    printf("%ld %ld\n", p.a, p.b);
+    return p;
+}
+
+int main(int argc, char **argv) {
+    const int count = 8;
+    long *values = malloc(count * sizeof(long));
+    for (int i = 0; i < count; i++) {
+        values[i] = i * i + argc;
+    }
+    long sum = checksum(values, count);
+    long result = report(sum, argc + 2);
+    struct pair p = split(result);
+    free(values);
+    return (int)(p.a + p.b) & 1;
+}
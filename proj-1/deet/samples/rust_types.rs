@@ -0,0 +1,44 @@
+#[derive(Debug)]
+enum Shape {
+    Circle { radius: u32 },
+    Rect { width: u32, height: u32 },
+    Empty,
+}
+
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[inline(never)]
+fn area(shape: &Shape) -> u32 {
+    match shape {
+        Shape::Circle { radius } => 3 * radius * radius,
+        Shape::Rect { width, height } => width * height,
+        Shape::Empty => 0,
+    }
+}
+
+#[inline(never)]
+fn describe(name: &str, values: &Vec<i32>, shape: &Shape) -> usize {
+    let count = values.len();
+    println!("{} has {} values, shape {:?} of area {}", name, count, shape, area(shape));
+    count + name.len()
+}
+
+fn main() {
+    let greeting = String::from("hello");
+    let slice: &str = "world";
+    let numbers = vec![1, 2, 3];
+    let maybe: Option<i32> = Some(7);
+    let nothing: Option<i32> = None;
+    let boxed = Box::new(Point { x: 1, y: 2 });
+    let named: Option<&str> = Some("niche");
+    let missing: Option<Box<Point>> = None;
+    let shape = Shape::Rect { width: 3, height: 4 };
+    let circle = Shape::Circle { radius: 5 };
+    let empty = Shape::Empty;
+    let total = describe(slice, &numbers, &shape);
+    println!("{} {} {:?} {:?} {} {}", greeting, total, maybe, nothing, boxed.x, boxed.y);
+    println!("{:?} {} {} {}", named, missing.is_none(), area(&circle), area(&empty));
+}
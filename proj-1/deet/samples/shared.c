@@ -0,0 +1,26 @@
+#include <dlfcn.h>
+#include <stdio.h>
+
+int greet(const char *name);
+
+int counter = 0;
+
+int add(int a, int b) {
+    counter++;
+    return a + b;
+}
+
+int main() {
+    int sum = add(1, 2);
+    greet("world");
+
+    // Loaded only now, so breakpoints in it stay pending until this point
+    void *plugin = dlopen("libplugin.so", RTLD_NOW);
+    if (!plugin) {
+        printf("dlopen failed: %s\n", dlerror());
+        return 1;
+    }
+    int (*plugin_run)(int) = (int (*)(int))dlsym(plugin, "plugin_run");
+    printf("plugin returned %d\n", plugin_run(sum));
+    return 0;
+}
@@ -0,0 +1,19 @@
+#include <signal.h>
+#include <stdio.h>
+
+volatile sig_atomic_t received = 0;
+
+void on_signal(int sig) {
+    received++;
+}
+
+int main() {
+    signal(SIGUSR1, on_signal);
+    signal(SIGALRM, on_signal);
+    for (int i = 0; i < 3; i++) {
+        raise(SIGUSR1);
+    }
+    raise(SIGALRM);
+    printf("received %d signals\n", received);
+    return 0;
+}
@@ -0,0 +1,16 @@
+#include <stdio.h>
+
+int calls = 0;
+
+int add(int a, int b) {
+    calls++;
+    return a + b;
+}
+
+int main() {
+    int sum = add(1, 2);
+    printf("sum = %d\n", sum);
+    sum = add(sum, 3);
+    printf("sum = %d\n", sum);
+    return 0;
+}
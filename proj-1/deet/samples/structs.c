@@ -0,0 +1,50 @@
+#include <stdio.h>
+#include <stdlib.h>
+
+enum color { RED, GREEN, BLUE };
+
+typedef unsigned long size_t_like;
+
+struct point {
+    int x;
+    int y;
+};
+
+struct node {
+    int value;
+    struct node *next;
+};
+
+struct shape {
+    char name[16];
+    enum color color;
+    struct point corners[2];
+    unsigned int visible : 1;
+    int depth : 7;
+    union {
+        int radius;
+        float scale;
+    };
+};
+
+struct point origin = {0, 0};
+int squares[5] = {0, 1, 4, 9, 16};
+
+struct node *push(struct node *head, int value) {
+    struct node *n = malloc(sizeof(struct node));
+    n->value = value;
+    n->next = head;
+    return n;
+}
+
+int main() {
+    struct node *list = NULL;
+    for (int i = 1; i <= 3; i++) {
+        list = push(list, i * 10);
+    }
+    struct shape box = {"box", BLUE, {{1, 2}, {3, 4}}, 1, -5, {7}};
+    size_t_like count = 3;
+    int grid[2][3] = {{1, 2, 3}, {4, 5, 6}};
+    printf("%d %s %lu %d\n", list->next->value, box.name, count, grid[1][2]);
+    return 0;
+}
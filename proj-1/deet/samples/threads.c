@@ -0,0 +1,26 @@
+#include <pthread.h>
+#include <stdio.h>
+
+int counter = 0;
+
+void *worker(void *arg) {
+    int id = *(int *)arg;
+    for (int i = 0; i < 3; i++) {
+        counter += id;
+        printf("worker %d: %d\n", id, i);
+    }
+    return NULL;
+}
+
+int main() {
+    pthread_t threads[2];
+    int ids[2] = {1, 2};
+    for (int i = 0; i < 2; i++) {
+        pthread_create(&threads[i], NULL, worker, &ids[i]);
+    }
+    for (int i = 0; i < 2; i++) {
+        pthread_join(threads[i], NULL);
+    }
+    printf("counter = %d\n", counter);
+    return 0;
+}
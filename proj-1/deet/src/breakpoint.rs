@@ -0,0 +1,354 @@
+use crate::dwarf_data::{DwarfData, Line, Type};
+use crate::expr::Expr;
+use crate::inferior::WatchpointHit;
+
+/// A condition attached to a breakpoint with `break LOCATION if CONDITION`.
+pub struct Condition {
+    pub text: String,
+    pub expr: Expr,
+}
+
+/// A breakpoint set by the user. Breakpoints are owned by the debugger rather than the inferior,
+/// so they keep their ids and settings when the program is restarted. The inferior only keeps
+/// track of which bytes it has replaced with 0xcc.
+pub struct Breakpoint {
+    pub id: usize,
+    /// Location as the user gave it, e.g. "main" or "count.c:7", so that it can be looked up
+    /// again when the program is replaced by exec or libraries are loaded
+    pub spec: String,
+    pub addr: usize,
+    /// The location isn't in the program yet, e.g. a function in a library that will be loaded
+    /// later. Pending breakpoints have no address.
+    pub pending: bool,
+    pub function: Option<String>,
+    pub location: Option<Line>,
+    pub enabled: bool,
+    pub hit_count: usize,
+    pub condition: Option<Condition>,
+    /// Number of upcoming hits that should not stop the program
+    pub ignore_count: usize,
+}
+
+/// x86-64 has four debug address registers (DR0-DR3), so at most four hardware watchpoints can
+/// be active at a time.
+pub const MAX_WATCHPOINTS: usize = 4;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    /// Stop when the watched memory is written to (`watch`)
+    Write,
+    /// Stop when the watched memory is read (`rwatch`). x86 can't trap on reads alone, so these
+    /// are implemented like access watchpoints.
+    Read,
+    /// Stop when the watched memory is read or written (`awatch`)
+    Access,
+}
+
+/// A hardware watchpoint on len bytes of memory at addr. Watchpoints share ids with breakpoints.
+pub struct Watchpoint {
+    pub id: usize,
+    pub kind: WatchKind,
+    /// What the user asked to watch, e.g. "i" or "*0x404028"
+    pub expression: String,
+    pub addr: usize,
+    pub len: usize,
+    /// Type used to display the watched value
+    pub entity_type: Type,
+    /// Whether the watched memory is a local variable, which is only valid during the current run
+    pub local: bool,
+    pub enabled: bool,
+    pub hit_count: usize,
+    /// Number of upcoming hits that should not stop the program
+    pub ignore_count: usize,
+    /// Contents of the watched memory the last time we looked, used to report old and new values
+    pub value: Option<Vec<u8>>,
+}
+
+impl Watchpoint {
+    pub fn description(&self) -> &'static str {
+        match self.kind {
+            WatchKind::Write => "Hardware watchpoint",
+            WatchKind::Read => "Hardware read watchpoint",
+            WatchKind::Access => "Hardware access (read/write) watchpoint",
+        }
+    }
+}
+
+pub struct BreakpointTable {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    next_id: usize,
+}
+
+impl BreakpointTable {
+    pub fn new() -> BreakpointTable {
+        BreakpointTable {
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Adds an enabled breakpoint and returns its id.
+    pub fn add(
+        &mut self,
+        spec: String,
+        addr: usize,
+        function: Option<String>,
+        location: Option<Line>,
+        condition: Option<Condition>,
+    ) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint {
+            id,
+            spec,
+            addr,
+            pending: false,
+            function,
+            location,
+            enabled: true,
+            hit_count: 0,
+            condition,
+            ignore_count: 0,
+        });
+        id
+    }
+
+    /// Adds a breakpoint whose location can't be found yet, and returns its id.
+    pub fn add_pending(&mut self, spec: String, condition: Option<Condition>) -> usize {
+        let id = self.add(spec, 0, None, None, condition);
+        self.get_mut(id).unwrap().pending = true;
+        id
+    }
+
+    /// Adds an enabled watchpoint and returns its id. The caller is responsible for checking that
+    /// there is a free debug register for it.
+    pub fn add_watchpoint(
+        &mut self,
+        kind: WatchKind,
+        expression: String,
+        addr: usize,
+        entity_type: Type,
+        local: bool,
+    ) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.watchpoints.push(Watchpoint {
+            id,
+            kind,
+            expression,
+            addr,
+            len: entity_type.size,
+            entity_type,
+            local,
+            enabled: true,
+            hit_count: 0,
+            ignore_count: 0,
+            value: None,
+        });
+        id
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Breakpoint> {
+        self.breakpoints.iter_mut().find(|bp| bp.id == id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Breakpoint> {
+        let index = self.breakpoints.iter().position(|bp| bp.id == id)?;
+        Some(self.breakpoints.remove(index))
+    }
+
+    pub fn watchpoint_mut(&mut self, id: usize) -> Option<&mut Watchpoint> {
+        self.watchpoints.iter_mut().find(|wp| wp.id == id)
+    }
+
+    pub fn remove_watchpoint(&mut self, id: usize) -> Option<Watchpoint> {
+        let index = self.watchpoints.iter().position(|wp| wp.id == id)?;
+        Some(self.watchpoints.remove(index))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.breakpoints.iter()
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = &Watchpoint> {
+        self.watchpoints.iter()
+    }
+
+    /// Returns the enabled watchpoints, in the order they are assigned to debug registers.
+    pub fn enabled_watchpoints_mut(&mut self) -> impl Iterator<Item = &mut Watchpoint> {
+        self.watchpoints.iter_mut().filter(|wp| wp.enabled)
+    }
+
+    pub fn enabled_watchpoint_count(&self) -> usize {
+        self.watchpoints.iter().filter(|wp| wp.enabled).count()
+    }
+
+    /// Ids of all breakpoints and watchpoints.
+    pub fn ids(&self) -> Vec<usize> {
+        let mut ids: Vec<usize> = self.breakpoints.iter().map(|bp| bp.id).collect();
+        ids.extend(self.watchpoints.iter().map(|wp| wp.id));
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Removes the watchpoints on local variables. Their addresses point into stack frames of a
+    /// run of the program that is over.
+    pub fn remove_local_watchpoints(&mut self) {
+        for wp in self.watchpoints.iter().filter(|wp| wp.local) {
+            println!(
+                "Watchpoint {} deleted because the program has left the block in which its \
+                 expression is valid.",
+                wp.id
+            );
+        }
+        self.watchpoints.retain(|wp| !wp.local);
+    }
+
+    /// Returns true if any enabled breakpoint is set at addr. Several breakpoints may share an
+    /// address, in which case the 0xcc byte must stay in place until all of them are disabled.
+    pub fn is_enabled_at(&self, addr: usize) -> bool {
+        self.breakpoints.iter().any(|bp| bp.enabled && !bp.pending && bp.addr == addr)
+    }
+
+    /// Called when the inferior reaches addr. Counts a hit on every enabled breakpoint there whose
+    /// condition holds, and returns the ids of the ones that should stop the program (i.e. that
+    /// are not being ignored). Conditions are evaluated with eval; a condition that cannot be
+    /// evaluated stops the program so the user can fix it.
+    pub fn check_hit<F>(&mut self, addr: usize, eval: F) -> Vec<usize>
+    where
+        F: Fn(&Expr) -> Result<i64, String>,
+    {
+        let mut ids = Vec::new();
+        for bp in self.breakpoints.iter_mut() {
+            if !bp.enabled || bp.pending || bp.addr != addr {
+                continue;
+            }
+            if let Some(condition) = &bp.condition {
+                match eval(&condition.expr) {
+                    Ok(0) => continue,
+                    Ok(_) => {}
+                    Err(err) => {
+                        println!("Error in testing condition for breakpoint {}: {}", bp.id, err);
+                        bp.hit_count += 1;
+                        ids.push(bp.id);
+                        continue;
+                    }
+                }
+            }
+            bp.hit_count += 1;
+            if bp.ignore_count > 0 {
+                bp.ignore_count -= 1;
+                continue;
+            }
+            ids.push(bp.id);
+        }
+        ids
+    }
+
+    /// Hit counts describe the current run of the program, so they start over on every run.
+    pub fn reset_hit_counts(&mut self) {
+        for bp in self.breakpoints.iter_mut() {
+            bp.hit_count = 0;
+        }
+        for wp in self.watchpoints.iter_mut() {
+            wp.hit_count = 0;
+        }
+    }
+
+    pub fn print(&self) {
+        if self.breakpoints.is_empty() && self.watchpoints.is_empty() {
+            println!("No breakpoints or watchpoints.");
+            return;
+        }
+        println!("{:<7} {:<14} {:<3} {:<18} What", "Num", "Type", "Enb", "Address");
+        for id in self.ids() {
+            match self.breakpoints.iter().find(|bp| bp.id == id) {
+                Some(bp) => Self::print_breakpoint(bp),
+                None => Self::print_watchpoint(self.watchpoints.iter().find(|wp| wp.id == id).unwrap()),
+            }
+        }
+    }
+
+    /// Reports a watchpoint that triggered, showing how the watched value changed. Pointers in the
+    /// values aren't followed, since the memory they point to may have changed too.
+    pub fn print_watchpoint_hit(&self, hit: &WatchpointHit, debug_data: &DwarfData) {
+        let wp = match self.watchpoints.iter().find(|wp| wp.id == hit.id) {
+            Some(wp) => wp,
+            None => return,
+        };
+        println!("{} {}: {}", wp.description(), wp.id, wp.expression);
+        let new_value = wp.entity_type.format_value(&hit.new_value, debug_data);
+        match &hit.old_value {
+            Some(old_value) if *old_value != hit.new_value => {
+                println!("Old value = {}", wp.entity_type.format_value(old_value, debug_data));
+                println!("New value = {}", new_value);
+            }
+            _ => println!("Value = {}", new_value),
+        }
+    }
+
+    fn print_watchpoint(wp: &Watchpoint) {
+        let kind = match wp.kind {
+            WatchKind::Write => "hw watchpoint",
+            WatchKind::Read => "read watchpoint",
+            WatchKind::Access => "acc watchpoint",
+        };
+        println!(
+            "{:<7} {:<14} {:<3} {:#018x} {}",
+            wp.id,
+            kind,
+            if wp.enabled { "y" } else { "n" },
+            wp.addr,
+            wp.expression
+        );
+        if wp.hit_count > 0 {
+            println!(
+                "\tbreakpoint already hit {} time{}",
+                wp.hit_count,
+                if wp.hit_count == 1 { "" } else { "s" }
+            );
+        }
+        if wp.ignore_count > 0 {
+            println!("\tWill ignore next {} crossings of breakpoint.", wp.ignore_count);
+        }
+    }
+
+    fn print_breakpoint(bp: &Breakpoint) {
+        let mut what = String::new();
+        if let Some(function) = &bp.function {
+            what.push_str(&format!("in {} ", function));
+        }
+        if let Some(location) = &bp.location {
+            what.push_str(&format!("at {}", location));
+        }
+        // Pending breakpoints are described by where the user asked for them
+        let (address, what) = if bp.pending {
+            ("<PENDING>".to_string(), bp.spec.as_str())
+        } else {
+            (format!("{:#018x}", bp.addr), what.trim_end())
+        };
+        println!(
+            "{:<7} {:<14} {:<3} {:<18} {}",
+            bp.id,
+            "breakpoint",
+            if bp.enabled { "y" } else { "n" },
+            address,
+            what
+        );
+        if let Some(condition) = &bp.condition {
+            println!("\tstop only if {}", condition.text);
+        }
+        if bp.hit_count > 0 {
+            println!(
+                "\tbreakpoint already hit {} time{}",
+                bp.hit_count,
+                if bp.hit_count == 1 { "" } else { "s" }
+            );
+        }
+        if bp.ignore_count > 0 {
+            println!("\tWill ignore next {} crossings of breakpoint.", bp.ignore_count);
+        }
+    }
+}
@@ -0,0 +1,289 @@
+//! Post-mortem debugging of ELF core dumps: the registers of the thread that crashed come from its
+//! NT_PRSTATUS note and memory from the PT_LOAD segments, with the files listed in the NT_FILE
+//! note filling in the code that the kernel leaves out of the dump.
+
+use crate::target::{Region, Target};
+use crate::unwind::Unwinder;
+use memmap2::Mmap;
+use nix::sys::signal::Signal;
+use object::elf::{self, FileHeader64};
+use object::read::elf::{FileHeader, ProgramHeader};
+use object::Endianness;
+use std::cell::RefCell;
+use std::convert::{TryFrom, TryInto};
+use std::fs::File;
+use std::mem::size_of;
+use std::os::unix::fs::FileExt;
+
+/// Offset of pr_cursig, the signal that killed the process, in struct elf_prstatus.
+const PRSTATUS_CURSIG: usize = 12;
+/// Offset of pr_reg, the general purpose registers laid out like user_regs_struct, in struct
+/// elf_prstatus.
+const PRSTATUS_REG: usize = 112;
+/// Offset and length of pr_psargs, the start of the command line, in struct elf_prpsinfo.
+const PRPSINFO_PSARGS: usize = 56;
+const PSARGS_LEN: usize = 80;
+
+/// A PT_LOAD segment: memory from vaddr to vaddr + memsz, of which the first filesz bytes are in
+/// the core file at offset.
+struct Segment {
+    vaddr: usize,
+    memsz: usize,
+    filesz: usize,
+    offset: usize,
+    /// e.g. "r-xp", like in /proc/PID/maps
+    perms: String,
+}
+
+/// A file mapped into the process, from the NT_FILE note.
+struct FileMapping {
+    start: usize,
+    end: usize,
+    /// Offset in the file of start
+    offset: usize,
+    path: String,
+}
+
+pub struct CoreFile {
+    data: Mmap,
+    segments: Vec<Segment>,
+    files: Vec<FileMapping>,
+    regs: libc::user_regs_struct,
+    fpregs: Option<libc::user_fpregs_struct>,
+    siginfo: Option<libc::siginfo_t>,
+    signal: Option<Signal>,
+    command: String,
+    selected_frame: usize,
+    unwinder: RefCell<Unwinder>,
+}
+
+/// Copies a C struct out of the bytes of a note, or returns None if the note is too short.
+fn read_struct<T>(bytes: &[u8]) -> Option<T> {
+    if bytes.len() < size_of::<T>() {
+        return None;
+    }
+    Some(unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<usize> {
+    let word = bytes.get(offset..offset + size_of::<u64>())?;
+    Some(u64::from_le_bytes(word.try_into().ok()?) as usize)
+}
+
+/// Parses an NT_FILE note: a count and page size, then start, end and page offset for each
+/// mapping, then their paths as NUL-terminated strings.
+fn parse_file_note(desc: &[u8]) -> Vec<FileMapping> {
+    let (count, page_size) = match (read_u64(desc, 0), read_u64(desc, 8)) {
+        (Some(count), Some(page_size)) => (count, page_size),
+        _ => return Vec::new(),
+    };
+    let names_start = 16 + count * 24;
+    let names = desc.get(names_start..).unwrap_or_default().split(|byte| *byte == 0);
+    (0..count)
+        .zip(names)
+        .filter_map(|(index, name)| {
+            let entry = 16 + index * 24;
+            Some(FileMapping {
+                start: read_u64(desc, entry)?,
+                end: read_u64(desc, entry + 8)?,
+                offset: read_u64(desc, entry + 16)? * page_size,
+                path: String::from_utf8_lossy(name).into_owned(),
+            })
+        })
+        .collect()
+}
+
+fn segment_perms(flags: u32) -> String {
+    let flag = |bit, letter| if flags & bit != 0 { letter } else { '-' };
+    [flag(elf::PF_R, 'r'), flag(elf::PF_W, 'w'), flag(elf::PF_X, 'x'), 'p']
+        .iter()
+        .collect()
+}
+
+impl CoreFile {
+    /// Loads a core dump. Only the registers of the first thread in it, the one that received the
+    /// fatal signal, are kept.
+    pub fn open(path: &str) -> Result<CoreFile, String> {
+        let file = File::open(path).map_err(|err| format!("{}: {}", path, err))?;
+        let data = unsafe { Mmap::map(&file) }.map_err(|err| format!("{}: {}", path, err))?;
+        let not_core = |_| format!("\"{}\" is not a core dump: file format not recognized", path);
+        let header = FileHeader64::<Endianness>::parse(&*data).map_err(not_core)?;
+        let endian = header.endian().map_err(not_core)?;
+        if header.e_type(endian) != elf::ET_CORE {
+            return Err(format!("\"{}\" is not a core dump", path));
+        }
+
+        let mut segments = Vec::new();
+        let mut files = Vec::new();
+        let mut regs = None;
+        let mut fpregs = None;
+        let mut siginfo = None;
+        let mut signal = None;
+        let mut command = String::new();
+        for segment in header.program_headers(endian, &*data).map_err(not_core)? {
+            if segment.p_type(endian) == elf::PT_LOAD {
+                segments.push(Segment {
+                    vaddr: segment.p_vaddr(endian) as usize,
+                    memsz: segment.p_memsz(endian) as usize,
+                    filesz: segment.p_filesz(endian) as usize,
+                    offset: segment.p_offset(endian) as usize,
+                    perms: segment_perms(segment.p_flags(endian)),
+                });
+            }
+            let mut notes = match segment.notes(endian, &*data).map_err(not_core)? {
+                Some(notes) => notes,
+                None => continue,
+            };
+            while let Some(note) = notes.next().map_err(not_core)? {
+                let desc = note.desc();
+                match note.n_type(endian) {
+                    // Later NT_PRSTATUS notes are for the other threads
+                    elf::NT_PRSTATUS if regs.is_none() => {
+                        regs = desc.get(PRSTATUS_REG..).and_then(read_struct);
+                        signal = desc
+                            .get(PRSTATUS_CURSIG..PRSTATUS_CURSIG + 2)
+                            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+                            .and_then(|sig| Signal::try_from(sig as i32).ok());
+                    }
+                    elf::NT_PRFPREG if fpregs.is_none() => fpregs = read_struct(desc),
+                    elf::NT_SIGINFO if siginfo.is_none() => siginfo = read_struct(desc),
+                    elf::NT_PRPSINFO => {
+                        let psargs = desc
+                            .get(PRPSINFO_PSARGS..PRPSINFO_PSARGS + PSARGS_LEN)
+                            .unwrap_or_default();
+                        let len = psargs.iter().position(|byte| *byte == 0).unwrap_or(psargs.len());
+                        command = String::from_utf8_lossy(&psargs[..len]).trim_end().to_string();
+                    }
+                    elf::NT_FILE => files = parse_file_note(desc),
+                    _ => {}
+                }
+            }
+        }
+        let regs = regs.ok_or(format!("\"{}\" has no register notes", path))?;
+        Ok(CoreFile {
+            data,
+            segments,
+            files,
+            regs,
+            fpregs,
+            siginfo,
+            signal,
+            command,
+            selected_frame: 0,
+            unwinder: RefCell::new(Unwinder::new()),
+        })
+    }
+
+    /// The command line the process was started with, as far as the kernel recorded it.
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// The signal that terminated the process.
+    pub fn signal(&self) -> Option<Signal> {
+        self.signal
+    }
+
+    /// The program the process was running, which is normally the first file it had mapped.
+    pub fn executable(&self) -> Option<&str> {
+        self.files.first().map(|file| file.path.as_str())
+    }
+
+    /// Reads bytes of memory the dump doesn't contain from the file mapped at addr.
+    fn read_mapped_file(&self, addr: usize, len: usize) -> Option<Vec<u8>> {
+        let mapping = self.files.iter().find(|file| file.start <= addr && addr < file.end)?;
+        let len = len.min(mapping.end - addr);
+        let mut bytes = vec![0u8; len];
+        let file = File::open(&mapping.path).ok()?;
+        let count = file
+            .read_at(&mut bytes, (mapping.offset + addr - mapping.start) as u64)
+            .ok()?;
+        bytes.truncate(count);
+        Some(bytes)
+    }
+}
+
+impl Target for CoreFile {
+    fn innermost_registers(&self) -> Result<libc::user_regs_struct, nix::Error> {
+        Ok(self.regs)
+    }
+
+    fn fp_registers(&self) -> Result<libc::user_fpregs_struct, nix::Error> {
+        self.fpregs.ok_or(nix::Error::ENODATA)
+    }
+
+    fn read_memory_partial(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            let addr = addr + bytes.len();
+            let remaining = len - bytes.len();
+            let segment = match self
+                .segments
+                .iter()
+                .find(|segment| segment.vaddr <= addr && addr < segment.vaddr + segment.memsz)
+            {
+                Some(segment) => segment,
+                None => break,
+            };
+            let offset = addr - segment.vaddr;
+            let chunk = if offset < segment.filesz {
+                let start = segment.offset + offset;
+                let count = remaining.min(segment.filesz - offset);
+                match self.data.get(start..start + count) {
+                    Some(chunk) => chunk.to_vec(),
+                    None => break,
+                }
+            } else {
+                // Pages the kernel didn't dump because they are unchanged from the mapped file
+                let count = remaining.min(segment.memsz - offset);
+                match self.read_mapped_file(addr, count) {
+                    Some(chunk) if !chunk.is_empty() => chunk,
+                    _ => break,
+                }
+            };
+            bytes.extend(chunk);
+        }
+        Ok(bytes)
+    }
+
+    fn memory_map(&self) -> Vec<Region> {
+        let rsp = self.regs.rsp as usize;
+        self.segments
+            .iter()
+            .map(|segment| {
+                let end = segment.vaddr + segment.memsz;
+                let file = self
+                    .files
+                    .iter()
+                    .find(|file| file.start <= segment.vaddr && segment.vaddr < file.end);
+                let path = match file {
+                    Some(file) => file.path.clone(),
+                    None if segment.vaddr <= rsp && rsp < end => "[stack]".to_string(),
+                    None => String::new(),
+                };
+                Region {
+                    start: segment.vaddr,
+                    end,
+                    perms: segment.perms.clone(),
+                    path,
+                }
+            })
+            .collect()
+    }
+
+    fn signal_info(&self) -> Result<libc::siginfo_t, nix::Error> {
+        self.siginfo.ok_or(nix::Error::ENODATA)
+    }
+
+    fn selected_frame(&self) -> usize {
+        self.selected_frame
+    }
+
+    fn set_selected_frame(&mut self, level: usize) {
+        self.selected_frame = level;
+    }
+
+    fn unwinder(&self) -> &RefCell<Unwinder> {
+        &self.unwinder
+    }
+}
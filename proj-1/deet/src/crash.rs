@@ -0,0 +1,87 @@
+//! Crash reports for programs that stop with a fault: what went wrong, at which address, and what
+//! the program had mapped there.
+
+use libc::siginfo_t;
+use crate::target::Region;
+use nix::sys::signal::Signal;
+
+/// Signals raised by an instruction that faulted, which get a crash report when they stop the
+/// program.
+pub const FAULT_SIGNALS: [Signal; 3] = [Signal::SIGSEGV, Signal::SIGBUS, Signal::SIGFPE];
+
+/// How far below the stack a faulting address can be to count as a stack overflow. The kernel
+/// grows the stack on demand, so the unmapped memory just below it is where a runaway recursion
+/// ends up.
+const STACK_GUARD_GAP: usize = 1 << 20;
+
+/// Why the kernel sent a fault signal, from the si_code values in <asm-generic/siginfo.h>, which
+/// the libc crate doesn't define for Linux.
+fn describe_code(sig: Signal, code: i32) -> Option<(&'static str, &'static str)> {
+    Some(match (sig, code) {
+        (_, 0) => ("SI_USER", "sent by kill()"),
+        (_, -1) => ("SI_QUEUE", "sent by sigqueue()"),
+        (_, -6) => ("SI_TKILL", "sent by tkill() or raise()"),
+        (Signal::SIGSEGV, 1) => ("SEGV_MAPERR", "address not mapped to object"),
+        (Signal::SIGSEGV, 2) => ("SEGV_ACCERR", "invalid permissions for mapped object"),
+        (Signal::SIGSEGV, 3) => ("SEGV_BNDERR", "failed address bound checks"),
+        (Signal::SIGSEGV, 4) => ("SEGV_PKUERR", "access denied by memory protection keys"),
+        (Signal::SIGBUS, 1) => ("BUS_ADRALN", "invalid address alignment"),
+        (Signal::SIGBUS, 2) => ("BUS_ADRERR", "nonexistent physical address"),
+        (Signal::SIGBUS, 3) => ("BUS_OBJERR", "object-specific hardware error"),
+        (Signal::SIGFPE, 1) => ("FPE_INTDIV", "integer divide by zero"),
+        (Signal::SIGFPE, 2) => ("FPE_INTOVF", "integer overflow"),
+        (Signal::SIGFPE, 3) => ("FPE_FLTDIV", "floating-point divide by zero"),
+        (Signal::SIGFPE, 4) => ("FPE_FLTOVF", "floating-point overflow"),
+        (Signal::SIGFPE, 5) => ("FPE_FLTUND", "floating-point underflow"),
+        (Signal::SIGFPE, 6) => ("FPE_FLTRES", "floating-point inexact result"),
+        (Signal::SIGFPE, 7) => ("FPE_FLTINV", "invalid floating-point operation"),
+        (Signal::SIGFPE, 8) => ("FPE_FLTSUB", "subscript out of range"),
+        _ => return None,
+    })
+}
+
+/// Says which region of the memory map an address falls in, e.g.
+/// "0x402010 is in /path/to/program (r--p)".
+fn describe_address(regions: &[Region], addr: usize) -> String {
+    if let Some(region) = regions.iter().find(|region| region.start <= addr && addr < region.end) {
+        let name = match region.path.as_str() {
+            "" => "anonymous memory",
+            path => path,
+        };
+        return format!(
+            "{:#x} is in {} ({}, {:#x}-{:#x})",
+            addr, name, region.perms, region.start, region.end
+        );
+    }
+    let below_stack = regions.iter().any(|region| {
+        region.path == "[stack]" && addr < region.start && region.start - addr <= STACK_GUARD_GAP
+    });
+    if below_stack {
+        format!("{:#x} is just below the stack; this may be a stack overflow", addr)
+    } else if addr < 0x1000 {
+        format!("{:#x} is not mapped; this looks like a NULL pointer dereference", addr)
+    } else {
+        format!("{:#x} is not in any mapped region", addr)
+    }
+}
+
+/// Prints what kind of fault the program stopped with and, if the kernel raised it, the address
+/// involved and what is mapped there.
+pub fn print_fault(sig: Signal, info: &siginfo_t, regions: &[Region]) {
+    let description = crate::signals::describe(sig);
+    match describe_code(sig, info.si_code) {
+        Some((_, reason)) if info.si_code <= 0 => {
+            // Signals sent by another process don't come with an address
+            println!("{} ({})", description, reason);
+            return;
+        }
+        Some((name, reason)) => println!("{}: {} ({})", description, reason, name),
+        None => println!("{} (si_code {})", description, info.si_code),
+    }
+    let addr = unsafe { info.si_addr() } as usize;
+    // For SIGFPE, the address is that of the instruction rather than of the memory accessed
+    match sig {
+        Signal::SIGFPE => println!("Faulting instruction at {}", describe_address(regions, addr)),
+        _ => println!("Faulting address {}", describe_address(regions, addr)),
+    }
+}
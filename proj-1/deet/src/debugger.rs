@@ -1,28 +1,95 @@
+use crate::breakpoint::{BreakpointTable, Condition, MAX_WATCHPOINTS};
+use crate::core_file::CoreFile;
+use crate::crash;
 use crate::debugger_command::DebuggerCommand;
-use crate::inferior::Inferior;
-use crate::inferior::Status;
-use crate::dwarf_data::{DwarfData, Error as DwarfError};
+use crate::inferior::{ForkPolicy, Inferior, Status};
+use crate::dwarf_data::{DwarfData, Error as DwarfError, Line, Location, Type};
+use crate::disassemble;
+use crate::examine::{self, Format};
+use crate::signals::{self, SignalTable};
+use crate::source::SourceFiles;
+use crate::expr::Expr;
+use crate::libraries;
+use crate::target::Target;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 use rustyline::history::FileHistory;
+use std::collections::VecDeque;
 use std::process::exit;
 
 fn parse_address(addr: &str) -> Option<usize> {
     let addr_without_0x = if addr.to_lowercase().starts_with("0x") {
         &addr[2..]
     } else {
-        &addr
+        addr
     };
     usize::from_str_radix(addr_without_0x, 16).ok()
 }
 
+/// Whether text is a plain C identifier, like a variable name.
+fn is_identifier(text: &str) -> bool {
+    !text.is_empty()
+        && !text.starts_with(|c: char| c.is_ascii_digit())
+        && text.chars().all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+/// Like Debugger::program, but mutable, and borrowing only the fields it needs so that the debug
+/// info can be used alongside it.
+fn program_mut<'a>(
+    inferior: &'a mut Option<Inferior>,
+    core: &'a mut Option<CoreFile>,
+) -> Option<&'a mut dyn Target> {
+    match (inferior, core) {
+        (Some(inferior), _) => Some(inferior),
+        (None, Some(core)) => Some(core),
+        (None, None) => None,
+    }
+}
+
+/// How many lines `list` shows at a time.
+const LIST_LINES: usize = 10;
+
+/// How many lines before and after the current one are shown when the program stops.
+const STOP_CONTEXT_LINES: usize = 2;
+
+/// How many frames of the stack a crash report shows, so a stack overflow doesn't fill the screen.
+const CRASH_BACKTRACE_FRAMES: usize = 10;
+
 pub struct Debugger {
     target: String,
     history_path: String,
     debug_data: DwarfData,
-    breakpoints: Vec<usize>,
+    breakpoints: BreakpointTable,
     readline: Editor<(), FileHistory>,
     inferior: Option<Inferior>,
+    /// Processes that forked off the inferior and are held stopped because detach-on-fork is off
+    held: Vec<Inferior>,
+    fork_policy: ForkPolicy,
+    /// `set breakpoint pending`: whether breakpoints on locations that aren't loaded yet are made
+    /// pending (Some(true)), refused (Some(false)) or asked about (None, gdb's `auto`)
+    pending_breakpoints: Option<bool>,
+    signals: SignalTable,
+    /// Core dump to inspect while no process is running
+    core: Option<CoreFile>,
+    /// Format of the last `x` command, which the next one defaults to
+    examine_format: Format,
+    /// Address after the memory shown by the last `x` command, where a bare `x` continues
+    next_examine: Option<usize>,
+    source: SourceFiles,
+    /// File and last line shown by the last `list` command, where a bare `list` continues. None
+    /// to list around the current line instead.
+    listed: Option<(String, usize)>,
+    /// How the inferior stopped or ended after it was last resumed
+    last_status: Option<Status>,
+    /// Commands from `-ex`, `-x` and `source` that are run before reading any from the terminal
+    script: VecDeque<String>,
+    /// Quit once the script is done instead of reading commands from the terminal
+    batch: bool,
+    /// The command being run came from the script rather than the terminal, so questions it asks
+    /// get their default answer
+    scripted: bool,
 }
 
 impl Debugger {
@@ -36,12 +103,14 @@ impl Debugger {
                 std::process::exit(1);
             }
             Err(DwarfError::DwarfFormatError(err)) => {
-                println!("Could not debugging symbols from {}: {:?}", target, err);
+                println!("\"{}\": not in executable format: {:?}", target, err);
                 std::process::exit(1);
             }
         };
-
-        debug_data.print();
+        // Functions can still be found by name in the symbol table
+        if !debug_data.has_debug_info() {
+            println!("(No debugging symbols found in {})", target);
+        }
 
         let history_path = format!("{}/.deet_history", std::env::var("HOME").unwrap());
         let mut readline = Editor::<(), FileHistory>::new().expect("Failed to create readline editor");
@@ -52,121 +121,1292 @@ impl Debugger {
             target: target.to_string(),
             history_path,
             debug_data,
-            breakpoints: Vec::new(),
+            breakpoints: BreakpointTable::new(),
             readline,
             inferior: None,
+            held: Vec::new(),
+            fork_policy: ForkPolicy::default(),
+            pending_breakpoints: None,
+            signals: SignalTable::new(),
+            core: None,
+            examine_format: Format::default(),
+            next_examine: None,
+            source: SourceFiles::new(),
+            listed: None,
+            last_status: None,
+            script: VecDeque::new(),
+            batch: false,
+            scripted: false,
         }
     }
 
+    /// Prints the debug information of the program: its files, functions, variables and lines.
+    pub fn print_debug_data(&self) {
+        self.debug_data.print();
+    }
+
+    /// Queues commands to run, in order, before any are read from the terminal. With batch set,
+    /// the debugger quits once they are done.
+    pub fn queue_commands(&mut self, commands: Vec<String>, batch: bool) {
+        self.script.extend(commands);
+        self.batch = batch;
+    }
+
     pub fn run(&mut self) {
         loop {
-            match self.get_next_command() {
-                DebuggerCommand::Run(args) => {
-                    // If an inferior is already running, we need to kill it first.
-                    if let Some(inferior) = self.inferior.as_mut() {
-                        println!("Killing running inferior (pid {})", inferior.pid());
-                        let _ = inferior.kill();
-                        self.inferior = None;
-                    }
-                    if let Some(inferior) = Inferior::new(&self.target, &args, &self.breakpoints) {
-                        // Create the inferior
-                        self.inferior = Some(inferior);
-                        // make the inferior run
-                        // You may use self.inferior.as_mut().unwrap() to get a mutable reference
-                        // to the Inferior object
-                        let result = self.inferior.as_mut().unwrap().cont();
-
-                        match result {
-                            Ok(status) => {
-                                match status {
-                                    Status::Exited(code) => {
-                                        println!("Child exited (status {})", code);
-                                        self.inferior = None;
-                                    }
-                                    Status::Signaled(signal) => {
-                                        println!("Child signaled ({})", signal);
-                                        self.inferior = None;
-                                    }
-                                    Status::Stopped(signal, _rip) => {
-                                        println!("Child stopped (signal {})", signal);
-                                        let _ = self.inferior.as_mut().unwrap().print_stopped_location(&self.debug_data);
-                                    }
-                                }
-                            }
-                            Err(err) => {
-                                println!("Error continuing subprocess: {}", err);
-                            }
-                        }
+            let cmd = self.get_next_command();
+            self.execute(cmd);
+        }
+    }
 
-                    } else {
-                        println!("Error starting subprocess");
+    /// Carries out a command, whether it was typed at the prompt or translated from a packet by
+    /// the gdbserver.
+    pub fn execute(&mut self, cmd: DebuggerCommand) {
+        match cmd {
+            DebuggerCommand::Run(args) => {
+                if self.start(&args) {
+                    // make the inferior run
+                    let result = self.inferior.as_mut().unwrap().cont(&mut self.breakpoints, &self.debug_data);
+                    self.report_status(result, false);
+                }
+            }
+            DebuggerCommand::Attach(pid) => {
+                self.release_inferior();
+                self.attach(Pid::from_raw(pid));
+            }
+            DebuggerCommand::Detach => {
+                if self.inferior.is_some() {
+                    self.detach();
+                } else {
+                    println!("No running inferior");
+                }
+            }
+            DebuggerCommand::Quit => {
+                // If an inferior is already running, we need to get rid of it first for
+                // clean exit.
+                self.release_inferior();
+                // exit the debugger, with the exit status of the program like a shell would
+                exit(match self.last_status {
+                    Some(Status::Exited(code)) => code,
+                    Some(Status::Signaled(signal)) => 128 + signal as i32,
+                    _ => 0,
+                });
+            }
+            DebuggerCommand::Source(path) => self.source_script(&path),
+            DebuggerCommand::Continue => {
+                if let Some(inferior) = self.inferior.as_mut() {
+                    let result = inferior.cont(&mut self.breakpoints, &self.debug_data);
+                    self.report_status(result, false);
+                } else {
+                    println!("No running inferior");
+                }
+            }
+            DebuggerCommand::Signal(name) => self.continue_with_signal(&name),
+            DebuggerCommand::StepInstruction => {
+                if let Some(inferior) = self.inferior.as_mut() {
+                    let result = inferior.step_instruction(&mut self.breakpoints);
+                    self.report_status(result, true);
+                } else {
+                    println!("No running inferior");
+                }
+            }
+            DebuggerCommand::Step => {
+                if let Some(inferior) = self.inferior.as_mut() {
+                    let result = inferior.step_line(&mut self.breakpoints, &self.debug_data);
+                    self.report_status(result, true);
+                } else {
+                    println!("No running inferior");
+                }
+            }
+            DebuggerCommand::Next => {
+                if let Some(inferior) = self.inferior.as_mut() {
+                    let result = inferior.next_line(&mut self.breakpoints, &self.debug_data);
+                    self.report_status(result, true);
+                } else {
+                    println!("No running inferior");
+                }
+            }
+            DebuggerCommand::Finish => {
+                if let Some(inferior) = self.inferior.as_mut() {
+                    let result = inferior.finish(&mut self.breakpoints, &self.debug_data);
+                    self.report_status(result, true);
+                } else {
+                    println!("No running inferior");
+                }
+            }
+            DebuggerCommand::Backtrace => match self.program() {
+                Some(program) => {
+                    let _ = program.print_backtrace(&self.debug_data, None);
+                }
+                None => println!("No stack."),
+            },
+            DebuggerCommand::Frame(level) => {
+                let debug_data = &self.debug_data;
+                let program = match program_mut(&mut self.inferior, &mut self.core) {
+                    Some(program) => program,
+                    None => {
+                        println!("No stack.");
+                        return;
+                    }
+                };
+                if let Some(level) = level {
+                    match program.select_frame(debug_data, level) {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            println!("No frame at level {}.", level);
+                            return;
+                        }
+                        Err(err) => {
+                            println!("Cannot unwind the stack: {}", err);
+                            return;
+                        }
                     }
                 }
-                DebuggerCommand::Quit => {
-                    // If an inferior is already running, we need to kill it first for clean exit.
-                    if let Some(inferior) = self.inferior.as_mut() {
-                        println!("Killing running inferior (pid {})", inferior.pid());
-                        let _ = inferior.kill();
-                        self.inferior = None;
+                let _ = program.print_selected_frame(debug_data);
+                self.listed = None;
+            }
+            DebuggerCommand::Up(count) => self.move_frame(count as isize),
+            DebuggerCommand::Down(count) => self.move_frame(-(count as isize)),
+            DebuggerCommand::Print(text) => {
+                let expr = match Expr::parse(&text) {
+                    Ok(expr) => expr,
+                    Err(err) => {
+                        println!("{}", err);
+                        return;
+                    }
+                };
+                if let Some(program) = self.program() {
+                    match program.evaluate_value(&expr, &self.debug_data) {
+                        Ok(value) => println!("{} = {}", text, program.format_value(&value, &self.debug_data)),
+                        Err(err) => println!("{}", err),
                     }
-                    // exit the debugger
-                    exit(0);
+                } else {
+                    println!("No running inferior");
                 }
-                DebuggerCommand::Continue => {
-                    if let Some(inferior) = self.inferior.as_mut() {
-                        let result = inferior.cont();
-                        match result {
-                            Ok(status) => {
-                                match status {
-                                    Status::Exited(code) => {
-                                        println!("Child exited (status {})", code);
-                                        self.inferior = None;
-                                    }
-                                    Status::Signaled(signal) => {
-                                        println!("Child signaled ({})", signal);
-                                        self.inferior = None;
-                                    }
-                                    Status::Stopped(signal, _rip) => {
-                                        println!("Child stopped (signal {})", signal);
-                                        let _ = self.inferior.as_mut().unwrap().print_stopped_location(&self.debug_data);
-                                    }
-                                }
-                            }
-                            Err(err) => {
-                                println!("Error continuing subprocess: {}", err);
-                            }
+            }
+            DebuggerCommand::Examine(format, address) => self.examine(format, address),
+            DebuggerCommand::Disassemble(location) => self.disassemble(location),
+            DebuggerCommand::List(location) => self.list(location),
+            DebuggerCommand::InfoLocals => {
+                if let Some(program) = self.program() {
+                    if let Err(err) = program.print_locals(&self.debug_data) {
+                        println!("Cannot access memory: {}", err);
+                    }
+                } else {
+                    println!("No running inferior");
+                }
+            }
+            DebuggerCommand::Break(location, condition) => {
+                let condition = match condition {
+                    Some(text) => match Expr::parse(&text) {
+                        Ok(expr) => Some(Condition { text, expr }),
+                        Err(err) => {
+                            println!("{}", err);
+                            return;
+                        }
+                    },
+                    None => None,
+                };
+                let addr = match self.resolve_location(&location) {
+                    Ok(addr) => addr,
+                    Err(err) if self.may_load_later(&location) => {
+                        println!("{}", err);
+                        let pending = match self.pending_breakpoints {
+                            Some(pending) => pending,
+                            None => self.query("Make breakpoint pending on future shared library load?"),
+                        };
+                        if pending {
+                            let id = self.breakpoints.add_pending(location.clone(), condition);
+                            println!("Breakpoint {} ({}) pending.", id, location);
+                        }
+                        return;
+                    }
+                    Err(err) => {
+                        println!("{}", err);
+                        return;
+                    }
+                };
+                if let Some(inferior) = self.inferior.as_mut() {
+                    if let Err(err) = inferior.set_breakpoint(addr) {
+                        println!("Failed to set breakpoint at {:#x}: {}", addr, err);
+                        return;
+                    }
+                }
+                let id = self.breakpoints.add(
+                    location,
+                    addr,
+                    self.debug_data.get_function_from_addr(addr),
+                    self.debug_data.get_line_from_addr(addr),
+                    condition,
+                );
+                println!("Set breakpoint {} at {:#x}", id, addr);
+            }
+            DebuggerCommand::Watch(kind, target, len) => {
+                if self.breakpoints.enabled_watchpoint_count() >= MAX_WATCHPOINTS {
+                    println!(
+                        "Hardware watchpoint limit reached: at most {} watchpoints can be enabled at a time",
+                        MAX_WATCHPOINTS
+                    );
+                    return;
+                }
+                let (addr, entity_type, local) = match self.resolve_watch_target(&target, len) {
+                    Ok(resolved) => resolved,
+                    Err(err) => {
+                        println!("{}", err);
+                        return;
+                    }
+                };
+                let id = self.breakpoints.add_watchpoint(kind, target, addr, entity_type, local);
+                self.sync_watchpoints();
+                let wp = self.breakpoints.watchpoint_mut(id).unwrap();
+                println!("{} {}: {}", wp.description(), wp.id, wp.expression);
+            }
+            DebuggerCommand::Ignore(id, count) => {
+                // Watchpoints share the breakpoints' numbers and can be ignored too
+                let ignore_count = match self.breakpoints.get_mut(id) {
+                    Some(bp) => Some(&mut bp.ignore_count),
+                    None => self.breakpoints.watchpoint_mut(id).map(|wp| &mut wp.ignore_count),
+                };
+                match ignore_count {
+                    Some(ignore_count) => {
+                        *ignore_count = count;
+                        match count {
+                            0 => println!("Will stop next time breakpoint {} is reached.", id),
+                            1 => println!("Will ignore next crossing of breakpoint {}.", id),
+                            _ => println!("Will ignore next {} crossings of breakpoint {}.", count, id),
                         }
+                    }
+                    None => println!("No breakpoint number {}.", id),
+                }
+            }
+            DebuggerCommand::Delete(ids) => {
+                for id in self.ids_or_all(ids) {
+                    if let Some(bp) = self.breakpoints.remove(id) {
+                        self.sync_breakpoint(bp.addr);
+                    } else if self.breakpoints.remove_watchpoint(id).is_some() {
+                        self.sync_watchpoints();
                     } else {
-                        println!("No running inferior");
+                        println!("No breakpoint number {}.", id);
                     }
                 }
-                DebuggerCommand::Backtrace => { 
+            }
+            DebuggerCommand::Disable(ids) => {
+                for id in self.ids_or_all(ids) {
+                    self.set_breakpoint_enabled(id, false);
+                }
+            }
+            DebuggerCommand::Enable(ids) => {
+                for id in self.ids_or_all(ids) {
+                    self.set_breakpoint_enabled(id, true);
+                }
+            }
+            DebuggerCommand::InfoBreakpoints => {
+                self.breakpoints.print();
+            }
+            DebuggerCommand::Set(name, value) => self.set(&name, &value),
+            DebuggerCommand::Handle(args) => match self.signals.handle(&args) {
+                Ok(changed) => {
+                    self.signals.print(&changed);
                     if let Some(inferior) = self.inferior.as_mut() {
-                        let _ = inferior.print_backtrace(&self.debug_data);
+                        inferior.set_signal_policy(&self.signals);
                     }
                 }
-                DebuggerCommand::Break(address) => {
-                    let addr = parse_address(&address).expect("Invalid address");
-                    if let Some(inferior) = self.inferior.as_mut() {
-                        inferior.set_breakpoint(addr).expect("Failed to set breakpoint");
-                        println!("Set breakpoint {} at {:#x}", inferior.get_breakpoints_count() - 1, addr);
-                    } else {
-                        // add to breakpoints list if the inferior is not running
-                        self.breakpoints.push(addr);
-                        println!("Set breakpoint {} at {:#x}", self.breakpoints.len() - 1, addr);
+                Err(err) => println!("{}", err),
+            },
+            DebuggerCommand::InfoSignals(name) => match name {
+                Some(name) => match signals::parse_signal(&name) {
+                    Ok(sig) => self.signals.print(&[sig]),
+                    Err(err) => println!("{}", err),
+                },
+                None => self.signals.print(&Signal::iterator().collect::<Vec<_>>()),
+            },
+            DebuggerCommand::InfoInferiors => {
+                if self.inferior.is_none() && self.held.is_empty() {
+                    println!("No inferiors.");
+                    return;
+                }
+                println!("  Pid      State");
+                if let Some(inferior) = self.inferior.as_ref() {
+                    println!("* {:<8} selected", inferior.pid());
+                }
+                for inferior in &self.held {
+                    println!("  {:<8} held stopped", inferior.pid());
+                }
+            }
+            DebuggerCommand::Inferior(pid) => {
+                let pos = match self.held.iter().position(|inferior| inferior.pid().as_raw() == pid) {
+                    Some(pos) => pos,
+                    None => {
+                        println!("No held inferior with pid {}.", pid);
+                        return;
+                    }
+                };
+                let mut inferior = self.held.remove(pos);
+                inferior.set_fork_policy(self.fork_policy);
+                inferior.set_signal_policy(&self.signals);
+                if let Some(previous) = self.inferior.replace(inferior) {
+                    self.held.push(previous);
+                }
+                println!("[Switching to inferior (process {})]", pid);
+                // The held process may be running another program than the one we switched
+                // away from, and has its own copy of the watched memory
+                if let Ok(exe) = std::fs::read_link(format!("/proc/{}/exe", pid)) {
+                    if Some(&exe) != std::fs::canonicalize(&self.target).ok().as_ref() {
+                        self.load_program(&exe.to_string_lossy());
+                    }
+                }
+                self.sync_watchpoints();
+                self.print_stopped_location();
+            }
+            DebuggerCommand::InfoSharedLibrary => self.debug_data.print_libraries(),
+            DebuggerCommand::InfoThreads => {
+                if let Some(inferior) = self.inferior.as_ref() {
+                    inferior.print_threads(&self.debug_data);
+                } else {
+                    println!("No running inferior");
+                }
+            }
+            DebuggerCommand::InfoRegisters(names, all) => match self.program() {
+                Some(program) => {
+                    if let Err(err) = program.print_registers(&self.debug_data, &names, all) {
+                        println!("Cannot read registers: {}", err);
+                    }
+                }
+                None => println!("The program has no registers now."),
+            },
+            DebuggerCommand::Thread(id) => {
+                let inferior = match self.inferior.as_mut() {
+                    Some(inferior) => inferior,
+                    None => {
+                        println!("No running inferior");
+                        return;
                     }
+                };
+                match id {
+                    Some(id) => match inferior.select_thread(id) {
+                        Some(tid) => {
+                            println!("[Switching to thread {} (LWP {})]", id, tid);
+                            self.print_stopped_location();
+                        }
+                        None => println!("Invalid thread ID: {}", id),
+                    },
+                    None => {
+                        let (id, tid) = inferior.current_thread();
+                        println!("[Current thread is {} (LWP {})]", id, tid);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Starts the program, stopped before its first instruction with the breakpoints inserted.
+    /// Returns false if it couldn't be started.
+    pub fn start(&mut self, args: &[String]) -> bool {
+        // If an inferior is already running, we need to get rid of it first.
+        self.release_inferior();
+        self.core = None;
+        self.breakpoints.reset_hit_counts();
+        self.last_status = None;
+        match Inferior::new(&self.target, args) {
+            Some(mut inferior) => {
+                inferior.set_fork_policy(self.fork_policy);
+                inferior.set_signal_policy(&self.signals);
+                self.inferior = Some(inferior);
+                self.load_libraries();
+                self.sync_watchpoints();
+                true
+            }
+            None => {
+                println!("Error starting subprocess");
+                false
+            }
+        }
+    }
+
+    /// How the inferior stopped or ended after it was last resumed, or None if it wasn't resumed
+    /// or couldn't be.
+    pub fn last_status(&self) -> Option<Status> {
+        self.last_status
+    }
+
+    /// The process being debugged, if any.
+    pub fn inferior_mut(&mut self) -> Option<&mut Inferior> {
+        self.inferior.as_mut()
+    }
+
+    /// Returns the id of a breakpoint at addr, if there is one.
+    pub fn breakpoint_at(&self, addr: usize) -> Option<usize> {
+        self.breakpoints.iter().find(|bp| !bp.pending && bp.addr == addr).map(|bp| bp.id)
+    }
+
+    /// Starts debugging a process that is already running. It is left stopped at whatever it was
+    /// doing.
+    pub fn attach(&mut self, pid: Pid) {
+        if let Ok(exe) = std::fs::read_link(format!("/proc/{}/exe", pid)) {
+            let target = std::fs::canonicalize(&self.target).unwrap_or_default();
+            if exe != target {
+                println!(
+                    "Warning: process {} is running {}, not {}; symbols may not match",
+                    pid,
+                    exe.display(),
+                    self.target
+                );
+            }
+        }
+        self.breakpoints.reset_hit_counts();
+        match Inferior::attach(pid) {
+            Ok(mut inferior) => {
+                println!("Attaching to process {}", pid);
+                self.core = None;
+                inferior.set_fork_policy(self.fork_policy);
+                inferior.set_signal_policy(&self.signals);
+                self.inferior = Some(inferior);
+                self.load_libraries();
+                self.sync_watchpoints();
+                self.print_stopped_location();
+            }
+            Err(err) => println!("Could not attach to process {}: {}", pid, err),
+        }
+    }
+
+    /// Restores the original code of the inferior and lets it run on without us.
+    fn detach(&mut self) {
+        if let Some(mut inferior) = self.inferior.take() {
+            println!("Detaching from process {}", inferior.pid());
+            if let Err(err) = inferior.detach() {
+                println!("Error detaching from process {}: {}", inferior.pid(), err);
+            }
+            self.breakpoints.remove_local_watchpoints();
+        }
+    }
+
+    /// Gets rid of the current inferior and the held ones, if any. Processes we started are
+    /// killed, while processes we attached to are detached from so that they keep running.
+    fn release_inferior(&mut self) {
+        for mut inferior in std::mem::take(&mut self.held) {
+            if inferior.is_attached() {
+                println!("Detaching from process {}", inferior.pid());
+                let _ = inferior.detach();
+            } else {
+                println!("Killing held inferior (pid {})", inferior.pid());
+                let _ = inferior.kill();
+            }
+        }
+        match self.inferior.as_mut() {
+            Some(inferior) if inferior.is_attached() => self.detach(),
+            Some(inferior) => {
+                println!("Killing running inferior (pid {})", inferior.pid());
+                let _ = inferior.kill();
+                self.inferior = None;
+                self.breakpoints.remove_local_watchpoints();
+            }
+            None => {}
+        }
+    }
+
+    /// The program that commands like `bt`, `print` and `x` look at: the running inferior, or else
+    /// the core dump.
+    fn program(&self) -> Option<&dyn Target> {
+        match (&self.inferior, &self.core) {
+            (Some(inferior), _) => Some(inferior),
+            (None, Some(core)) => Some(core),
+            (None, None) => None,
+        }
+    }
+
+    /// Loads a core dump and shows how the process died, like a crash report for a live process.
+    pub fn load_core(&mut self, path: &str) {
+        let core = match CoreFile::open(path) {
+            Ok(core) => core,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        if let Some(executable) = core.executable() {
+            let target = std::fs::canonicalize(&self.target).unwrap_or_default();
+            if std::fs::canonicalize(executable).ok() != Some(target) {
+                println!("warning: core file may not match specified executable file.");
+            }
+        }
+        if !core.command().is_empty() {
+            println!("Core was generated by `{}'.", core.command());
+        }
+        let signal = core.signal();
+        if let Some(signal) = signal {
+            println!("Program terminated with signal {}, {}.", signal, signals::describe(signal));
+        }
+        self.core = Some(core);
+        self.load_libraries();
+        if let Some(signal) = signal.filter(|signal| crash::FAULT_SIGNALS.contains(signal)) {
+            self.print_crash_report(signal);
+        }
+        self.print_stopped_location();
+    }
+
+    /// Changes a debugger setting with `set NAME VALUE`.
+    fn set(&mut self, name: &str, value: &str) {
+        if let Some(register) = name.strip_prefix('$') {
+            self.set_register(register, value);
+            return;
+        }
+        if name == "substitute-path" {
+            match value.split_whitespace().collect::<Vec<_>>()[..] {
+                [from, to] => self.source.add_substitution(from, to),
+                _ => println!("Usage: set substitute-path FROM TO"),
+            }
+            return;
+        }
+        if name == "breakpoint" {
+            self.pending_breakpoints = match value {
+                "pending on" => Some(true),
+                "pending off" => Some(false),
+                "pending auto" => None,
+                _ => {
+                    println!("Usage: set breakpoint pending on|off|auto");
+                    return;
                 }
+            };
+            return;
+        }
+        match (name, value) {
+            ("follow-fork-mode", "parent") => self.fork_policy.follow_child = false,
+            ("follow-fork-mode", "child") => self.fork_policy.follow_child = true,
+            ("detach-on-fork", "on") => self.fork_policy.detach_on_fork = true,
+            ("detach-on-fork", "off") => self.fork_policy.detach_on_fork = false,
+            ("follow-fork-mode", _) => {
+                println!("Undefined item: \"{}\". Use \"parent\" or \"child\".", value);
+                return;
+            }
+            ("detach-on-fork", _) => {
+                println!("\"on\" or \"off\" expected.");
+                return;
+            }
+            _ => {
+                println!("No setting named \"{}\".", name);
+                return;
             }
         }
+        if let Some(inferior) = self.inferior.as_mut() {
+            inferior.set_fork_policy(self.fork_policy);
+        }
     }
 
+    /// Handles `signal SIG`: resumes the inferior, delivering SIG to it instead of the signal it
+    /// stopped with. `signal 0` resumes it without a signal.
+    fn continue_with_signal(&mut self, name: &str) {
+        let sig = match name {
+            "0" => None,
+            _ => match signals::parse_signal(name) {
+                Ok(sig) => Some(sig),
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+            },
+        };
+        let inferior = match self.inferior.as_mut() {
+            Some(inferior) => inferior,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        match sig {
+            Some(sig) => println!("Continuing with signal {}.", sig),
+            None => println!("Continuing with no signal."),
+        }
+        inferior.set_signal(sig);
+        let result = inferior.cont(&mut self.breakpoints, &self.debug_data);
+        self.report_status(result, false);
+    }
+
+    /// Handles `x/FMT ADDRESS`. Without an address, continues after the memory shown last.
+    fn examine(&mut self, format: Option<String>, address: Option<String>) {
+        let format = match format {
+            Some(spec) => match Format::parse(&spec, &self.examine_format) {
+                Ok(format) => format,
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+            },
+            None => Format {
+                count: 1,
+                ..self.examine_format
+            },
+        };
+        let program = match self.program() {
+            Some(program) => program,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        let addr = match address {
+            Some(text) => {
+                match Expr::parse(&text).and_then(|expr| program.evaluate(&expr, &self.debug_data)) {
+                    Ok(addr) => addr as usize,
+                    Err(err) => {
+                        println!("{}", err);
+                        return;
+                    }
+                }
+            }
+            None => match self.next_examine {
+                Some(addr) => addr,
+                None => {
+                    println!("Argument required (starting display address).");
+                    return;
+                }
+            },
+        };
+        let result = examine::examine(program, &self.debug_data, &self.breakpoints, &format, addr);
+        self.examine_format = format;
+        match result {
+            Ok(next) => self.next_examine = Some(next),
+            Err(err) => {
+                println!("{}", err);
+                self.next_examine = None;
+            }
+        }
+    }
+
+    /// Prints where the program stopped along with the source lines around it.
+    fn print_stopped_location(&mut self) {
+        let program = match self.program() {
+            Some(program) => program,
+            None => return,
+        };
+        let line = program.print_stopped_location(&self.debug_data);
+        // A new stop means `list` starts again from the current line
+        self.listed = None;
+        if let Ok(Some(line)) = line {
+            let first = line.number.saturating_sub(STOP_CONTEXT_LINES).max(1);
+            let last = line.number + STOP_CONTEXT_LINES;
+            if let Err(err) = self.source.print(&line.file, first, last, Some(line.number)) {
+                println!("{}", err);
+            }
+        }
+    }
+
+    /// Handles `list`, `list LINE`, `list FUNCTION` and `list FILE:LINE`. Without a location,
+    /// continues after the lines listed last, or lists around the current line.
+    fn list(&mut self, location: Option<String>) {
+        let current = self
+            .program()
+            .and_then(|program| program.selected_line(&self.debug_data));
+        // The file and line to center the listing on
+        let (file, center) = match (&location, &self.listed) {
+            (None, Some((file, last))) => {
+                let (file, first) = (file.clone(), last + 1);
+                self.print_listing(file, first, current);
+                return;
+            }
+            (None, None) => match &current {
+                Some(line) => (line.file.clone(), line.number),
+                None => match self.debug_data.get_function_source(None, "main") {
+                    Some(source) => source,
+                    None => {
+                        println!("No symbol table is loaded.");
+                        return;
+                    }
+                },
+            },
+            (Some(location), _) => match self.resolve_source_location(location, current.as_ref()) {
+                Ok(source) => source,
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+            },
+        };
+        let first = center.saturating_sub(LIST_LINES / 2).max(1);
+        self.print_listing(file, first, current);
+    }
+
+    /// Lists LIST_LINES lines of a file from `first`, and remembers where to continue.
+    fn print_listing(&mut self, file: String, first: usize, current: Option<Line>) {
+        let current = current.filter(|line| line.file == file).map(|line| line.number);
+        match self.source.print(&file, first, first + LIST_LINES - 1, current) {
+            Ok(last) => self.listed = Some((file, last)),
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    /// Finds the file and line that `list LOCATION` refers to. A bare line number is in the file
+    /// listed last, or else the current one.
+    fn resolve_source_location(
+        &self,
+        location: &str,
+        current: Option<&Line>,
+    ) -> Result<(String, usize), String> {
+        let (file, line_or_func) = split_location(location);
+        if let Ok(line_number) = line_or_func.parse::<usize>() {
+            let path = match file {
+                Some(file) => self
+                    .debug_data
+                    .get_source_path(Some(file))
+                    .ok_or(format!("No source file named {}.", file))?,
+                None => match (&self.listed, current) {
+                    (Some((file, _)), _) => file.clone(),
+                    (None, Some(line)) => line.file.clone(),
+                    (None, None) => self
+                        .debug_data
+                        .get_source_path(None)
+                        .ok_or("No symbol table is loaded.")?,
+                },
+            };
+            return Ok((path, line_number));
+        }
+        self.debug_data
+            .get_function_source(file, line_or_func)
+            .ok_or(format!("Function \"{}\" not defined.", line_or_func))
+    }
+
+    /// Handles `disassemble [FUNCTION|ADDRESS]`, showing the function that contains the selected
+    /// frame's pc if no location is given.
+    fn disassemble(&self, location: Option<String>) {
+        let program = match self.program() {
+            Some(program) => program,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        let addr = match &location {
+            None => match program.registers() {
+                Ok(regs) => regs.rip as usize,
+                Err(err) => {
+                    println!("Cannot read registers: {}", err);
+                    return;
+                }
+            },
+            Some(text) => match self.debug_data.get_addr_for_function(None, text) {
+                Some(addr) => addr,
+                None => match Expr::parse(text)
+                    .and_then(|expr| program.evaluate(&expr, &self.debug_data))
+                {
+                    Ok(addr) => addr as usize,
+                    Err(err) => {
+                        println!("{}", err);
+                        return;
+                    }
+                },
+            },
+        };
+        // Code without debug info is disassembled from its symbol table entry
+        let (name, start, len) = match self.debug_data.get_function_containing(addr) {
+            Some(func) => (func.name.clone(), func.address, func.text_length),
+            None => match self.debug_data.get_symbol_containing(addr) {
+                Some(symbol) => (symbol.name.clone(), symbol.address, symbol.size),
+                None if location.is_none() => {
+                    println!("No function contains program counter for selected frame.");
+                    return;
+                }
+                None => {
+                    println!("No function contains specified address.");
+                    return;
+                }
+            },
+        };
+        if let Err(err) = disassemble::disassemble_function(
+            program,
+            &self.debug_data,
+            &self.breakpoints,
+            &name,
+            start..start + len,
+        ) {
+            println!("{}", err);
+        }
+    }
+
+    /// Handles `set $REGISTER = EXPRESSION`.
+    fn set_register(&mut self, register: &str, value: &str) {
+        let expr = match Expr::parse(value) {
+            Ok(expr) => expr,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+        let inferior = match self.inferior.as_mut() {
+            Some(inferior) => inferior,
+            None => {
+                println!("The program has no registers now.");
+                return;
+            }
+        };
+        let result = inferior
+            .evaluate(&expr, &self.debug_data)
+            .and_then(|value| inferior.set_register(register, value as u64));
+        if let Err(err) = result {
+            println!("{}", err);
+        }
+    }
+
+    /// Evaluates the address in `break *ADDRESS` or `watch *ADDRESS`. It is hex, with or without
+    /// 0x, or else an expression such as `$rsp+8`.
+    fn evaluate_address(&self, text: &str) -> Result<usize, String> {
+        if let Some(addr) = parse_address(text) {
+            return Ok(addr);
+        }
+        let expr = Expr::parse(text)?;
+        match self.program() {
+            Some(program) => Ok(program.evaluate(&expr, &self.debug_data)? as usize),
+            None => Err(format!("Invalid address \"{}\"", text)),
+        }
+    }
+
+    /// Switches to the program the inferior started running with exec: loads its debug info,
+    /// looks up the breakpoints and watchpoints again and inserts them into the new image.
+    /// Breakpoints whose location isn't in the new program become pending, and such watchpoints
+    /// are disabled.
+    fn load_program(&mut self, path: &str) {
+        match DwarfData::from_file(path) {
+            Ok(debug_data) => self.debug_data = debug_data,
+            Err(_) => println!("Could not load debugging symbols from {}", path),
+        }
+        self.target = path.to_string();
+
+        self.breakpoints.remove_local_watchpoints();
+        let resolved: Vec<usize> = self.breakpoints.iter().filter(|bp| !bp.pending).map(|bp| bp.id).collect();
+        self.load_libraries();
+        for bp in self.breakpoints.iter().filter(|bp| bp.pending && resolved.contains(&bp.id)) {
+            println!("Breakpoint {} ({}) is pending until its location is loaded.", bp.id, bp.spec);
+        }
+        self.resolve_watchpoints();
+        self.sync_watchpoints();
+    }
+
+    /// Finds out where the executable and its shared libraries are loaded, loads the symbols of
+    /// the libraries and looks up the breakpoints again, since their code may have moved or just
+    /// been loaded. Watchpoints are looked up again if the executable moved.
+    fn load_libraries(&mut self) {
+        let program = match self.program() {
+            Some(program) => program,
+            None => return,
+        };
+        let regions = program.memory_map();
+        let linker = libraries::find_dynamic_linker(&self.target, &regions);
+        let loaded = linker
+            .as_ref()
+            .and_then(|linker| libraries::loaded_libraries(program, linker.r_debug));
+
+        let moved = match libraries::load_bias(&regions, &self.target) {
+            Some(bias) => self.debug_data.relocate_executable(bias),
+            None => false,
+        };
+        if let Some(loaded) = loaded {
+            self.debug_data.update_libraries(&loaded);
+        }
+        if let Some(inferior) = self.inferior.as_mut() {
+            inferior.forget_unwind_info();
+        }
+        if let (Some(inferior), Some(linker)) = (self.inferior.as_mut(), linker) {
+            if let Err(err) = inferior.set_solib_breakpoint(linker.breakpoint) {
+                println!("Cannot insert breakpoint for shared library events: {}", err);
+            }
+        }
+        self.resolve_breakpoints();
+        if moved {
+            self.resolve_watchpoints();
+        }
+    }
+
+    /// Looks up the location of every breakpoint again, and updates the inferior to match.
+    /// Breakpoints whose location isn't in the program become pending, and pending breakpoints
+    /// whose location has been loaded are set. Breakpoints on raw addresses stay where they are.
+    fn resolve_breakpoints(&mut self) {
+        let mut addrs: Vec<usize> = self.breakpoints.iter().filter(|bp| !bp.pending).map(|bp| bp.addr).collect();
+        let specs: Vec<(usize, String)> = self
+            .breakpoints
+            .iter()
+            .filter(|bp| !bp.spec.starts_with('*'))
+            .map(|bp| (bp.id, bp.spec.clone()))
+            .collect();
+        for (id, spec) in specs {
+            let resolved = self.resolve_location(&spec);
+            let bp = self.breakpoints.get_mut(id).unwrap();
+            match resolved {
+                Ok(addr) => {
+                    bp.addr = addr;
+                    bp.pending = false;
+                    bp.function = self.debug_data.get_function_from_addr(addr);
+                    bp.location = self.debug_data.get_line_from_addr(addr);
+                }
+                Err(_) => bp.pending = true,
+            }
+        }
+        addrs.extend(self.breakpoints.iter().filter(|bp| !bp.pending).map(|bp| bp.addr));
+        addrs.sort_unstable();
+        addrs.dedup();
+        for addr in addrs {
+            self.sync_breakpoint(addr);
+        }
+    }
+
+    /// Looks up the memory watched by every watchpoint on a global again. Watchpoints whose
+    /// expression can't be evaluated any more are disabled.
+    fn resolve_watchpoints(&mut self) {
+        let watches: Vec<(usize, String, usize)> = self
+            .breakpoints
+            .watchpoints()
+            .filter(|wp| !wp.local)
+            .map(|wp| (wp.id, wp.expression.clone(), wp.len))
+            .collect();
+        for (id, expression, len) in watches {
+            let len = Some(len).filter(|_| expression.starts_with('*'));
+            let resolved = self.resolve_watch_target(&expression, len);
+            let wp = self.breakpoints.watchpoint_mut(id).unwrap();
+            match resolved {
+                Ok((addr, entity_type, _)) => {
+                    wp.addr = addr;
+                    wp.len = entity_type.size;
+                    wp.entity_type = entity_type;
+                }
+                Err(_) if wp.enabled => {
+                    wp.enabled = false;
+                    println!("Watchpoint {} ({}) is not in the new program; disabling it.", id, expression);
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// Selects the frame `offset` levels up (towards main) from the selected one, or down if it
+    /// is negative, stopping at the outermost or innermost frame.
+    fn move_frame(&mut self, offset: isize) {
+        let debug_data = &self.debug_data;
+        let program = match program_mut(&mut self.inferior, &mut self.core) {
+            Some(program) => program,
+            None => {
+                println!("No stack.");
+                return;
+            }
+        };
+        let frames = match program.frames(debug_data) {
+            Ok(frames) => frames,
+            Err(err) => {
+                println!("Cannot unwind the stack: {}", err);
+                return;
+            }
+        };
+        let current = program.selected_frame();
+        if offset > 0 && current + 1 >= frames.len() {
+            println!("Initial frame selected; you cannot go up.");
+            return;
+        }
+        if offset < 0 && current == 0 {
+            println!("Bottom (innermost) frame selected; you cannot go down.");
+            return;
+        }
+        let level = (current as isize + offset).clamp(0, frames.len() as isize - 1) as usize;
+        if program.select_frame(debug_data, level).unwrap_or(false) {
+            let _ = program.print_selected_frame(debug_data);
+            self.listed = None;
+        }
+    }
+
+    /// Commands that take breakpoint ids apply to every breakpoint when no ids are given.
+    fn ids_or_all(&self, ids: Vec<usize>) -> Vec<usize> {
+        if ids.is_empty() {
+            self.breakpoints.ids()
+        } else {
+            ids
+        }
+    }
+
+    fn set_breakpoint_enabled(&mut self, id: usize, enabled: bool) {
+        if let Some(bp) = self.breakpoints.get_mut(id) {
+            bp.enabled = enabled;
+            let addr = bp.addr;
+            self.sync_breakpoint(addr);
+            return;
+        }
+        let enabled_count = self.breakpoints.enabled_watchpoint_count();
+        match self.breakpoints.watchpoint_mut(id) {
+            Some(wp) if enabled && !wp.enabled && enabled_count >= MAX_WATCHPOINTS => {
+                println!(
+                    "Cannot enable watchpoint {}: at most {} watchpoints can be enabled at a time",
+                    id, MAX_WATCHPOINTS
+                );
+            }
+            Some(wp) => {
+                wp.enabled = enabled;
+                self.sync_watchpoints();
+            }
+            None => println!("No breakpoint number {}.", id),
+        }
+    }
+
+    /// Reloads the running inferior's debug registers after the watchpoints have changed.
+    fn sync_watchpoints(&mut self) {
+        if let Some(inferior) = self.inferior.as_mut() {
+            if let Err(err) = inferior.update_watchpoints(&mut self.breakpoints) {
+                println!("Failed to update watchpoints: {}", err);
+            }
+        }
+    }
+
+    /// Works out what memory a watchpoint covers. The target is either a variable or an expression
+    /// like `p->value`, whose type determines the length, or a raw address (`*0x404028`), which is
+    /// watched as a 4-byte int unless another length is given. Only addresses take a length.
+    /// Returns the address, the type used to display the value, and whether it is a local variable.
+    fn resolve_watch_target(&self, target: &str, len: Option<usize>) -> Result<(usize, Type, bool), String> {
+        let (addr, entity_type, local) = if let Some(address) = target.strip_prefix('*') {
+            let addr = self.evaluate_address(address)?;
+            (addr, Type::new("int".to_string(), len.unwrap_or(4)), false)
+        } else if len.is_some() {
+            return Err(format!(
+                "A length can only be given for an address, as in `watch *ADDR LEN'; `{}' is watched as a whole",
+                target
+            ));
+        } else if let (Some(inferior), false) = (self.inferior.as_ref(), is_identifier(target)) {
+            // An expression like p->next->value or arr[3], which is watched wherever it is now
+            let value = Expr::parse(target)
+                .and_then(|expr| inferior.evaluate_value(&expr, &self.debug_data))?;
+            let addr = value
+                .addr
+                .ok_or(format!("Cannot watch constant value `{}'.", target))?;
+            (addr, value.value_type, false)
+        } else {
+            let frame = match self.inferior.as_ref() {
+                Some(inferior) => Some(inferior.unwound_frame()?),
+                None => None,
+            };
+            let var = match (self.inferior.as_ref(), frame.as_ref()) {
+                (Some(inferior), Some(frame)) => inferior.lookup_variable(&self.debug_data, frame, target),
+                _ => self.debug_data.get_global_variable(target),
+            }
+            .ok_or(format!("No symbol \"{}\" in current context.", target))?;
+            let addr = match (&var.location, self.inferior.as_ref(), frame.as_ref()) {
+                (Location::Address(addr), _, _) => *addr,
+                (_, Some(inferior), Some(frame)) => inferior.variable_addr(&self.debug_data, frame, var)?,
+                _ => return Err("Local variables can only be watched while the program is running".to_string()),
+            };
+            let local = !matches!(var.location, Location::Address(_));
+            (addr, var.entity_type.clone(), local)
+        };
+        let len = entity_type.size;
+        if ![1, 2, 4, 8].contains(&len) || addr % len != 0 {
+            return Err(format!(
+                "Cannot watch {} bytes at {:#x}: hardware watchpoints must be 1, 2, 4 or 8 bytes long and aligned to their length",
+                len, addr
+            ));
+        }
+        Ok((addr, entity_type, local))
+    }
+
+    /// Makes the running inferior's memory at addr agree with the breakpoint table: the 0xcc byte
+    /// stays in place as long as some enabled breakpoint uses that address, and the original byte
+    /// is restored otherwise.
+    fn sync_breakpoint(&mut self, addr: usize) {
+        if let Some(inferior) = self.inferior.as_mut() {
+            let result = if self.breakpoints.is_enabled_at(addr) {
+                inferior.set_breakpoint(addr)
+            } else {
+                inferior.remove_breakpoint(addr)
+            };
+            if let Err(err) = result {
+                println!("Failed to update breakpoint at {:#x}: {}", addr, err);
+            }
+        }
+    }
+
+    /// Prints how the inferior stopped after being resumed, and forgets about it if it is gone.
+    /// Stepping commands expect to stop with SIGTRAP, so with `stepping` set that stop only prints
+    /// the new location.
+    fn report_status(&mut self, result: Result<Status, nix::Error>, stepping: bool) {
+        self.last_status = result.as_ref().ok().copied();
+        match result {
+            Ok(Status::Exited(code)) => {
+                self.take_held();
+                println!("Child exited (status {})", code);
+                self.inferior = None;
+                self.breakpoints.remove_local_watchpoints();
+                self.print_held_hint();
+            }
+            Ok(Status::Signaled(signal)) => {
+                self.take_held();
+                println!("Child signaled ({})", signal);
+                self.inferior = None;
+                self.breakpoints.remove_local_watchpoints();
+                self.print_held_hint();
+            }
+            Ok(Status::Stopped(signal, rip)) => {
+                self.take_held();
+                if let Some(path) = self.inferior.as_mut().unwrap().take_exec() {
+                    self.load_program(&path);
+                    if !stepping {
+                        // Like gdb, keep going in the new program
+                        let result = self.inferior.as_mut().unwrap().cont(&mut self.breakpoints, &self.debug_data);
+                        return self.report_status(result, false);
+                    }
+                }
+                if self.inferior.as_mut().unwrap().take_libraries_changed() {
+                    self.load_libraries();
+                    // The stop was only to pick up the libraries, so carry on with what the user
+                    // asked for
+                    if signal == Signal::SIGTRAP && self.inferior.as_ref().unwrap().solib_breakpoint() == Some(rip) {
+                        let result = self.inferior.as_mut().unwrap().cont(&mut self.breakpoints, &self.debug_data);
+                        return self.report_status(result, stepping);
+                    }
+                }
+                if let Some((id, tid)) = self.inferior.as_ref().unwrap().switched_thread() {
+                    println!("[Switching to thread {} (LWP {})]", id, tid);
+                }
+                if !stepping || signal != Signal::SIGTRAP {
+                    println!("Child stopped (signal {})", signal);
+                }
+                if crash::FAULT_SIGNALS.contains(&signal) {
+                    self.print_crash_report(signal);
+                }
+                for id in self.inferior.as_ref().unwrap().breakpoints_hit() {
+                    println!("Hit breakpoint {}", id);
+                }
+                for hit in self.inferior.as_ref().unwrap().watchpoints_hit() {
+                    self.breakpoints.print_watchpoint_hit(hit, &self.debug_data);
+                }
+                self.print_stopped_location();
+            }
+            Err(err) => {
+                println!("Error continuing subprocess: {}", err);
+            }
+        }
+    }
+
+    /// Explains why the program faulted and how it got there. The faulting source line follows
+    /// as part of the stopped location.
+    fn print_crash_report(&self, signal: Signal) {
+        let program = match self.program() {
+            Some(program) => program,
+            None => return,
+        };
+        match program.signal_info() {
+            Ok(info) => crash::print_fault(signal, &info, &program.memory_map()),
+            Err(err) => println!("Cannot get signal information: {}", err),
+        }
+        let _ = program.print_backtrace(&self.debug_data, Some(CRASH_BACKTRACE_FRAMES));
+    }
+
+    /// Takes over the processes the inferior has forked off and is holding stopped.
+    fn take_held(&mut self) {
+        if let Some(inferior) = self.inferior.as_mut() {
+            self.held.extend(inferior.take_held());
+        }
+    }
+
+    fn print_held_hint(&self) {
+        if let Some(inferior) = self.held.first() {
+            println!(
+                "{} process(es) held stopped after fork; switch to one with \"inferior {}\"",
+                self.held.len(),
+                inferior.pid()
+            );
+        }
+    }
+
+    /// Translates a breakpoint location into an address. A location is either a raw address
+    /// (`*0x401136`), a source line (`count.c:7`, or just `7` for the first compilation unit), or
+    /// a function name (`main`).
+    fn resolve_location(&self, location: &str) -> Result<usize, String> {
+        if let Some(address) = location.strip_prefix('*') {
+            return self.evaluate_address(address);
+        }
+        let (file, line_or_func) = split_location(location);
+        if let Some(file) = file.filter(|file| self.debug_data.get_source_path(Some(file)).is_none()) {
+            return Err(format!("No source file named {}.", file));
+        }
+        if let Ok(line_number) = line_or_func.parse::<usize>() {
+            return self
+                .debug_data
+                .get_addr_for_line(file, line_number)
+                .ok_or(match file {
+                    Some(file) => format!("No line {} in file \"{}\"", line_number, file),
+                    None => format!("No line {} in the current file", line_number),
+                });
+        }
+        self.debug_data
+            .get_body_addr_for_function(file, line_or_func)
+            .ok_or(match file {
+                Some(file) => format!("Function \"{}\" not defined in \"{}\"", line_or_func, file),
+                None => format!("Function \"{}\" not defined", line_or_func),
+            })
+    }
+
+    /// Returns true if a breakpoint location that can't be found now may still be in a shared
+    /// library that isn't loaded yet: a function or file that isn't in any loaded object, while
+    /// the program hasn't started or is linked against shared libraries and so can dlopen more.
+    fn may_load_later(&self, location: &str) -> bool {
+        if location.starts_with('*') {
+            return false;
+        }
+        let (file, line_or_func) = split_location(location);
+        let unknown = match file {
+            Some(file) => self.debug_data.get_source_path(Some(file)).is_none(),
+            // A bare line number is in the first source file, which is always loaded
+            None => line_or_func.parse::<usize>().is_err(),
+        };
+        unknown && (self.inferior.is_none() || self.debug_data.has_libraries())
+    }
+
+    /// Asks a question that defaults to no, like gdb's nquery. Questions asked by scripted
+    /// commands aren't put to the user.
+    fn query(&mut self, question: &str) -> bool {
+        if self.scripted {
+            println!("{} (y or [n]) [answered N; input not from terminal]", question);
+            return false;
+        }
+        match self.readline.readline(&format!("{} (y or [n]) ", question)) {
+            Ok(answer) => matches!(answer.trim(), "y" | "Y" | "yes"),
+            Err(_) => false,
+        }
+    }
+
+    /// Handles `source FILE`: runs the commands in a file, one per line, before any that were
+    /// queued already. Blank lines and lines starting with `#` are skipped.
+    fn source_script(&mut self, path: &str) {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) => {
+                // Without the "(os error 2)" that io::Error adds
+                let reason = match err.raw_os_error() {
+                    Some(errno) => nix::Error::from_i32(errno).desc().to_string(),
+                    None => err.to_string(),
+                };
+                println!("{}: {}.", path, reason);
+                return;
+            }
+        };
+        for line in text.lines().rev() {
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                self.script.push_front(line.to_string());
+            }
+        }
+    }
 
     /// This function prompts the user to enter a command, and continues re-prompting until the user
     /// enters a valid command. It uses DebuggerCommand::from_tokens to do the command parsing.
-    ///
-    /// You don't need to read, understand, or modify this function.
+    /// Queued commands are run first, echoed after the prompt as if they had been typed.
     fn get_next_command(&mut self) -> DebuggerCommand {
         loop {
+            self.scripted = !self.script.is_empty();
+            if let Some(line) = self.script.pop_front() {
+                println!("(deet) {}", line);
+                let tokens: Vec<&str> = line.split_whitespace().collect();
+                match DebuggerCommand::from_tokens(&tokens) {
+                    Some(cmd) => return cmd,
+                    None => {
+                        println!("Unrecognized command.");
+                        continue;
+                    }
+                }
+            }
+            if self.batch {
+                return DebuggerCommand::Quit;
+            }
             // Print prompt and get next line of user input
             match self.readline.readline("(deet) ") {
                 Err(ReadlineError::Interrupted) => {
@@ -181,7 +1421,7 @@ impl Debugger {
                     panic!("Unexpected I/O error: {:?}", err);
                 }
                 Ok(line) => {
-                    if line.trim().len() == 0 {
+                    if line.trim().is_empty() {
                         continue;
                     }
                     let _ = self.readline.add_history_entry(line.as_str());
@@ -202,3 +1442,18 @@ impl Debugger {
         }
     }
 }
+
+/// Splits a location like `count.c:7` or `count.c:main` into the file and the line or function.
+/// The `::` in a Rust path like `shapes::area` doesn't separate a file.
+fn split_location(location: &str) -> (Option<&str>, &str) {
+    let bytes = location.as_bytes();
+    let is_separator = |index: usize| {
+        bytes[index] == b':'
+            && bytes.get(index + 1) != Some(&b':')
+            && (index == 0 || bytes[index - 1] != b':')
+    };
+    match (0..bytes.len()).rev().find(|index| is_separator(*index)) {
+        Some(index) => (Some(&location[..index]), &location[index + 1..]),
+        None => (None, location),
+    }
+}
@@ -1,15 +1,46 @@
-use substring::Substring;
+use crate::breakpoint::WatchKind;
 
 pub enum DebuggerCommand {
     Quit,
     Run(Vec<String>),
+    Attach(i32),
+    Detach,
     Continue,
+    Signal(String),
+    StepInstruction,
+    Step,
+    Next,
+    Finish,
     Backtrace,
-    Break(String),
+    Frame(Option<usize>),
+    Up(usize),
+    Down(usize),
+    Break(String, Option<String>),
+    Ignore(usize, usize),
+    Watch(WatchKind, String, Option<usize>),
+    Delete(Vec<usize>),
+    Disable(Vec<usize>),
+    Enable(Vec<usize>),
+    Print(String),
+    Examine(Option<String>, Option<String>),
+    Disassemble(Option<String>),
+    List(Option<String>),
+    InfoLocals,
+    InfoBreakpoints,
+    InfoThreads,
+    InfoRegisters(Vec<String>, bool),
+    Thread(Option<usize>),
+    Set(String, String),
+    Handle(Vec<String>),
+    InfoSignals(Option<String>),
+    InfoInferiors,
+    InfoSharedLibrary,
+    Inferior(i32),
+    Source(String),
 }
 
 impl DebuggerCommand {
-    pub fn from_tokens(tokens: &Vec<&str>) -> Option<DebuggerCommand> {
+    pub fn from_tokens(tokens: &[&str]) -> Option<DebuggerCommand> {
         match tokens[0] {
             "q" | "quit" => Some(DebuggerCommand::Quit),
             "r" | "run" => {
@@ -18,15 +49,154 @@ impl DebuggerCommand {
                     args.iter().map(|s| s.to_string()).collect(),
                 ))
             },
-            "c" | "cont" => Some(DebuggerCommand::Continue),
+            "attach" => Some(DebuggerCommand::Attach(tokens.get(1)?.parse().ok()?)),
+            "detach" => Some(DebuggerCommand::Detach),
+            "c" | "cont" | "continue" => Some(DebuggerCommand::Continue),
+            "signal" => Some(DebuggerCommand::Signal(tokens.get(1)?.to_string())),
+            // handle SIGNAL... [stop|nostop] [print|noprint] [pass|nopass]
+            "handle" => {
+                if tokens.len() < 2 {
+                    return None;
+                }
+                Some(DebuggerCommand::Handle(tokens[1..].iter().map(|s| s.to_string()).collect()))
+            },
+            "si" | "stepi" => Some(DebuggerCommand::StepInstruction),
+            "s" | "step" => Some(DebuggerCommand::Step),
+            "n" | "next" => Some(DebuggerCommand::Next),
+            "fin" | "finish" => Some(DebuggerCommand::Finish),
             "bt" | "back" | "backtrace" => Some(DebuggerCommand::Backtrace),
-            "b" | "break" => { 
-                let str = tokens[1].to_string();
-                assert!(str.substring(0, 1) == "*");
-                Some(DebuggerCommand::Break(str.substring(1, str.len()).to_string()))
+            // Without a level, shows the selected frame
+            "f" | "frame" => match tokens.get(1) {
+                Some(level) => Some(DebuggerCommand::Frame(Some(level.parse().ok()?))),
+                None => Some(DebuggerCommand::Frame(None)),
+            },
+            // Optionally followed by the number of frames to move
+            "up" | "down" => {
+                let count = match tokens.get(1) {
+                    Some(count) => count.parse().ok()?,
+                    None => 1,
+                };
+                match tokens[0] {
+                    "up" => Some(DebuggerCommand::Up(count)),
+                    _ => Some(DebuggerCommand::Down(count)),
+                }
+            },
+            "b" | "break" => {
+                // Accepts *0xADDR, FILE:LINE, LINE or FUNCTION, optionally followed by
+                // "if CONDITION"
+                let location = tokens.get(1)?;
+                let condition = match tokens.get(2) {
+                    Some(&"if") if tokens.len() > 3 => Some(tokens[3..].join(" ")),
+                    Some(_) => return None,
+                    None => None,
+                };
+                Some(DebuggerCommand::Break(location.to_string(), condition))
+            },
+            // Accepts a variable name, an expression without spaces like p->value, or *0xADDR,
+            // optionally followed by the number of bytes to watch
+            "watch" | "rwatch" | "awatch" => {
+                let kind = match tokens[0] {
+                    "watch" => WatchKind::Write,
+                    "rwatch" => WatchKind::Read,
+                    _ => WatchKind::Access,
+                };
+                let target = tokens.get(1)?;
+                let len = match tokens.get(2) {
+                    Some(len) => Some(len.parse().ok()?),
+                    None => None,
+                };
+                Some(DebuggerCommand::Watch(kind, target.to_string(), len))
+            },
+            "ignore" => {
+                let id = tokens.get(1)?.parse().ok()?;
+                let count = tokens.get(2)?.parse().ok()?;
+                Some(DebuggerCommand::Ignore(id, count))
+            },
+            // Breakpoint ids; no ids means all breakpoints
+            "d" | "delete" => Some(DebuggerCommand::Delete(parse_ids(&tokens[1..])?)),
+            "disable" => Some(DebuggerCommand::Disable(parse_ids(&tokens[1..])?)),
+            "enable" => Some(DebuggerCommand::Enable(parse_ids(&tokens[1..])?)),
+            "p" | "print" => {
+                if tokens.len() < 2 {
+                    return None;
+                }
+                Some(DebuggerCommand::Print(tokens[1..].join(" ")))
+            },
+            // x/FMT ADDRESS; both parts are optional
+            cmd if cmd == "x" || cmd.starts_with("x/") => {
+                let format = cmd.strip_prefix("x/").map(|format| format.to_string());
+                let address = match tokens.len() {
+                    1 => None,
+                    _ => Some(tokens[1..].join(" ")),
+                };
+                Some(DebuggerCommand::Examine(format, address))
+            },
+            // LINE, FUNCTION or FILE:LINE; without one, continues the previous listing
+            "l" | "list" => match tokens.len() {
+                1 => Some(DebuggerCommand::List(None)),
+                _ => Some(DebuggerCommand::List(Some(tokens[1..].join(" ")))),
+            },
+            // A function name or an address inside the function; defaults to the selected frame's
+            // function
+            "disas" | "disassemble" => match tokens.len() {
+                1 => Some(DebuggerCommand::Disassemble(None)),
+                _ => Some(DebuggerCommand::Disassemble(Some(tokens[1..].join(" ")))),
+            },
+            "inferior" => Some(DebuggerCommand::Inferior(tokens.get(1)?.parse().ok()?)),
+            "source" => {
+                if tokens.len() < 2 {
+                    return None;
+                }
+                Some(DebuggerCommand::Source(tokens[1..].join(" ")))
+            },
+            // set NAME VALUE, or set $REGISTER = VALUE
+            "set" => {
+                let name = tokens.get(1)?;
+                if name.starts_with('$') {
+                    let assignment = tokens[1..].join(" ");
+                    let (register, value) = assignment.split_once('=')?;
+                    return Some(DebuggerCommand::Set(
+                        register.trim().to_string(),
+                        value.trim().to_string(),
+                    ));
+                }
+                if tokens.len() < 3 {
+                    return None;
+                }
+                Some(DebuggerCommand::Set(name.to_string(), tokens[2..].join(" ")))
+            },
+            // Without an id, shows the selected thread
+            "thread" => match tokens.get(1) {
+                Some(id) => Some(DebuggerCommand::Thread(Some(id.parse().ok()?))),
+                None => Some(DebuggerCommand::Thread(None)),
+            },
+            "i" | "info" => match *tokens.get(1)? {
+                "locals" => Some(DebuggerCommand::InfoLocals),
+                "b" | "break" | "breakpoints" => Some(DebuggerCommand::InfoBreakpoints),
+                "threads" => Some(DebuggerCommand::InfoThreads),
+                // Optionally followed by the names of the registers to show
+                "r" | "registers" => Some(DebuggerCommand::InfoRegisters(
+                    tokens[2..].iter().map(|s| s.to_string()).collect(),
+                    false,
+                )),
+                "all-registers" => Some(DebuggerCommand::InfoRegisters(
+                    tokens[2..].iter().map(|s| s.to_string()).collect(),
+                    true,
+                )),
+                "inferiors" => Some(DebuggerCommand::InfoInferiors),
+                "sharedlibrary" | "dll" => Some(DebuggerCommand::InfoSharedLibrary),
+                // Optionally followed by a signal name
+                "signals" | "handle" => Some(DebuggerCommand::InfoSignals(
+                    tokens.get(2).map(|s| s.to_string()),
+                )),
+                _ => None,
             },
             // Default case:
             _ => None,
         }
     }
 }
+
+fn parse_ids(tokens: &[&str]) -> Option<Vec<usize>> {
+    tokens.iter().map(|token| token.parse().ok()).collect()
+}
@@ -0,0 +1,125 @@
+//! Decoding of x86-64 machine code in the program's memory, for `disassemble` and `x/i`.
+
+use crate::breakpoint::BreakpointTable;
+use crate::dwarf_data::DwarfData;
+use crate::target::Target;
+use iced_x86::{Decoder, DecoderOptions, Formatter, GasFormatter, Instruction, OpKind};
+use std::ops::Range;
+
+/// Longest possible x86-64 instruction.
+pub const MAX_INSTRUCTION_LEN: usize = 15;
+
+/// One decoded instruction.
+pub struct Decoded {
+    pub addr: usize,
+    pub len: usize,
+    /// AT&T syntax, like gdb shows by default
+    pub text: String,
+}
+
+fn formatter() -> GasFormatter {
+    let mut formatter = GasFormatter::new();
+    // Show numbers the way gdb does: -0x8(%rbp) rather than -8(%rbp), and 0x401206 rather than
+    // 0x0000000000401206
+    formatter.options_mut().set_small_hex_numbers_in_decimal(false);
+    formatter.options_mut().set_branch_leading_zeros(false);
+    formatter.options_mut().set_uppercase_hex(false);
+    formatter
+}
+
+/// Decodes the instructions in bytes, which were read from addr. Bytes that aren't a valid
+/// instruction decode as "(bad)", except that if `more` is set, the code continues past the end
+/// of bytes and decoding stops at an instruction that may have been cut off. The targets of
+/// jumps and calls are labelled with their symbols.
+pub fn decode(bytes: &[u8], addr: usize, more: bool, debug_data: &DwarfData) -> Vec<Decoded> {
+    let mut formatter = formatter();
+    let mut decoder = Decoder::with_ip(64, bytes, addr as u64, DecoderOptions::NONE);
+    let mut instruction = Instruction::default();
+    let mut decoded = Vec::new();
+    while decoder.can_decode() {
+        let position = decoder.position();
+        decoder.decode_out(&mut instruction);
+        let mut text = String::new();
+        if instruction.is_invalid() {
+            if more && bytes.len() - position < MAX_INSTRUCTION_LEN {
+                // Could be a valid instruction whose last bytes we didn't read
+                break;
+            }
+            text.push_str("(bad)");
+        } else {
+            formatter.format(&instruction, &mut text);
+            if instruction.op_count() > 0 && instruction.op0_kind() == OpKind::NearBranch64 {
+                let target = instruction.near_branch_target() as usize;
+                if let Some(symbol) = debug_data.get_symbol_for_addr(target) {
+                    text.push(' ');
+                    text.push_str(&symbol);
+                }
+            }
+        }
+        decoded.push(Decoded {
+            addr: addr + position,
+            len: if instruction.is_invalid() { 1 } else { instruction.len() },
+            text,
+        });
+        if instruction.is_invalid() {
+            // Resynchronize one byte further on
+            decoder.set_position(position + 1).unwrap();
+            decoder.set_ip((addr + position + 1) as u64);
+        }
+    }
+    decoded
+}
+
+/// Prints one instruction, marking the one rip points at with "=>" and those with an enabled
+/// breakpoint with "*". `symbol` labels the address, e.g. "<main+4>".
+pub fn print_instruction(
+    instruction: &Decoded,
+    symbol: Option<String>,
+    rip: Option<usize>,
+    breakpoints: &BreakpointTable,
+) {
+    let current = if rip == Some(instruction.addr) { "=>" } else { "  " };
+    let breakpoint = if breakpoints.is_enabled_at(instruction.addr) { "*" } else { " " };
+    let label = match symbol {
+        Some(symbol) => format!("{:#x} {}", instruction.addr, symbol),
+        None => format!("{:#x}", instruction.addr),
+    };
+    println!("{}{}{}:\t{}", current, breakpoint, label, instruction.text);
+}
+
+/// Prints the instructions of the function occupying code, with a header for each source line
+/// they come from.
+pub fn disassemble_function(
+    target: &dyn Target,
+    debug_data: &DwarfData,
+    breakpoints: &BreakpointTable,
+    name: &str,
+    code: Range<usize>,
+) -> Result<(), String> {
+    let bytes = target
+        .read_code(code.start, code.len())
+        .map_err(|err| format!("Cannot read memory: {}", err))?;
+    let rip = target.registers().ok().map(|regs| regs.rip as usize);
+    println!("Dump of assembler code for function {}:", name);
+    let mut last_line = None;
+    let instructions = decode(&bytes, code.start, false, debug_data);
+    for instruction in &instructions {
+        let line = debug_data.get_line_from_addr(instruction.addr);
+        if let Some(line) = &line {
+            if last_line.as_ref() != Some(&(line.file.clone(), line.number)) {
+                println!("{}:", line);
+                last_line = Some((line.file.clone(), line.number));
+            }
+        }
+        let symbol = format!("<+{}>", instruction.addr - code.start);
+        print_instruction(instruction, Some(symbol), rip, breakpoints);
+    }
+    let end = instructions
+        .last()
+        .map_or(code.start, |instruction| instruction.addr + instruction.len);
+    if end < code.end {
+        println!("Cannot access memory at address {:#x}", end);
+    }
+    println!("End of assembler dump.");
+    Ok(())
+}
@@ -1,61 +1,324 @@
 use crate::gimli_wrapper;
+use crate::registers;
 use addr2line::Context;
-use object::Object;
+use object::{Object as _, ObjectSection, ObjectSegment, ObjectSymbol, SymbolKind};
 use std::convert::TryInto;
+use std::collections::HashMap;
+use std::ops::Range;
 use std::{fmt, fs};
 
+/// Most elements of an array that `print` shows, like gdb's default `print elements` limit.
+const MAX_ELEMENTS: usize = 200;
+
+/// Distance between the type ids of different objects. Type ids are .debug_info offsets, so
+/// adding a multiple of this for each object keeps the ids of different files apart.
+const TYPE_ID_SPACING: usize = 1 << 40;
+
 #[derive(Debug)]
 pub enum Error {
     ErrorOpeningFile,
     DwarfFormatError(gimli_wrapper::Error),
 }
 
-pub struct DwarfData {
+/// A function from an ELF symbol table, which names code that has no debug info.
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub address: usize,
+    pub size: usize,
+}
+
+/// The debug info and symbols of one ELF file in the program: the executable or a shared
+/// library.
+struct Object {
+    path: String,
+    /// How far the file is loaded from the addresses it was linked for. Every address in `files`
+    /// and `symbols` has it added already.
+    bias: usize,
+    /// Addresses the file's segments occupy
+    range: Range<usize>,
     files: Vec<File>,
-    addr2line: Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+    /// Functions from .symtab and .dynsym, sorted by address
+    symbols: Vec<Symbol>,
+    /// None if the debug info is unreadable
+    addr2line: Option<Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>>,
 }
 
-impl fmt::Debug for DwarfData {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "DwarfData {{files: {:?}}}", self.files)
+/// Where gdb looks for separate debug info files by default.
+const DEBUG_DIRECTORY: &str = "/usr/lib/debug";
+
+/// Finds the separate debug info file of an ELF file, like those in distributions' -dbg
+/// packages: by build id under /usr/lib/debug/.build-id, or by the name in its .gnu_debuglink
+/// section next to it, in a .debug directory next to it or under /usr/lib/debug.
+fn find_debug_file(path: &str, object: &object::File) -> Option<String> {
+    let mut candidates = Vec::new();
+    if let Ok(Some(build_id)) = object.build_id() {
+        let hex: Vec<String> = build_id.iter().map(|byte| format!("{:02x}", byte)).collect();
+        if hex.len() > 1 {
+            candidates.push(format!("{}/.build-id/{}/{}.debug", DEBUG_DIRECTORY, hex[0], hex[1..].concat()));
+        }
+    }
+    if let Ok(Some((name, _crc))) = object.gnu_debuglink() {
+        let name = String::from_utf8_lossy(name);
+        let dir = fs::canonicalize(path)
+            .ok()
+            .and_then(|path| Some(path.parent()?.to_string_lossy().into_owned()))
+            .unwrap_or_default();
+        candidates.push(format!("{}/{}", dir, name));
+        candidates.push(format!("{}/.debug/{}", dir, name));
+        candidates.push(format!("{}{}/{}", DEBUG_DIRECTORY, dir, name));
     }
+    let original = fs::canonicalize(path).ok();
+    candidates
+        .into_iter()
+        .find(|candidate| fs::canonicalize(candidate).is_ok_and(|found| Some(found) != original))
 }
 
-impl From<gimli_wrapper::Error> for Error {
-    fn from(err: gimli_wrapper::Error) -> Self {
-        Error::DwarfFormatError(err)
+/// Reads the functions in the ELF symbol tables of a file, sorted by address.
+fn read_symbols(object: &object::File) -> Vec<Symbol> {
+    // Along with each symbol, the end of the section it is in
+    let mut symbols: Vec<(Symbol, usize)> = object
+        .symbols()
+        .chain(object.dynamic_symbols())
+        .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.is_definition())
+        .filter_map(|symbol| {
+            let section = object.section_by_index(symbol.section_index()?).ok()?;
+            let symbol = Symbol {
+                name: demangle(symbol.name().ok()?),
+                address: symbol.address().try_into().ok()?,
+                size: symbol.size().try_into().ok()?,
+            };
+            Some((symbol, (section.address() + section.size()) as usize))
+        })
+        .filter(|(symbol, _)| symbol.address != 0)
+        .collect();
+    symbols.sort_by_key(|(symbol, _)| symbol.address);
+    // .symtab and .dynsym list many of the same functions
+    symbols.dedup_by(|(a, _), (b, _)| a.address == b.address && a.name == b.name);
+    // Some assembly functions have no size; count them as extending to the next function, but
+    // not past the end of their section
+    let starts: Vec<usize> = symbols.iter().map(|(symbol, _)| symbol.address).collect();
+    for (symbol, section_end) in symbols.iter_mut().filter(|(symbol, _)| symbol.size == 0) {
+        let next = starts[starts.partition_point(|start| *start <= symbol.address)..]
+            .first()
+            .copied()
+            .unwrap_or(*section_end);
+        symbol.size = next.min(*section_end).saturating_sub(symbol.address);
     }
+    symbols.into_iter().map(|(symbol, _)| symbol).collect()
 }
 
-impl DwarfData {
-    pub fn from_file(path: &str) -> Result<DwarfData, Error> {
+/// Demangles a Rust symbol name, in either the legacy or the v0 scheme, leaving off the hash
+/// like Rust's own backtraces do. Other names are returned unchanged.
+fn demangle(name: &str) -> String {
+    match rustc_demangle::try_demangle(name) {
+        Ok(demangled) => format!("{:#}", demangled),
+        Err(_) => name.to_string(),
+    }
+}
+
+impl Object {
+    /// Loads the debug info and symbols of an ELF file, numbering its types from type_base. The
+    /// debug info comes from a separate debug file if there is one. A file whose debug info
+    /// can't be read is still loaded, with only its symbol table.
+    fn load(path: &str, type_base: usize) -> Result<(Object, HashMap<TypeId, Type>), Error> {
         let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
         let mmap = unsafe { memmap2::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? };
         let object = object::File::parse(&*mmap)
-            .or_else(|e| Err(gimli_wrapper::Error::ObjectError(e.to_string())))?;
+            .map_err(|e| gimli_wrapper::Error::ObjectError(e.to_string()))?;
         let endian = if object.is_little_endian() {
             gimli::RunTimeEndian::Little
         } else {
             gimli::RunTimeEndian::Big
         };
+
+        let debug_mmap = find_debug_file(path, &object).and_then(|debug_path| {
+            let file = fs::File::open(debug_path).ok()?;
+            unsafe { memmap2::Mmap::map(&file) }.ok()
+        });
+        let debug_object = debug_mmap.as_ref().and_then(|mmap| object::File::parse(&**mmap).ok());
+        let dwarf_object = debug_object.as_ref().unwrap_or(&object);
+
+        let (mut files, types) = match gimli_wrapper::load_file(dwarf_object, endian) {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                println!("Could not read the debug info of {}: {:?}", path, err);
+                (Vec::new(), HashMap::new())
+            }
+        };
+        for file in files.iter_mut() {
+            file.rebase_types(type_base);
+        }
+        let types = types
+            .into_iter()
+            .map(|(id, mut entity_type)| {
+                entity_type.rebase(type_base);
+                (id + type_base, entity_type)
+            })
+            .collect();
+
+        // A stripped file only has .dynsym, while its debug file keeps the full .symtab
+        let mut symbols = read_symbols(dwarf_object);
+        if symbols.is_empty() {
+            symbols = read_symbols(&object);
+        }
+
+        let start = object.segments().map(|segment| segment.address()).min().unwrap_or(0);
+        let end = object
+            .segments()
+            .map(|segment| segment.address() + segment.size())
+            .max()
+            .unwrap_or(0);
+        Ok((
+            Object {
+                path: path.to_string(),
+                bias: 0,
+                range: start as usize..end as usize,
+                files,
+                symbols,
+                addr2line: Context::new(dwarf_object).ok(),
+            },
+            types,
+        ))
+    }
+
+    /// Moves the object to where it is loaded: `bias` bytes from the addresses it was linked for.
+    fn relocate(&mut self, bias: usize) {
+        if bias == self.bias {
+            return;
+        }
+        let delta = bias.wrapping_sub(self.bias);
+        for file in self.files.iter_mut() {
+            file.relocate(delta);
+        }
+        for symbol in self.symbols.iter_mut() {
+            symbol.address = symbol.address.wrapping_add(delta);
+        }
+        self.range = self.range.start.wrapping_add(delta)..self.range.end.wrapping_add(delta);
+        self.bias = bias;
+    }
+
+    fn has_debug_info(&self) -> bool {
+        !self.files.is_empty()
+    }
+
+    /// Returns the function symbol whose code contains addr.
+    fn symbol_containing(&self, addr: usize) -> Option<&Symbol> {
+        let index = self.symbols.partition_point(|symbol| symbol.address <= addr);
+        let symbol = self.symbols[..index].last()?;
+        (addr < symbol.address + symbol.size).then_some(symbol)
+    }
+}
+
+pub struct DwarfData {
+    /// The executable first, followed by the shared libraries in the order they were loaded
+    objects: Vec<Object>,
+    types: HashMap<TypeId, Type>,
+    /// Added to the type ids of the next object that is loaded
+    next_type_base: usize,
+}
+
+impl fmt::Debug for DwarfData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let files: Vec<&File> = self.files().collect();
+        write!(f, "DwarfData {{files: {:?}}}", files)
+    }
+}
+
+impl From<gimli_wrapper::Error> for Error {
+    fn from(err: gimli_wrapper::Error) -> Self {
+        Error::DwarfFormatError(err)
+    }
+}
+
+impl DwarfData {
+    pub fn from_file(path: &str) -> Result<DwarfData, Error> {
+        let (executable, types) = Object::load(path, 0)?;
         Ok(DwarfData {
-            files: gimli_wrapper::load_file(&object, endian)?,
-            addr2line: Context::new(&object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
+            objects: vec![executable],
+            types,
+            next_type_base: TYPE_ID_SPACING,
         })
     }
 
-    #[allow(dead_code)]
+    /// Moves the executable's debug info to where it is loaded, which changes from run to run for
+    /// position-independent executables. Returns true if its addresses changed.
+    pub fn relocate_executable(&mut self, bias: usize) -> bool {
+        let executable = &mut self.objects[0];
+        if executable.bias == bias {
+            return false;
+        }
+        executable.relocate(bias);
+        true
+    }
+
+    /// Makes the shared libraries we have symbols for match the ones the program has loaded,
+    /// given as paths and load biases: new libraries are loaded, libraries that moved are
+    /// relocated and those that were unloaded are forgotten.
+    pub fn update_libraries(&mut self, libraries: &[(String, usize)]) {
+        let mut previous: Vec<Object> = self.objects.drain(1..).collect();
+        for (path, bias) in libraries {
+            let object = match previous.iter().position(|object| object.path == *path) {
+                Some(index) => previous.remove(index),
+                None => match Object::load(path, self.next_type_base) {
+                    Ok((object, types)) => {
+                        self.types.extend(types);
+                        self.next_type_base += TYPE_ID_SPACING;
+                        object
+                    }
+                    Err(_) => {
+                        println!("Could not load shared library symbols for {}.", path);
+                        continue;
+                    }
+                },
+            };
+            self.objects.push(object);
+            self.objects.last_mut().unwrap().relocate(*bias);
+        }
+    }
+
+    /// Returns true if the program has loaded any shared libraries.
+    pub fn has_libraries(&self) -> bool {
+        self.objects.len() > 1
+    }
+
+    /// Prints the shared libraries the program has loaded, like gdb's `info sharedlibrary`.
+    pub fn print_libraries(&self) {
+        if !self.has_libraries() {
+            println!("No shared libraries loaded at this time.");
+            return;
+        }
+        println!("{:<18} {:<18} {:<11} Shared Object Library", "From", "To", "Debug Info");
+        for object in &self.objects[1..] {
+            println!(
+                "{:#018x} {:#018x} {:<11} {}",
+                object.range.start,
+                object.range.end,
+                if object.has_debug_info() { "Yes" } else { "No" },
+                object.path
+            );
+        }
+    }
+
+    /// Every compilation unit with debug info, those of the executable first.
+    fn files(&self) -> impl Iterator<Item = &File> {
+        self.objects.iter().flat_map(|object| object.files.iter())
+    }
+
+    fn object_containing(&self, addr: usize) -> Option<&Object> {
+        self.objects.iter().find(|object| object.range.contains(&addr))
+    }
+
     fn get_target_file(&self, file: &str) -> Option<&File> {
-        self.files.iter().find(|f| {
+        self.files().find(|f| {
             f.name == file || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
         })
     }
 
-    #[allow(dead_code)]
     pub fn get_addr_for_line(&self, file: Option<&str>, line_number: usize) -> Option<usize> {
         let target_file = match file {
             Some(filename) => self.get_target_file(filename)?,
-            None => self.files.get(0)?,
+            None => self.files().next()?,
         };
         Some(
             target_file
@@ -66,32 +329,117 @@ impl DwarfData {
         )
     }
 
-    #[allow(dead_code)]
+    /// Returns the path of a source file given by name, or of the first source file, in the same
+    /// form as the file of the Lines returned by get_line_from_addr.
+    pub fn get_source_path(&self, file: Option<&str>) -> Option<String> {
+        let target_file = match file {
+            Some(filename) => self.get_target_file(filename)?,
+            None => self.files().next()?,
+        };
+        // The file names in the DWARF info may be relative to the compilation directory, which
+        // addr2line resolves
+        Some(
+            target_file
+                .lines
+                .first()
+                .and_then(|line| self.get_line_from_addr(line.address))
+                .map_or(target_file.name.clone(), |line| line.file),
+        )
+    }
+
+    /// Returns the path of the source file a function is defined in, and the line of its
+    /// declaration.
+    pub fn get_function_source(&self, file: Option<&str>, func_name: &str) -> Option<(String, usize)> {
+        let func = self.get_function(file, func_name)?;
+        Some((self.get_line_from_addr(func.address)?.file, func.line_number))
+    }
+
+    /// Returns the address of a function, which may also be one without debug info in a shared
+    /// library.
     pub fn get_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
-        match file {
-            Some(filename) => Some(
-                self.get_target_file(filename)?
-                    .functions
-                    .iter()
-                    .find(|func| func.name == func_name)?
-                    .address,
-            ),
-            None => {
-                for file in &self.files {
-                    if let Some(func) = file.functions.iter().find(|func| func.name == func_name) {
-                        return Some(func.address);
-                    }
+        match self.get_function(file, func_name) {
+            Some(func) => Some(func.address),
+            None if file.is_none() => Some(self.get_symbol(func_name)?.address),
+            None => None,
+        }
+    }
+
+    /// Returns the address of the first line of the function body, past the prologue that sets up
+    /// the stack frame. This is where a breakpoint on the function should go so that the frame
+    /// pointer is valid once it is hit. Functions without debug info have no lines, so their
+    /// first instruction is used.
+    pub fn get_body_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
+        let func = match self.get_function(file, func_name) {
+            Some(func) => func,
+            // A demangled Rust path like `crate::module::function` names the symbol, whose debug
+            // info may still be known by address
+            None if file.is_none() => {
+                let symbol = self.get_symbol(func_name)?;
+                match self.get_function_containing(symbol.address) {
+                    Some(func) if func.address == symbol.address => func,
+                    _ => return Some(symbol.address),
                 }
-                None
             }
+            None => return None,
+        };
+        Some(self.body_addr(func))
+    }
+
+    /// Returns where the body of the function containing addr starts, past its prologue.
+    pub fn get_body_addr_containing(&self, addr: usize) -> Option<usize> {
+        Some(self.body_addr(self.get_function_containing(addr)?))
+    }
+
+    /// The body of a function starts at its second line table row, after the prologue. That row
+    /// may be on the same line as the entry, as in `int sq(int x) { return x * x; }`.
+    fn body_addr(&self, func: &Function) -> usize {
+        let end = func.address + func.text_length;
+        self.files()
+            .flat_map(|file| file.lines.iter())
+            .map(|line| line.address)
+            .filter(|addr| *addr > func.address && *addr < end)
+            .min()
+            .unwrap_or(func.address)
+    }
+
+    /// Finds a function that has code in the program. Declarations of external functions (such
+    /// as printf) show up in the DWARF info with an address of 0, so those are skipped.
+    fn get_function(&self, file: Option<&str>, func_name: &str) -> Option<&Function> {
+        let is_match = |func: &&Function| func.name == func_name && func.text_length > 0;
+        match file {
+            Some(filename) => self.get_target_file(filename)?.functions.iter().find(is_match),
+            None => self.files().find_map(|file| file.functions.iter().find(is_match)),
         }
     }
 
+    /// Finds a function in the ELF symbol tables, preferring the executable and then the
+    /// libraries in load order, like the dynamic linker does.
+    fn get_symbol(&self, func_name: &str) -> Option<&Symbol> {
+        self.objects
+            .iter()
+            .find_map(|object| object.symbols.iter().find(|symbol| symbol.name == func_name))
+    }
+
+    /// Returns the function whose code contains the given address.
+    pub fn get_function_containing(&self, addr: usize) -> Option<&Function> {
+        self.files()
+            .flat_map(|file| file.functions.iter())
+            .find(|func| func.address <= addr && addr < func.address + func.text_length)
+    }
+
+    pub fn get_global_variable(&self, name: &str) -> Option<&Variable> {
+        self.files()
+            .flat_map(|file| file.global_variables.iter())
+            .find(|var| var.name == name)
+    }
+
     #[allow(dead_code)]
     pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
-        let location = self
+        let object = self.object_containing(curr_addr)?;
+        let location = object
             .addr2line
-            .find_location(curr_addr.try_into().unwrap())
+            .as_ref()?
+            .find_location((curr_addr - object.bias).try_into().unwrap())
             .ok()??;
         Some(Line {
             file: location.file?.to_string(),
@@ -100,20 +448,72 @@ impl DwarfData {
         })
     }
 
-    #[allow(dead_code)]
+    /// Returns the name of the function containing an address, from the debug info or else the
+    /// symbol table, e.g. for code in libc.
     pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
-        let frame = self
+        let object = self.object_containing(curr_addr)?;
+        let frame = object
             .addr2line
-            .find_frames(curr_addr.try_into().unwrap())
-            .ok()?
-            .next()
-            .ok()??;
-        Some(frame.function?.raw_name().ok()?.to_string())
+            .as_ref()
+            .and_then(|context| context.find_frames((curr_addr - object.bias).try_into().unwrap()).ok())
+            .and_then(|mut frames| frames.next().ok().flatten());
+        match frame.and_then(|frame| frame.function) {
+            Some(function) => Some(demangle(&function.raw_name().ok()?)),
+            None => Some(object.symbol_containing(curr_addr)?.name.clone()),
+        }
+    }
+
+    /// Returns the function symbol whose code contains an address, for code without debug info.
+    pub fn get_symbol_containing(&self, addr: usize) -> Option<&Symbol> {
+        self.object_containing(addr)?.symbol_containing(addr)
+    }
+
+    /// Returns the name of the function containing an address and how far into it the address
+    /// is, from the debug info or else the symbol table.
+    pub fn get_function_offset(&self, addr: usize) -> Option<(String, usize)> {
+        match self.get_function_containing(addr) {
+            Some(func) => Some((func.name.clone(), addr - func.address)),
+            None => {
+                let symbol = self.get_symbol_containing(addr)?;
+                Some((symbol.name.clone(), addr - symbol.address))
+            }
+        }
+    }
+
+    /// Describes an address as an offset into the function or global variable that contains it,
+    /// e.g. "<main+4>".
+    pub fn get_symbol_for_addr(&self, addr: usize) -> Option<String> {
+        if let Some(func) = self.get_function_containing(addr) {
+            return Some(format_symbol(&func.name, addr - func.address));
+        }
+        let global = self
+            .files()
+            .flat_map(|file| file.global_variables.iter())
+            .find_map(|var| match var.location {
+                Location::Address(start) if start <= addr && addr < start + var.entity_type.size => {
+                    Some(format_symbol(&var.name, addr - start))
+                }
+                _ => None,
+            });
+        global.or_else(|| {
+            let symbol = self.get_symbol_containing(addr)?;
+            Some(format_symbol(&symbol.name, addr - symbol.address))
+        })
+    }
+
+    /// Returns true if the executable has debug info, rather than only a symbol table.
+    pub fn has_debug_info(&self) -> bool {
+        self.objects[0].has_debug_info()
+    }
+
+    /// Looks up a type that another type refers to, e.g. the type a pointer points to.
+    pub fn get_type(&self, id: TypeId) -> Option<&Type> {
+        self.types.get(&id)
     }
 
     #[allow(dead_code)]
     pub fn print(&self) {
-        for file in &self.files {
+        for file in self.files() {
             println!("------");
             println!("{}", file.name);
             println!("------");
@@ -148,32 +548,564 @@ impl DwarfData {
     }
 }
 
-#[derive(Debug, Clone, Default)]
+/// Offset of a type's entry in .debug_info, which identifies it in DwarfData's type table.
+pub type TypeId = usize;
+
+/// The language a compilation unit is written in (DW_AT_language), which decides how its types
+/// are named and how their values are printed. Languages other than Rust are treated as C.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Language {
+    #[default]
+    C,
+    Rust,
+}
+
+/// How the bits of a base type are interpreted (DW_AT_encoding).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Signed,
+    Unsigned,
+    SignedChar,
+    UnsignedChar,
+    Bool,
+    Float,
+}
+
+#[derive(Debug, Clone)]
+pub enum TypeKind {
+    Base(Encoding),
+    /// Refers to the pointed-to type by id, since types like linked list nodes point to
+    /// themselves. None for void pointers and pointers to functions.
+    Pointer(Option<TypeId>),
+    /// Element type and number of elements. Multi-dimensional arrays are arrays of arrays.
+    Array(Box<Type>, usize),
+    Struct(Vec<Member>),
+    Union(Vec<Member>),
+    /// Names of the enumerators and their values
+    Enum(Vec<(String, i64)>),
+    /// A Rust enum with data (DW_TAG_variant_part). The discriminant member says which variant a
+    /// value holds; enums with a single variant have none.
+    Variants {
+        discriminant: Option<Box<Member>>,
+        variants: Vec<Variant>,
+    },
+    Function,
+}
+
+/// A member of a struct or union. Anonymous struct and union members have an empty name.
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    /// Offset in bytes from the start of the enclosing struct
+    pub offset: usize,
+    /// For bit fields, the offset in bits from `offset` and the number of bits
+    pub bits: Option<(usize, usize)>,
+    pub member_type: Type,
+}
+
+/// One variant of a Rust enum (DW_TAG_variant).
+#[derive(Debug, Clone)]
+pub struct Variant {
+    /// The discriminant value that selects this variant. None for the variant a value holds when
+    /// the discriminant matches no other: with a niche-encoded discriminant, the one whose data
+    /// has the niche, e.g. `Some` in an `Option<&T>`, where None is a null pointer.
+    pub discr_value: Option<u64>,
+    /// Named after the variant, with a struct type holding the variant's fields
+    pub member: Member,
+}
+
+/// What formatting a value needs from the program beyond the value's own bytes: the memory its
+/// pointers point to and the types they refer to.
+pub trait ValueContext {
+    /// Reads len bytes of the program's memory.
+    fn read_memory(&self, addr: usize, len: usize) -> Option<Vec<u8>>;
+
+    /// Reads the NUL-terminated string at addr, up to a length limit.
+    fn read_string(&self, addr: usize) -> Option<String>;
+
+    fn get_type(&self, id: TypeId) -> Option<&Type>;
+}
+
+/// The debug info alone can look up types but not read memory, which is enough for values whose
+/// pointers can't be followed any more, like the old value of a watchpoint.
+impl ValueContext for DwarfData {
+    fn read_memory(&self, _addr: usize, _len: usize) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn read_string(&self, _addr: usize) -> Option<String> {
+        None
+    }
+
+    fn get_type(&self, id: TypeId) -> Option<&Type> {
+        self.types.get(&id)
+    }
+}
+
+/// A C or Rust type. Typedefs and const/volatile qualifiers are resolved to the type they stand
+/// for, but keep their own name.
+#[derive(Debug, Clone)]
 pub struct Type {
     pub name: String,
     pub size: usize,
+    pub kind: TypeKind,
+    /// Where the type is in DwarfData's type table, or None for types we made up
+    pub id: Option<TypeId>,
+    pub language: Language,
+    /// The type parameters of a generic Rust type by name, like T in `Vec<T>`
+    pub template_params: Vec<(String, TypeId)>,
 }
 
 impl Type {
+    /// Makes a signed integer type, for values that don't come from the debug info.
     pub fn new(name: String, size: usize) -> Self {
         Type {
-            name: name,
-            size: size,
+            name,
+            size,
+            kind: TypeKind::Base(Encoding::Signed),
+            id: None,
+            language: Language::C,
+            template_params: Vec::new(),
+        }
+    }
+
+    /// Adds base to the ids of this type and the types it refers to, which keeps them apart from
+    /// those of other objects in the type table.
+    fn rebase(&mut self, base: usize) {
+        if let Some(id) = self.id.as_mut() {
+            *id += base;
+        }
+        for (_, id) in self.template_params.iter_mut() {
+            *id += base;
+        }
+        match &mut self.kind {
+            TypeKind::Pointer(Some(id)) => *id += base,
+            TypeKind::Array(element, _) => element.rebase(base),
+            TypeKind::Struct(members) | TypeKind::Union(members) => {
+                for member in members {
+                    member.member_type.rebase(base);
+                }
+            }
+            TypeKind::Variants {
+                discriminant,
+                variants,
+            } => {
+                if let Some(discriminant) = discriminant {
+                    discriminant.member_type.rebase(base);
+                }
+                for variant in variants {
+                    variant.member.member_type.rebase(base);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Finds a member of a struct or union by name, looking inside anonymous members too.
+    /// Returns the member along with its offset from the start of this type.
+    pub fn member(&self, name: &str) -> Option<(usize, &Member)> {
+        let members = match &self.kind {
+            TypeKind::Struct(members) | TypeKind::Union(members) => members,
+            _ => return None,
+        };
+        members.iter().find_map(|member| {
+            if member.name == name {
+                Some((member.offset, member))
+            } else if member.name.is_empty() {
+                let (offset, inner) = member.member_type.member(name)?;
+                Some((member.offset + offset, inner))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Returns the variant of a Rust enum that a value holds, going by its discriminant.
+    pub fn variant(&self, bytes: &[u8]) -> Option<&Variant> {
+        let (discriminant, variants) = match &self.kind {
+            TypeKind::Variants {
+                discriminant,
+                variants,
+            } => (discriminant, variants),
+            _ => return None,
+        };
+        let discriminant = match discriminant {
+            Some(discriminant) => discriminant,
+            None => return variants.first(),
+        };
+        let value = raw_int_value(&discriminant.value_bytes(bytes)).0;
+        // Discriminant values may be written sign-extended, so compare only as many bytes as
+        // the discriminant has
+        let mask = match discriminant.member_type.size {
+            size if size < 8 => (1u64 << (8 * size)) - 1,
+            _ => !0,
+        };
+        variants
+            .iter()
+            .find(|variant| variant.discr_value.is_some_and(|discr| discr & mask == value))
+            .or_else(|| variants.iter().find(|variant| variant.discr_value.is_none()))
+    }
+
+    /// Interprets the bytes of a value of this type as an integer, sign-extending signed types.
+    /// Floating point values are truncated.
+    pub fn int_value(&self, bytes: &[u8]) -> i64 {
+        let (unsigned, signed) = raw_int_value(bytes);
+        match self.kind {
+            TypeKind::Base(Encoding::Float) if bytes.len() == 4 => {
+                f32::from_bits(unsigned as u32) as i64
+            }
+            TypeKind::Base(Encoding::Float) if bytes.len() == 8 => f64::from_bits(unsigned) as i64,
+            TypeKind::Base(Encoding::Unsigned)
+            | TypeKind::Base(Encoding::UnsignedChar)
+            | TypeKind::Base(Encoding::Bool)
+            | TypeKind::Pointer(_) => unsigned as i64,
+            _ => signed,
+        }
+    }
+
+    /// Formats a value of this type given its bytes in memory, the way gdb's `print` does: structs
+    /// as `{a = 1, b = 2}`, arrays as `{1, 2, 3}`, enums by name and pointers in hex. Rust values
+    /// are shown the way Rust would write them instead. `context` is used to follow pointers, such
+    /// as to show the string a char pointer points to.
+    pub fn format_value(&self, bytes: &[u8], context: &dyn ValueContext) -> String {
+        if self.language == Language::Rust {
+            if let Some(value) = self.format_rust_value(bytes, context) {
+                return value;
+            }
+        }
+        let (unsigned, signed) = raw_int_value(bytes);
+        match &self.kind {
+            TypeKind::Base(Encoding::Bool) => format!("{}", unsigned != 0),
+            TypeKind::Base(Encoding::Float) => match bytes.len() {
+                4 => registers::format_float(f32::from_bits(unsigned as u32)),
+                8 => registers::format_float(f64::from_bits(unsigned)),
+                // long double, which is an x87 extended precision number padded to 16 bytes
+                10 | 16 => registers::format_float(registers::extended_to_f64(bytes)),
+                _ => format!("{}", signed),
+            },
+            TypeKind::Base(Encoding::SignedChar) if bytes.len() == 1 => {
+                format!("{} {:?}", signed, unsigned as u8 as char)
+            }
+            TypeKind::Base(Encoding::UnsignedChar) if bytes.len() == 1 => {
+                format!("{} {:?}", unsigned, unsigned as u8 as char)
+            }
+            TypeKind::Base(Encoding::Unsigned) | TypeKind::Base(Encoding::UnsignedChar) => {
+                format!("{}", unsigned)
+            }
+            TypeKind::Base(_) => format!("{}", signed),
+            // Show the string a char pointer points to, like gdb does
+            TypeKind::Pointer(_) if self.name.ends_with("char *") => {
+                match context.read_string(unsigned as usize) {
+                    Some(string) => format!("{:#x} {:?}", unsigned, string),
+                    None => format!("{:#x}", unsigned),
+                }
+            }
+            TypeKind::Pointer(_) => format!("{:#x}", unsigned),
+            // Compare only as many bytes as the enum has, since the debug info doesn't say
+            // whether enumerator values are signed
+            TypeKind::Enum(values) => match values.iter().find(|(_, value)| {
+                raw_int_value(&value.to_le_bytes()[..bytes.len().min(8)]).0 == unsigned
+            }) {
+                Some((name, _)) => name.clone(),
+                None => format!("{}", signed),
+            },
+            TypeKind::Array(element, count) => {
+                let elements = bytes.chunks(element.size.max(1)).take(*count);
+                if let TypeKind::Base(Encoding::SignedChar | Encoding::UnsignedChar) =
+                    element.kind
+                {
+                    // Char arrays are shown as strings
+                    let len = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+                    return format!("{:?}", String::from_utf8_lossy(&bytes[..len]));
+                }
+                format!("{{{}}}", format_elements(element, elements, *count, context))
+            }
+            TypeKind::Struct(members) | TypeKind::Union(members) => {
+                let values: Vec<String> = members
+                    .iter()
+                    .map(|member| {
+                        let value = member.format_value(bytes, context);
+                        if member.name.is_empty() {
+                            value
+                        } else {
+                            format!("{} = {}", member.name, value)
+                        }
+                    })
+                    .collect();
+                format!("{{{}}}", values.join(", "))
+            }
+            TypeKind::Variants { .. } => match self.variant(bytes) {
+                Some(variant) => variant.member.format_value(bytes, context),
+                None => format!("<unknown variant of {}>", self.name),
+            },
+            TypeKind::Function => format!("{{{}}}", self.name),
+        }
+    }
+
+    /// Formats the Rust types that are shown by what they hold rather than how they are laid
+    /// out: `String` and `&str` as strings, `Vec<T>` and slices as their elements, and `Box<T>`
+    /// as what it points to. Structs and the variants of enums are shown like Rust's `{:?}`, e.g.
+    /// `Point {x: 1, y: 2}` and `Some(7)`. Returns None for other types, or if the value doesn't
+    /// look like the type it claims to be.
+    fn format_rust_value(&self, bytes: &[u8], context: &dyn ValueContext) -> Option<String> {
+        let name = self.name.as_str();
+        match &self.kind {
+            TypeKind::Struct(_) if name == "&str" || name == "&mut str" => {
+                let (addr, len) = self.rust_slice(bytes)?;
+                Some(format_rust_string(addr, len, context))
+            }
+            TypeKind::Struct(_) if name.starts_with("&[") || name.starts_with("&mut [") => {
+                let (addr, len) = self.rust_slice(bytes)?;
+                let (_, data_ptr) = self.member("data_ptr")?;
+                let element = match data_ptr.member_type.kind {
+                    TypeKind::Pointer(Some(id)) => context.get_type(id)?,
+                    _ => return None,
+                };
+                Some(format!("[{}]", format_rust_elements(element, addr, len, context)))
+            }
+            TypeKind::Struct(_) if name == "alloc::string::String" => {
+                let (_, vec) = self.member("vec")?;
+                let (addr, len) = vec.member_type.rust_vec(&vec.value_bytes(bytes))?;
+                Some(format_rust_string(addr, len, context))
+            }
+            TypeKind::Struct(_) if name.starts_with("alloc::vec::Vec<") => {
+                let (addr, len) = self.rust_vec(bytes)?;
+                let (_, element) = self.template_params.iter().find(|(name, _)| name == "T")?;
+                let element = context.get_type(*element)?;
+                Some(format!("vec![{}]", format_rust_elements(element, addr, len, context)))
+            }
+            TypeKind::Struct(members) => {
+                Some(format_rust_struct(rust_short_name(name), members, bytes, context))
+            }
+            TypeKind::Pointer(Some(id)) if name.starts_with("alloc::boxed::Box<") => {
+                let addr = raw_int_value(bytes).0 as usize;
+                let pointee = context.get_type(*id)?;
+                Some(match context.read_memory(addr, pointee.size) {
+                    Some(bytes) => format!("Box({})", pointee.format_value(&bytes, context)),
+                    None => format!("Box({:#x})", addr),
+                })
+            }
+            TypeKind::Variants { .. } => {
+                let variant = self.variant(bytes)?;
+                let members = match &variant.member.member_type.kind {
+                    TypeKind::Struct(members) => members,
+                    _ => return None,
+                };
+                let bytes = variant.member.value_bytes(bytes);
+                Some(format_rust_struct(&variant.member.name, members, &bytes, context))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the address and length of a Rust slice or `&str`, which is a pointer to the data
+    /// and the number of elements.
+    fn rust_slice(&self, bytes: &[u8]) -> Option<(usize, usize)> {
+        let (_, data_ptr) = self.member("data_ptr")?;
+        let (_, length) = self.member("length")?;
+        let addr = raw_int_value(&data_ptr.value_bytes(bytes)).0 as usize;
+        Some((addr, raw_int_value(&length.value_bytes(bytes)).0 as usize))
+    }
+
+    /// Returns the address of the elements of a Rust `Vec` and how many there are. The pointer
+    /// to the elements is the first one inside `buf`, however many wrappers (`RawVec`, `Unique`,
+    /// `NonNull` and so on) the standard library puts around it.
+    fn rust_vec(&self, bytes: &[u8]) -> Option<(usize, usize)> {
+        let (_, buf) = self.member("buf")?;
+        let (_, len) = self.member("len")?;
+        let addr = buf.member_type.first_pointer(&buf.value_bytes(bytes))?;
+        Some((addr, raw_int_value(&len.value_bytes(bytes)).0 as usize))
+    }
+
+    fn first_pointer(&self, bytes: &[u8]) -> Option<usize> {
+        match &self.kind {
+            TypeKind::Pointer(_) => Some(raw_int_value(bytes).0 as usize),
+            TypeKind::Struct(members) => members
+                .iter()
+                .find_map(|member| member.member_type.first_pointer(&member.value_bytes(bytes))),
+            _ => None,
         }
     }
 }
 
+impl Member {
+    /// Extracts this member's bytes from the bytes of the struct containing it. Bit fields are
+    /// shifted and sign-extended into bytes of their own.
+    pub fn value_bytes(&self, bytes: &[u8]) -> Vec<u8> {
+        let size = self.member_type.size;
+        let end = (self.offset + size).min(bytes.len());
+        let start = self.offset.min(end);
+        match self.bits {
+            None => bytes[start..end].to_vec(),
+            Some((bit_offset, bit_count)) => {
+                let end = (self.offset + (bit_offset + bit_count).div_ceil(8)).min(bytes.len());
+                let (raw, _) = raw_int_value(&bytes[start..end]);
+                let shift = 64 - bit_count as u32;
+                let value = (raw >> bit_offset) << shift;
+                let value = match self.member_type.kind {
+                    TypeKind::Base(Encoding::Signed) | TypeKind::Base(Encoding::SignedChar) => {
+                        ((value as i64) >> shift) as u64
+                    }
+                    _ => value >> shift,
+                };
+                value.to_le_bytes()[..size.min(8)].to_vec()
+            }
+        }
+    }
+
+    fn format_value(&self, bytes: &[u8], context: &dyn ValueContext) -> String {
+        self.member_type.format_value(&self.value_bytes(bytes), context)
+    }
+}
+
+/// Formats up to MAX_ELEMENTS elements of an array, separated by commas.
+fn format_elements<'a>(
+    element: &Type,
+    elements: impl Iterator<Item = &'a [u8]>,
+    count: usize,
+    context: &dyn ValueContext,
+) -> String {
+    let mut values: Vec<String> = elements
+        .take(MAX_ELEMENTS)
+        .map(|bytes| element.format_value(bytes, context))
+        .collect();
+    if count > MAX_ELEMENTS {
+        values.push("...".to_string());
+    }
+    values.join(", ")
+}
+
+/// Formats the elements of a Rust `Vec` or slice, which are in the program's memory at addr.
+fn format_rust_elements(element: &Type, addr: usize, len: usize, context: &dyn ValueContext) -> String {
+    let shown = len.min(MAX_ELEMENTS);
+    match context.read_memory(addr, shown * element.size) {
+        Some(bytes) => format_elements(element, bytes.chunks(element.size.max(1)), len, context),
+        None => format!("<error reading {} elements at {:#x}>", len, addr),
+    }
+}
+
+/// Formats a Rust string, which is len bytes of UTF-8 at addr. Long strings are cut off like
+/// gdb does, with "..." after the closing quote.
+fn format_rust_string(addr: usize, len: usize, context: &dyn ValueContext) -> String {
+    let shown = len.min(MAX_ELEMENTS);
+    match context.read_memory(addr, shown) {
+        Some(bytes) if shown < len => format!("{:?}...", String::from_utf8_lossy(&bytes)),
+        Some(bytes) => format!("{:?}", String::from_utf8_lossy(&bytes)),
+        None => format!("<error reading string at {:#x}>", addr),
+    }
+}
+
+/// Formats a Rust struct or enum variant like `{:?}` does: `Name {a: 1, b: 2}`, `Name(1, 2)` for
+/// tuple structs, whose fields are named __0, __1 and so on, or just `Name` when it has no fields.
+/// Tuples are named like `(i32, i32)` and shown as `(1, 2)`.
+fn format_rust_struct(
+    name: &str,
+    members: &[Member],
+    bytes: &[u8],
+    context: &dyn ValueContext,
+) -> String {
+    if members.is_empty() {
+        return name.to_string();
+    }
+    let name = if name.starts_with('(') { "" } else { name };
+    if members.iter().all(|member| member.name.starts_with("__")) {
+        let values: Vec<String> =
+            members.iter().map(|member| member.format_value(bytes, context)).collect();
+        return format!("{}({})", name, values.join(", "));
+    }
+    let values: Vec<String> = members
+        .iter()
+        .map(|member| format!("{}: {}", member.name, member.format_value(bytes, context)))
+        .collect();
+    format!("{} {{{}}}", name, values.join(", "))
+}
+
+/// Leaves the module path off a Rust type name, e.g. "Point" for "shapes::Point" or
+/// "Option<i32>" for "core::option::Option<i32>". The paths inside type arguments, tuples and
+/// references are kept.
+fn rust_short_name(name: &str) -> &str {
+    if name.starts_with(['(', '&', '[', '*']) {
+        return name;
+    }
+    let path = &name[..name.find('<').unwrap_or(name.len())];
+    match path.rfind("::") {
+        Some(end) => &name[end + 2..],
+        None => name,
+    }
+}
+
+/// Reads up to 8 little-endian bytes as an integer, returning it both zero-extended and
+/// sign-extended.
+fn raw_int_value(bytes: &[u8]) -> (u64, i64) {
+    let mut buf = [0u8; 8];
+    let len = bytes.len().min(buf.len());
+    buf[..len].copy_from_slice(&bytes[..len]);
+    let unsigned = u64::from_le_bytes(buf);
+    let signed = if len > 0 && len < 8 {
+        let shift = 64 - 8 * len as u32;
+        ((unsigned << shift) as i64) >> shift
+    } else {
+        unsigned as i64
+    };
+    (unsigned, signed)
+}
+
 #[derive(Clone)]
 pub enum Location {
     Address(usize),
     FramePointerOffset(isize),
+    /// Any other DWARF expression, evaluated against the registers and memory of the frame
+    Expression(DwarfExpression),
+    /// Where the variable is for each range of code addresses, for variables that optimized code
+    /// moves between registers and memory. Outside the ranges the variable doesn't exist.
+    List(Vec<(usize, usize, DwarfExpression)>),
+    /// The value itself (DW_AT_const_value), for constants the compiler didn't store anywhere
+    Constant(Vec<u8>),
+    OptimizedOut,
+}
+
+impl Location {
+    fn expressions_mut(&mut self) -> Vec<&mut DwarfExpression> {
+        match self {
+            Location::Expression(expr) => vec![expr],
+            Location::List(ranges) => ranges.iter_mut().map(|(_, _, expr)| expr).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn relocate(&mut self, delta: usize) {
+        match self {
+            Location::Address(addr) => *addr = addr.wrapping_add(delta),
+            Location::List(ranges) => {
+                for (start, end, _) in ranges.iter_mut() {
+                    *start = start.wrapping_add(delta);
+                    *end = end.wrapping_add(delta);
+                }
+            }
+            _ => {}
+        }
+        for expr in self.expressions_mut() {
+            expr.bias = expr.bias.wrapping_add(delta);
+        }
+    }
 }
 
 impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
+        match self {
             Location::Address(addr) => write!(f, "Address({:#x})", addr),
             Location::FramePointerOffset(offset) => write!(f, "FramePointerOffset({})", offset),
+            Location::Expression(expr) => write!(f, "Expression({})", expr),
+            Location::List(ranges) => {
+                write!(f, "List(")?;
+                for (index, (start, end, expr)) in ranges.iter().enumerate() {
+                    let separator = if index == 0 { "" } else { ", " };
+                    write!(f, "{}{:#x}-{:#x}: {}", separator, start, end, expr)?;
+                }
+                write!(f, ")")
+            }
+            Location::Constant(bytes) => write!(f, "Constant({:02x?})", bytes),
+            Location::OptimizedOut => write!(f, "OptimizedOut"),
         }
     }
 }
@@ -184,6 +1116,34 @@ impl fmt::Debug for Location {
     }
 }
 
+/// The bytecode of a DWARF expression, with what's needed to evaluate it later.
+#[derive(Clone)]
+pub struct DwarfExpression {
+    pub bytes: Vec<u8>,
+    pub encoding: gimli::Encoding,
+    /// Section offset of the unit the expression is from, since typed operations refer to base
+    /// types by their offset in the unit
+    pub unit_offset: usize,
+    /// Load bias of the object the expression is from, which DW_OP_addr operands are relative to
+    pub bias: usize,
+    /// The register that DW_OP_fbreg is relative to, for functions whose DW_AT_frame_base is one
+    /// (LLVM uses rbp). None if the frame base is the CFA, as gcc makes it.
+    pub frame_base: Option<u16>,
+}
+
+impl fmt::Display for DwarfExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        write!(f, "{}", bytes.join(" "))
+    }
+}
+
+impl fmt::Debug for DwarfExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
 // For variables and formal parameters
 #[derive(Debug, Clone)]
 pub struct Variable {
@@ -199,7 +1159,21 @@ pub struct Function {
     pub address: usize,
     pub text_length: usize,
     pub line_number: usize, // Line number in source file
+    /// None for functions that return void
+    pub return_type: Option<Type>,
     pub variables: Vec<Variable>,
+    pub call_sites: Vec<CallSite>,
+}
+
+/// A call made by a function, with the values the caller passed in registers
+/// (DW_TAG_call_site), which is how DW_OP_entry_value in the callee is worked out.
+#[derive(Debug, Clone)]
+pub struct CallSite {
+    /// Address of the instruction after the call
+    pub return_addr: usize,
+    /// The DWARF number of each register an argument was passed in, and an expression for its
+    /// value in the caller's frame
+    pub parameters: Vec<(u16, DwarfExpression)>,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -210,6 +1184,56 @@ pub struct File {
     pub lines: Vec<Line>,
 }
 
+impl File {
+    /// Adds base to the ids of the types of the variables in this file, and to the unit offsets
+    /// of their expressions, to match the rebased type table.
+    fn rebase_types(&mut self, base: usize) {
+        for return_type in self.functions.iter_mut().filter_map(|func| func.return_type.as_mut()) {
+            return_type.rebase(base);
+        }
+        let functions = self.functions.iter_mut();
+        let variables = self
+            .global_variables
+            .iter_mut()
+            .chain(functions.flat_map(|func| func.variables.iter_mut()));
+        for var in variables {
+            var.entity_type.rebase(base);
+            for expr in var.location.expressions_mut() {
+                expr.unit_offset += base;
+            }
+        }
+        for call_site in self.functions.iter_mut().flat_map(|func| func.call_sites.iter_mut()) {
+            for (_, expr) in call_site.parameters.iter_mut() {
+                expr.unit_offset += base;
+            }
+        }
+    }
+
+    /// Moves every code and data address in this file by delta.
+    fn relocate(&mut self, delta: usize) {
+        for line in self.lines.iter_mut() {
+            line.address = line.address.wrapping_add(delta);
+        }
+        for func in self.functions.iter_mut() {
+            func.address = func.address.wrapping_add(delta);
+            for call_site in func.call_sites.iter_mut() {
+                call_site.return_addr = call_site.return_addr.wrapping_add(delta);
+                for (_, expr) in call_site.parameters.iter_mut() {
+                    expr.bias = expr.bias.wrapping_add(delta);
+                }
+            }
+        }
+        let functions = self.functions.iter_mut();
+        let variables = self
+            .global_variables
+            .iter_mut()
+            .chain(functions.flat_map(|func| func.variables.iter_mut()));
+        for var in variables {
+            var.location.relocate(delta);
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Line {
     pub file: String,
@@ -222,3 +1246,11 @@ impl fmt::Display for Line {
         write!(f, "{}:{}", self.file, self.number)
     }
 }
+
+fn format_symbol(name: &str, offset: usize) -> String {
+    if offset == 0 {
+        format!("<{}>", name)
+    } else {
+        format!("<{}+{}>", name, offset)
+    }
+}
@@ -0,0 +1,275 @@
+//! The `x/FMT ADDRESS` command, which dumps the program's memory in various formats.
+
+use crate::dwarf_data::DwarfData;
+use crate::target::Target;
+use crate::breakpoint::BreakpointTable;
+use crate::disassemble::{self, MAX_INSTRUCTION_LEN};
+
+/// Longest string `x/s` shows before cutting it off, like gdb's default `print elements` limit.
+const MAX_STRING_LEN: usize = 200;
+
+/// What `x` shows: `count` units of `size` bytes each, in the format given by `letter`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Format {
+    pub count: usize,
+    pub letter: char,
+    pub size: usize,
+}
+
+impl Default for Format {
+    fn default() -> Format {
+        Format {
+            count: 1,
+            letter: 'x',
+            size: 4,
+        }
+    }
+}
+
+impl Format {
+    /// Parses the part after the "/" of `x/FMT`: an optional count followed by format and size
+    /// letters in any order. Letters that aren't given are taken from the previous format, like
+    /// gdb does.
+    pub fn parse(spec: &str, last: &Format) -> Result<Format, String> {
+        let digits = spec.chars().take_while(|c| c.is_ascii_digit()).count();
+        let count = match &spec[..digits] {
+            "" => 1,
+            count => count.parse().map_err(|_| format!("Invalid count \"{}\"", count))?,
+        };
+        let mut format = Format {
+            count,
+            ..*last
+        };
+        for c in spec[digits..].chars() {
+            match c {
+                'x' | 'd' | 'u' | 'o' | 't' | 'c' | 's' | 'i' => format.letter = c,
+                'b' => format.size = 1,
+                'h' => format.size = 2,
+                'w' => format.size = 4,
+                'g' => format.size = 8,
+                _ => return Err(format!("Undefined output format \"{}\".", c)),
+            }
+        }
+        if format.letter == 'c' {
+            format.size = 1;
+        }
+        Ok(format)
+    }
+
+    /// How many units fit on one line of output.
+    fn per_line(&self) -> usize {
+        match (self.letter, self.size) {
+            ('c', _) | (_, 1) | (_, 2) => 8,
+            (_, 4) => 4,
+            _ => 2,
+        }
+    }
+
+    /// Formats one unit of memory, given as a little-endian integer.
+    fn format_unit(&self, value: u64) -> String {
+        let bits = self.size * 8;
+        // Sign-extend from the unit's size
+        let signed = ((value << (64 - bits)) as i64) >> (64 - bits);
+        match self.letter {
+            'd' => signed.to_string(),
+            'u' => value.to_string(),
+            'o' if value == 0 => "0".to_string(),
+            'o' => format!("0{:o}", value),
+            't' => format!("{:0width$b}", value, width = bits),
+            'c' => format!("{} {}", signed, quote_char(value as u8, '\'')),
+            _ => format!("0x{:0width$x}", value, width = self.size * 2),
+        }
+    }
+}
+
+/// Quotes a byte the way C would write it inside a character or string literal.
+fn escape(byte: u8, quote: char) -> String {
+    match byte {
+        b'\n' => "\\n".to_string(),
+        b'\t' => "\\t".to_string(),
+        b'\r' => "\\r".to_string(),
+        0 => "\\0".to_string(),
+        b'\\' => "\\\\".to_string(),
+        _ if byte as char == quote => format!("\\{}", quote),
+        0x20..=0x7e => (byte as char).to_string(),
+        _ => format!("\\{:03o}", byte),
+    }
+}
+
+fn quote_char(byte: u8, quote: char) -> String {
+    format!("{}{}{}", quote, escape(byte, quote), quote)
+}
+
+/// The address at the start of a line of output, with the symbol it belongs to if there is one.
+fn label(debug_data: &DwarfData, addr: usize) -> String {
+    match debug_data.get_symbol_for_addr(addr) {
+        Some(symbol) => format!("{:#x} {}", addr, symbol),
+        None => format!("{:#x}", addr),
+    }
+}
+
+/// Prints memory starting at addr according to format. Returns the address just past what was
+/// shown, or an error naming the first address that couldn't be read.
+pub fn examine(
+    target: &dyn Target,
+    debug_data: &DwarfData,
+    breakpoints: &BreakpointTable,
+    format: &Format,
+    addr: usize,
+) -> Result<usize, String> {
+    match format.letter {
+        's' => examine_strings(target, debug_data, format, addr),
+        'i' => examine_instructions(target, debug_data, breakpoints, format, addr),
+        _ => examine_units(target, debug_data, format, addr),
+    }
+}
+
+fn cannot_access(addr: usize) -> String {
+    format!("Cannot access memory at address {:#x}", addr)
+}
+
+fn read(target: &dyn Target, addr: usize, len: usize) -> Result<Vec<u8>, String> {
+    target
+        .read_memory_partial(addr, len)
+        .map_err(|err| format!("Cannot read memory: {}", err))
+}
+
+fn examine_units(
+    target: &dyn Target,
+    debug_data: &DwarfData,
+    format: &Format,
+    addr: usize,
+) -> Result<usize, String> {
+    let bytes = read(target, addr, format.count * format.size)?;
+    let units: Vec<u64> = bytes
+        .chunks_exact(format.size)
+        .map(|chunk| {
+            let mut value = [0u8; 8];
+            value[..chunk.len()].copy_from_slice(chunk);
+            u64::from_le_bytes(value)
+        })
+        .collect();
+    for (line, values) in units.chunks(format.per_line()).enumerate() {
+        let line_addr = addr + line * format.per_line() * format.size;
+        let values: Vec<String> = values.iter().map(|value| format.format_unit(*value)).collect();
+        println!("{}:\t{}", label(debug_data, line_addr), values.join("\t"));
+    }
+    let end = addr + units.len() * format.size;
+    if units.len() < format.count {
+        return Err(cannot_access(end));
+    }
+    Ok(end)
+}
+
+fn examine_strings(
+    target: &dyn Target,
+    debug_data: &DwarfData,
+    format: &Format,
+    mut addr: usize,
+) -> Result<usize, String> {
+    for _ in 0..format.count {
+        let bytes = read(target, addr, MAX_STRING_LEN + 1)?;
+        let (len, terminated) = match bytes.iter().position(|byte| *byte == 0) {
+            Some(len) => (len, true),
+            None if bytes.len() > MAX_STRING_LEN => (MAX_STRING_LEN, false),
+            // The string runs into memory we can't read
+            None => return Err(cannot_access(addr + bytes.len())),
+        };
+        let text: String = bytes[..len].iter().map(|byte| escape(*byte, '"')).collect();
+        let ellipsis = if terminated { "" } else { "..." };
+        println!("{}:\t\"{}\"{}", label(debug_data, addr), text, ellipsis);
+        addr += if terminated { len + 1 } else { len };
+    }
+    Ok(addr)
+}
+
+fn examine_instructions(
+    target: &dyn Target,
+    debug_data: &DwarfData,
+    breakpoints: &BreakpointTable,
+    format: &Format,
+    addr: usize,
+) -> Result<usize, String> {
+    let bytes = target
+        .read_code(addr, format.count * MAX_INSTRUCTION_LEN)
+        .map_err(|err| format!("Cannot read memory: {}", err))?;
+    let rip = target.registers().ok().map(|regs| regs.rip as usize);
+    let mut instructions = disassemble::decode(&bytes, addr, true, debug_data);
+    let decoded = instructions.len();
+    instructions.truncate(format.count);
+    for instruction in &instructions {
+        let symbol = debug_data.get_symbol_for_addr(instruction.addr);
+        disassemble::print_instruction(instruction, symbol, rip, breakpoints);
+    }
+    let end = instructions
+        .last()
+        .map_or(addr, |instruction| instruction.addr + instruction.len);
+    if decoded < format.count {
+        return Err(cannot_access(end));
+    }
+    Ok(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(count: usize, letter: char, size: usize) -> Format {
+        Format { count, letter, size }
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(Format::default(), format(1, 'x', 4));
+        assert_eq!(Format::parse("", &Format::default()), Ok(format(1, 'x', 4)));
+    }
+
+    #[test]
+    fn letters_in_any_order() {
+        let last = Format::default();
+        assert_eq!(Format::parse("d", &last), Ok(format(1, 'd', 4)));
+        assert_eq!(Format::parse("g", &last), Ok(format(1, 'x', 8)));
+        assert_eq!(Format::parse("xb", &last), Ok(format(1, 'x', 1)));
+        assert_eq!(Format::parse("bx", &last), Ok(format(1, 'x', 1)));
+        assert_eq!(Format::parse("hu", &last), Ok(format(1, 'u', 2)));
+        // The last letter of each kind wins
+        assert_eq!(Format::parse("dxbw", &last), Ok(format(1, 'x', 4)));
+    }
+
+    #[test]
+    fn sticky_size_and_format() {
+        let last = format(8, 'd', 8);
+        // The count isn't sticky, but the letter and size are
+        assert_eq!(Format::parse("", &last), Ok(format(1, 'd', 8)));
+        assert_eq!(Format::parse("b", &last), Ok(format(1, 'd', 1)));
+        assert_eq!(Format::parse("4o", &last), Ok(format(4, 'o', 8)));
+        // Characters are always one byte, and the next format keeps that size
+        let chars = Format::parse("c", &last).unwrap();
+        assert_eq!(chars, format(1, 'c', 1));
+        assert_eq!(Format::parse("x", &chars), Ok(format(1, 'x', 1)));
+        assert_eq!(Format::parse("cg", &last), Ok(format(1, 'c', 1)));
+    }
+
+    #[test]
+    fn counts() {
+        let last = Format::default();
+        assert_eq!(Format::parse("4", &last), Ok(format(4, 'x', 4)));
+        assert_eq!(Format::parse("16xb", &last), Ok(format(16, 'x', 1)));
+        assert_eq!(Format::parse("0x", &last), Ok(format(0, 'x', 4)));
+        assert_eq!(Format::parse("3i", &last), Ok(format(3, 'i', 4)));
+        assert_eq!(
+            Format::parse("99999999999999999999x", &last),
+            Err("Invalid count \"99999999999999999999\"".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid_letters() {
+        let last = Format::default();
+        assert_eq!(Format::parse("z", &last), Err("Undefined output format \"z\".".to_string()));
+        assert_eq!(Format::parse("4xq", &last), Err("Undefined output format \"q\".".to_string()));
+        // A count has to come first
+        assert_eq!(Format::parse("x4", &last), Err("Undefined output format \"4\".".to_string()));
+        assert_eq!(Format::parse("-1x", &last), Err("Undefined output format \"-\".".to_string()));
+    }
+}
@@ -0,0 +1,505 @@
+//! A small C-like expression language used for breakpoint conditions and `print`. Expressions
+//! are made of integer literals, variable names and `$registers`, combined with the usual C
+//! arithmetic, bitwise, comparison and logical operators. Struct members (`s.a`, `p->next`),
+//! subscripts (`arr[3]`), `*p` and `&x` follow the types in the debug info; everything else is
+//! computed on 64-bit integers.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    /// Binding strength of the operator, following C. Higher binds tighter.
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 10,
+            BinaryOp::Add | BinaryOp::Sub => 9,
+            BinaryOp::Shl | BinaryOp::Shr => 8,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 7,
+            BinaryOp::Eq | BinaryOp::Ne => 6,
+            BinaryOp::BitAnd => 5,
+            BinaryOp::BitXor => 4,
+            BinaryOp::BitOr => 3,
+            BinaryOp::And => 2,
+            BinaryOp::Or => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Variable(String),
+    Register(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// `s.member`; `p->member` is parsed as `(*p).member`
+    Member(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Deref(Box<Expr>),
+    AddressOf(Box<Expr>),
+}
+
+/// Supplies the values of the names that appear in an expression.
+pub trait Context {
+    fn variable(&self, name: &str) -> Result<i64, String>;
+    fn register(&self, name: &str) -> Result<i64, String>;
+    /// Evaluates a member access, subscript, `*` or `&` expression, which needs type information.
+    fn typed(&self, expr: &Expr) -> Result<i64, String>;
+}
+
+impl Expr {
+    pub fn parse(input: &str) -> Result<Expr, String> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_binary(0)?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expr),
+            Some(token) => Err(format!("Unexpected \"{}\" in expression", token)),
+        }
+    }
+
+    pub fn eval(&self, ctx: &dyn Context) -> Result<i64, String> {
+        match self {
+            Expr::Number(value) => Ok(*value),
+            Expr::Variable(name) => ctx.variable(name),
+            Expr::Register(name) => ctx.register(name),
+            Expr::Member(..) | Expr::Index(..) | Expr::Deref(_) | Expr::AddressOf(_) => {
+                ctx.typed(self)
+            }
+            Expr::Unary(op, operand) => {
+                let value = operand.eval(ctx)?;
+                Ok(match op {
+                    UnaryOp::Neg => value.wrapping_neg(),
+                    UnaryOp::Not => (value == 0) as i64,
+                    UnaryOp::BitNot => !value,
+                })
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = lhs.eval(ctx)?;
+                // && and || short-circuit like in C
+                match op {
+                    BinaryOp::And if lhs == 0 => return Ok(0),
+                    BinaryOp::Or if lhs != 0 => return Ok(1),
+                    _ => {}
+                }
+                let rhs = rhs.eval(ctx)?;
+                Ok(match op {
+                    BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                    BinaryOp::Div | BinaryOp::Rem if rhs == 0 => {
+                        return Err("Division by zero".to_string())
+                    }
+                    BinaryOp::Div => lhs.wrapping_div(rhs),
+                    BinaryOp::Rem => lhs.wrapping_rem(rhs),
+                    BinaryOp::Add => lhs.wrapping_add(rhs),
+                    BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                    BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+                    BinaryOp::Shr => lhs.wrapping_shr(rhs as u32),
+                    BinaryOp::Lt => (lhs < rhs) as i64,
+                    BinaryOp::Le => (lhs <= rhs) as i64,
+                    BinaryOp::Gt => (lhs > rhs) as i64,
+                    BinaryOp::Ge => (lhs >= rhs) as i64,
+                    BinaryOp::Eq => (lhs == rhs) as i64,
+                    BinaryOp::Ne => (lhs != rhs) as i64,
+                    BinaryOp::BitAnd => lhs & rhs,
+                    BinaryOp::BitXor => lhs ^ rhs,
+                    BinaryOp::BitOr => lhs | rhs,
+                    BinaryOp::And | BinaryOp::Or => (rhs != 0) as i64,
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Register(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{}", value),
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Register(name) => write!(f, "${}", name),
+            Token::Op(op) => write!(f, "{}", op),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::LBracket => write!(f, "["),
+            Token::RBracket => write!(f, "]"),
+        }
+    }
+}
+
+// Longer operators come first so that e.g. "<=" isn't read as "<" followed by "="
+const OPERATORS: [&str; 22] = [
+    "->", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "*", "/", "%", "+", "-", "<", ">", "&",
+    "^", "|", "!", "~", ".",
+];
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            tokens.push(Token::LParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            i += 1;
+        } else if c == '[' {
+            tokens.push(Token::LBracket);
+            i += 1;
+        } else if c == ']' {
+            tokens.push(Token::RBracket);
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let literal: String = chars[start..i].iter().collect();
+            // Hex literals up to 0xffffffffffffffff are allowed, as in addresses and masks, and
+            // keep their bits
+            let value = match literal.strip_prefix("0x").or(literal.strip_prefix("0X")) {
+                Some(hex) => u64::from_str_radix(hex, 16).map(|value| value as i64),
+                None => literal.parse(),
+            };
+            tokens.push(Token::Number(
+                value.map_err(|_| format!("Invalid number \"{}\"", literal))?,
+            ));
+        } else if c == '\'' {
+            // Character literal, e.g. 'a'
+            match (chars.get(i + 1), chars.get(i + 2)) {
+                (Some(ch), Some('\'')) => {
+                    tokens.push(Token::Number(*ch as i64));
+                    i += 3;
+                }
+                _ => return Err("Invalid character literal".to_string()),
+            }
+        } else if c == '$' || c == '_' || c.is_ascii_alphabetic() {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i] == '_' || chars[i].is_ascii_alphanumeric()) {
+                i += 1;
+            }
+            let name: String = chars[start..i].iter().collect();
+            match name.strip_prefix('$') {
+                Some("") => return Err("Missing register name after \"$\"".to_string()),
+                Some(register) => tokens.push(Token::Register(register.to_string())),
+                None => tokens.push(Token::Ident(name)),
+            }
+        } else {
+            let rest: String = chars[i..].iter().collect();
+            match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                Some(op) => {
+                    tokens.push(Token::Op(op));
+                    i += op.len();
+                }
+                None => return Err(format!("Unexpected character '{}' in expression", c)),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek_binary_op(&self) -> Option<BinaryOp> {
+        let op = match self.tokens.get(self.pos)? {
+            Token::Op(op) => *op,
+            _ => return None,
+        };
+        Some(match op {
+            "*" => BinaryOp::Mul,
+            "/" => BinaryOp::Div,
+            "%" => BinaryOp::Rem,
+            "+" => BinaryOp::Add,
+            "-" => BinaryOp::Sub,
+            "<<" => BinaryOp::Shl,
+            ">>" => BinaryOp::Shr,
+            "<" => BinaryOp::Lt,
+            "<=" => BinaryOp::Le,
+            ">" => BinaryOp::Gt,
+            ">=" => BinaryOp::Ge,
+            "==" => BinaryOp::Eq,
+            "!=" => BinaryOp::Ne,
+            "&" => BinaryOp::BitAnd,
+            "^" => BinaryOp::BitXor,
+            "|" => BinaryOp::BitOr,
+            "&&" => BinaryOp::And,
+            "||" => BinaryOp::Or,
+            _ => return None,
+        })
+    }
+
+    /// Parses a chain of binary operators that bind at least as tightly as min_precedence
+    /// (precedence climbing).
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Expr, String> {
+        let mut lhs = self.parse_unary()?;
+        while let Some(op) = self.peek_binary_op() {
+            if op.precedence() < min_precedence {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.parse_binary(op.precedence() + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        let op = match self.tokens.get(self.pos) {
+            Some(Token::Op("-")) => UnaryOp::Neg,
+            Some(Token::Op("!")) => UnaryOp::Not,
+            Some(Token::Op("~")) => UnaryOp::BitNot,
+            Some(Token::Op("+")) => {
+                self.pos += 1;
+                return self.parse_unary();
+            }
+            Some(Token::Op("*")) => {
+                self.pos += 1;
+                return Ok(Expr::Deref(Box::new(self.parse_unary()?)));
+            }
+            Some(Token::Op("&")) => {
+                self.pos += 1;
+                return Ok(Expr::AddressOf(Box::new(self.parse_unary()?)));
+            }
+            _ => return self.parse_postfix(),
+        };
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(self.parse_unary()?)))
+    }
+
+    /// Parses a primary expression followed by any number of `.member`, `->member` and `[index]`.
+    fn parse_postfix(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_primary()?;
+        loop {
+            match self.tokens.get(self.pos) {
+                Some(Token::Op(op)) if *op == "." || *op == "->" => {
+                    let arrow = *op == "->";
+                    let member = match self.tokens.get(self.pos + 1) {
+                        Some(Token::Ident(member)) => member.clone(),
+                        _ => return Err(format!("Expected a member name after \"{}\"", op)),
+                    };
+                    self.pos += 2;
+                    if arrow {
+                        expr = Expr::Deref(Box::new(expr));
+                    }
+                    expr = Expr::Member(Box::new(expr), member);
+                }
+                Some(Token::LBracket) => {
+                    self.pos += 1;
+                    let index = self.parse_binary(0)?;
+                    match self.tokens.get(self.pos) {
+                        Some(Token::RBracket) => self.pos += 1,
+                        _ => return Err("Missing \"]\" in expression".to_string()),
+                    }
+                    expr = Expr::Index(Box::new(expr), Box::new(index));
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| "Unexpected end of expression".to_string())?;
+        self.pos += 1;
+        match token {
+            Token::Number(value) => Ok(Expr::Number(value)),
+            Token::Ident(name) => Ok(Expr::Variable(name)),
+            Token::Register(name) => Ok(Expr::Register(name)),
+            Token::LParen => {
+                let expr = self.parse_binary(0)?;
+                match self.tokens.get(self.pos) {
+                    Some(Token::RParen) => {
+                        self.pos += 1;
+                        Ok(expr)
+                    }
+                    _ => Err("Missing \")\" in expression".to_string()),
+                }
+            }
+            other => Err(format!("Unexpected \"{}\" in expression", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Knows the variable `x` = 6 and the register `$rax` = 0x10.
+    struct TestContext;
+
+    impl Context for TestContext {
+        fn variable(&self, name: &str) -> Result<i64, String> {
+            match name {
+                "x" => Ok(6),
+                _ => Err(format!("No symbol \"{}\" in current context.", name)),
+            }
+        }
+
+        fn register(&self, name: &str) -> Result<i64, String> {
+            match name {
+                "rax" => Ok(0x10),
+                _ => Err(format!("Invalid register \"${}\"", name)),
+            }
+        }
+
+        fn typed(&self, _expr: &Expr) -> Result<i64, String> {
+            Err("No type information".to_string())
+        }
+    }
+
+    fn eval(input: &str) -> Result<i64, String> {
+        Expr::parse(input)?.eval(&TestContext)
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(eval("42"), Ok(42));
+        assert_eq!(eval("0x2a"), Ok(42));
+        assert_eq!(eval("0X2A"), Ok(42));
+        assert_eq!(eval("'a'"), Ok(97));
+        assert_eq!(eval("0x7fffffffffffffff"), Ok(i64::MAX));
+        assert_eq!(eval("0xffffffffffffffff"), Ok(-1));
+        assert_eq!(eval("0x8000000000000000"), Ok(i64::MIN));
+        assert!(eval("0x10000000000000000").is_err());
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("2 + 3 * 4"), Ok(14));
+        assert_eq!(eval("(2 + 3) * 4"), Ok(20));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3));
+        assert_eq!(eval("100 / 10 / 5"), Ok(2));
+        assert_eq!(eval("1 << 2 + 1"), Ok(8));
+        // 8 | (2 ^ (3 & 6)), not ((8 | 2) ^ 3) & 6
+        assert_eq!(eval("8 | 2 ^ 3 & 6"), Ok(8));
+        assert_eq!(eval("x + 1 == 7 && $rax > 8"), Ok(1));
+        assert_eq!(eval("0 || 1 && 0"), Ok(0));
+        assert_eq!(
+            Expr::parse("a + b * c"),
+            Ok(Expr::Binary(
+                BinaryOp::Add,
+                Box::new(Expr::Variable("a".to_string())),
+                Box::new(Expr::Binary(
+                    BinaryOp::Mul,
+                    Box::new(Expr::Variable("b".to_string())),
+                    Box::new(Expr::Variable("c".to_string())),
+                )),
+            ))
+        );
+    }
+
+    #[test]
+    fn unary_operators() {
+        assert_eq!(eval("-x"), Ok(-6));
+        assert_eq!(eval("--x"), Ok(6));
+        assert_eq!(eval("+x"), Ok(6));
+        assert_eq!(eval("!x"), Ok(0));
+        assert_eq!(eval("!0"), Ok(1));
+        assert_eq!(eval("~0"), Ok(-1));
+        assert_eq!(eval("-2 * 3"), Ok(-6));
+        assert_eq!(
+            Expr::parse("*p->next"),
+            Ok(Expr::Deref(Box::new(Expr::Member(
+                Box::new(Expr::Deref(Box::new(Expr::Variable("p".to_string())))),
+                "next".to_string(),
+            ))))
+        );
+        assert_eq!(
+            Expr::parse("&arr[2]"),
+            Ok(Expr::AddressOf(Box::new(Expr::Index(
+                Box::new(Expr::Variable("arr".to_string())),
+                Box::new(Expr::Number(2)),
+            ))))
+        );
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_eq!(eval("x < 7"), Ok(1));
+        assert_eq!(eval("x <= 6"), Ok(1));
+        assert_eq!(eval("x > 6"), Ok(0));
+        assert_eq!(eval("x >= 7"), Ok(0));
+        assert_eq!(eval("x == 6"), Ok(1));
+        assert_eq!(eval("x != 6"), Ok(0));
+        // Comparisons are signed
+        assert_eq!(eval("-1 < 0"), Ok(1));
+        assert_eq!(eval("0xffffffffffffffff < 0"), Ok(1));
+    }
+
+    #[test]
+    fn evaluation_errors() {
+        assert_eq!(eval("x / 0"), Err("Division by zero".to_string()));
+        assert_eq!(eval("x % 0"), Err("Division by zero".to_string()));
+        // The right side of && and || isn't evaluated when the left side decides the result
+        assert_eq!(eval("0 && 1 / 0"), Ok(0));
+        assert_eq!(eval("1 || nosuch"), Ok(1));
+        assert!(eval("nosuch + 1").is_err());
+        assert!(eval("$nosuch").is_err());
+    }
+
+    #[test]
+    fn malformed_input() {
+        assert_eq!(Expr::parse(""), Err("Unexpected end of expression".to_string()));
+        assert_eq!(Expr::parse("1 +"), Err("Unexpected end of expression".to_string()));
+        assert_eq!(Expr::parse("(1 + 2"), Err("Missing \")\" in expression".to_string()));
+        assert_eq!(Expr::parse("arr[1"), Err("Missing \"]\" in expression".to_string()));
+        assert_eq!(Expr::parse("1 2"), Err("Unexpected \"2\" in expression".to_string()));
+        assert_eq!(Expr::parse(")"), Err("Unexpected \")\" in expression".to_string()));
+        assert_eq!(Expr::parse("s."), Err("Expected a member name after \".\"".to_string()));
+        assert_eq!(Expr::parse("12ab"), Err("Invalid number \"12ab\"".to_string()));
+        assert_eq!(Expr::parse("0xzz"), Err("Invalid number \"0xzz\"".to_string()));
+        assert_eq!(Expr::parse("'a"), Err("Invalid character literal".to_string()));
+        assert_eq!(Expr::parse("$"), Err("Missing register name after \"$\"".to_string()));
+        assert_eq!(Expr::parse("x @ 1"), Err("Unexpected character '@' in expression".to_string()));
+    }
+}
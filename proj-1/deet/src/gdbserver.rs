@@ -0,0 +1,684 @@
+//! Serves the inferior over the GDB Remote Serial Protocol, so that gdb (`target remote :1234`)
+//! or another frontend can drive deet's ptrace backend. Packets that resume the inferior or
+//! change breakpoints are turned into debugger commands, so they behave just like the commands
+//! typed at the deet prompt, which are echoed on the server's console.
+//!
+//! See https://sourceware.org/gdb/onlinedocs/gdb/Remote-Protocol.html
+
+use crate::debugger::Debugger;
+use crate::debugger_command::DebuggerCommand;
+use crate::inferior::Status;
+use crate::registers::{self, fp_bytes};
+use crate::target::Target;
+use libc::{user_fpregs_struct, user_regs_struct};
+use nix::sys::signal::Signal;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+
+/// Largest packet the client may send us, which we announce in qSupported.
+const PACKET_SIZE: usize = 0x4000;
+
+/// A register in the target description: its name, size in bits and gdb type.
+type Register = (&'static str, usize, &'static str);
+
+/// The registers we describe to gdb, grouped by target description feature. `g` and `G` packets
+/// hold them in this order.
+const FEATURES: [(&str, &[Register]); 3] = [
+    (
+        "org.gnu.gdb.i386.core",
+        &[
+            ("rax", 64, "int64"),
+            ("rbx", 64, "int64"),
+            ("rcx", 64, "int64"),
+            ("rdx", 64, "int64"),
+            ("rsi", 64, "int64"),
+            ("rdi", 64, "int64"),
+            ("rbp", 64, "data_ptr"),
+            ("rsp", 64, "data_ptr"),
+            ("r8", 64, "int64"),
+            ("r9", 64, "int64"),
+            ("r10", 64, "int64"),
+            ("r11", 64, "int64"),
+            ("r12", 64, "int64"),
+            ("r13", 64, "int64"),
+            ("r14", 64, "int64"),
+            ("r15", 64, "int64"),
+            ("rip", 64, "code_ptr"),
+            ("eflags", 32, "int32"),
+            ("cs", 32, "int32"),
+            ("ss", 32, "int32"),
+            ("ds", 32, "int32"),
+            ("es", 32, "int32"),
+            ("fs", 32, "int32"),
+            ("gs", 32, "int32"),
+            ("st0", 80, "i387_ext"),
+            ("st1", 80, "i387_ext"),
+            ("st2", 80, "i387_ext"),
+            ("st3", 80, "i387_ext"),
+            ("st4", 80, "i387_ext"),
+            ("st5", 80, "i387_ext"),
+            ("st6", 80, "i387_ext"),
+            ("st7", 80, "i387_ext"),
+            ("fctrl", 32, "int"),
+            ("fstat", 32, "int"),
+            ("ftag", 32, "int"),
+            ("fiseg", 32, "int"),
+            ("fioff", 32, "int"),
+            ("foseg", 32, "int"),
+            ("fooff", 32, "int"),
+            ("fop", 32, "int"),
+        ],
+    ),
+    (
+        "org.gnu.gdb.i386.sse",
+        &[
+            ("xmm0", 128, "uint128"),
+            ("xmm1", 128, "uint128"),
+            ("xmm2", 128, "uint128"),
+            ("xmm3", 128, "uint128"),
+            ("xmm4", 128, "uint128"),
+            ("xmm5", 128, "uint128"),
+            ("xmm6", 128, "uint128"),
+            ("xmm7", 128, "uint128"),
+            ("xmm8", 128, "uint128"),
+            ("xmm9", 128, "uint128"),
+            ("xmm10", 128, "uint128"),
+            ("xmm11", 128, "uint128"),
+            ("xmm12", 128, "uint128"),
+            ("xmm13", 128, "uint128"),
+            ("xmm14", 128, "uint128"),
+            ("xmm15", 128, "uint128"),
+            ("mxcsr", 32, "int"),
+        ],
+    ),
+    ("org.gnu.gdb.i386.linux", &[("orig_rax", 64, "int")]),
+];
+
+/// The target description gdb asks for with qXfer:features:read, listing FEATURES.
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n\
+         <architecture>i386:x86-64</architecture>\n<osabi>GNU/Linux</osabi>\n",
+    );
+    for (feature, registers) in FEATURES.iter() {
+        xml += &format!("<feature name=\"{}\">\n", feature);
+        for (name, bits, kind) in registers.iter() {
+            xml += &format!("  <reg name=\"{}\" bitsize=\"{}\" type=\"{}\"/>\n", name, bits, kind);
+        }
+        xml += "</feature>\n";
+    }
+    xml + "</target>\n"
+}
+
+/// gdb numbers signals its own way, which only partly agrees with Linux.
+fn gdb_signal(sig: Signal) -> u8 {
+    match sig {
+        Signal::SIGHUP => 1,
+        Signal::SIGINT => 2,
+        Signal::SIGQUIT => 3,
+        Signal::SIGILL => 4,
+        Signal::SIGTRAP => 5,
+        Signal::SIGABRT => 6,
+        Signal::SIGFPE => 8,
+        Signal::SIGKILL => 9,
+        Signal::SIGBUS => 10,
+        Signal::SIGSEGV => 11,
+        Signal::SIGSYS => 12,
+        Signal::SIGPIPE => 13,
+        Signal::SIGALRM => 14,
+        Signal::SIGTERM => 15,
+        Signal::SIGURG => 16,
+        Signal::SIGSTOP => 17,
+        Signal::SIGTSTP => 18,
+        Signal::SIGCONT => 19,
+        Signal::SIGCHLD => 20,
+        Signal::SIGTTIN => 21,
+        Signal::SIGTTOU => 22,
+        Signal::SIGIO => 23,
+        Signal::SIGXCPU => 24,
+        Signal::SIGXFSZ => 25,
+        Signal::SIGVTALRM => 26,
+        Signal::SIGPROF => 27,
+        Signal::SIGWINCH => 28,
+        Signal::SIGUSR1 => 30,
+        Signal::SIGUSR2 => 31,
+        Signal::SIGPWR => 32,
+        // GDB_SIGNAL_UNKNOWN
+        _ => 143,
+    }
+}
+
+/// The Linux signal for gdb's signal number, or None for "no signal".
+fn linux_signal(number: u8) -> Option<Signal> {
+    Signal::iterator().find(|sig| gdb_signal(*sig) == number)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+/// Parses the "ADDR,LENGTH" that memory and breakpoint packets start with.
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (addr, len) = text.split_once(',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
+
+/// Overwrites part of the FXSAVE area that holds st0-st7 or xmm0-xmm15.
+fn set_fp_bytes(words: &mut [u32], offset: usize, bytes: &[u8]) {
+    let mut all = fp_bytes(words);
+    all[offset..offset + bytes.len()].copy_from_slice(bytes);
+    for (word, chunk) in words.iter_mut().zip(all.chunks(4)) {
+        *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+}
+
+/// FXSAVE only keeps one bit per x87 register saying whether it is in use, while gdb expects the
+/// full tag word with two bits per register. In-use registers are reported as valid.
+fn full_tag_word(abridged: u16) -> u32 {
+    (0..8)
+        .filter(|n| abridged & (1 << n) == 0)
+        .map(|n| 3 << (2 * n))
+        .sum()
+}
+
+fn abridged_tag_word(full: u32) -> u16 {
+    (0..8)
+        .filter(|n| (full >> (2 * n)) & 3 != 3)
+        .map(|n| 1 << n)
+        .sum()
+}
+
+/// Returns the little-endian bytes of a register in FEATURES.
+fn read_register(regs: &user_regs_struct, fpregs: &user_fpregs_struct, name: &str, size: usize) -> Vec<u8> {
+    if let Some(n) = name.strip_prefix("st").and_then(|n| n.parse::<usize>().ok()) {
+        return fp_bytes(&fpregs.st_space)[n * 16..n * 16 + size].to_vec();
+    }
+    if let Some(n) = name.strip_prefix("xmm").and_then(|n| n.parse::<usize>().ok()) {
+        return fp_bytes(&fpregs.xmm_space)[n * 16..n * 16 + size].to_vec();
+    }
+    let value = match name {
+        "fctrl" => fpregs.cwd as u64,
+        "fstat" => fpregs.swd as u64,
+        "ftag" => full_tag_word(fpregs.ftw) as u64,
+        "fiseg" => fpregs.rip >> 32,
+        "fioff" => fpregs.rip & 0xffff_ffff,
+        "foseg" => fpregs.rdp >> 32,
+        "fooff" => fpregs.rdp & 0xffff_ffff,
+        "fop" => fpregs.fop as u64,
+        "mxcsr" => fpregs.mxcsr as u64,
+        _ => registers::get_register(regs, name).unwrap_or_default(),
+    };
+    value.to_le_bytes()[..size].to_vec()
+}
+
+/// Sets a register in FEATURES from its little-endian bytes.
+fn write_register(regs: &mut user_regs_struct, fpregs: &mut user_fpregs_struct, name: &str, bytes: &[u8]) {
+    if let Some(n) = name.strip_prefix("st").and_then(|n| n.parse::<usize>().ok()) {
+        return set_fp_bytes(&mut fpregs.st_space, n * 16, bytes);
+    }
+    if let Some(n) = name.strip_prefix("xmm").and_then(|n| n.parse::<usize>().ok()) {
+        return set_fp_bytes(&mut fpregs.xmm_space, n * 16, bytes);
+    }
+    let mut word = [0u8; 8];
+    word[..bytes.len()].copy_from_slice(bytes);
+    let value = u64::from_le_bytes(word);
+    match name {
+        "fctrl" => fpregs.cwd = value as u16,
+        "fstat" => fpregs.swd = value as u16,
+        "ftag" => fpregs.ftw = abridged_tag_word(value as u32),
+        "fiseg" => fpregs.rip = (fpregs.rip & 0xffff_ffff) | (value << 32),
+        "fioff" => fpregs.rip = (fpregs.rip & !0xffff_ffff) | value,
+        "foseg" => fpregs.rdp = (fpregs.rdp & 0xffff_ffff) | (value << 32),
+        "fooff" => fpregs.rdp = (fpregs.rdp & !0xffff_ffff) | value,
+        "fop" => fpregs.fop = value as u16,
+        "mxcsr" => fpregs.mxcsr = value as u32,
+        _ => {
+            registers::set_register(regs, name, value);
+        }
+    }
+}
+
+/// Sizes in bytes of the registers in FEATURES, in order, with their names.
+fn register_layout() -> impl Iterator<Item = (&'static str, usize)> {
+    FEATURES
+        .iter()
+        .flat_map(|(_, registers)| registers.iter().map(|(name, bits, _)| (*name, bits / 8)))
+}
+
+/// Returns the part of a document that a qXfer read asks for with "OFFSET,LENGTH": "m" followed
+/// by the data if there is more to come, or "l" if it is the last part.
+fn xfer_chunk(document: &[u8], request: &str) -> Vec<u8> {
+    let (offset, len) = match parse_range(request) {
+        Some(range) => range,
+        None => return b"E00".to_vec(),
+    };
+    let start = offset.min(document.len());
+    let end = start.saturating_add(len).min(document.len());
+    let mut reply = vec![if end < document.len() { b'm' } else { b'l' }];
+    // Only the characters that frame packets need escaping in binary data
+    for byte in &document[start..end] {
+        match byte {
+            b'#' | b'$' | b'}' | b'*' => reply.extend([b'}', byte ^ 0x20]),
+            _ => reply.push(*byte),
+        }
+    }
+    reply
+}
+
+/// The checksum that follows a packet's data: the sum of its bytes modulo 256.
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// A connection to a gdb client, normally over TCP.
+struct Connection<R: BufRead, W: Write> {
+    reader: R,
+    writer: W,
+    /// Whether packets are acknowledged with "+", which the client can turn off with
+    /// QStartNoAckMode
+    ack: bool,
+}
+
+impl<R: BufRead, W: Write> Connection<R, W> {
+    /// Waits for the next packet, acknowledging it. Returns None once the client disconnects.
+    fn read_packet(&mut self) -> Option<String> {
+        loop {
+            // Skip acknowledgements, and interrupts, since the inferior is stopped whenever we
+            // are reading packets
+            let mut skipped = Vec::new();
+            self.reader.read_until(b'$', &mut skipped).ok()?;
+            if skipped.last() != Some(&b'$') {
+                return None;
+            }
+            let mut data = Vec::new();
+            self.reader.read_until(b'#', &mut data).ok()?;
+            if data.pop() != Some(b'#') {
+                return None;
+            }
+            let mut sum = [0u8; 2];
+            self.reader.read_exact(&mut sum).ok()?;
+            let expected = std::str::from_utf8(&sum).ok().and_then(|text| u8::from_str_radix(text, 16).ok());
+            if expected != Some(checksum(&data)) {
+                if self.ack {
+                    self.writer.write_all(b"-").ok()?;
+                }
+                continue;
+            }
+            if self.ack {
+                self.writer.write_all(b"+").ok()?;
+            }
+            return Some(String::from_utf8_lossy(&data).into_owned());
+        }
+    }
+
+    /// Sends a packet. When acknowledgements are on, the client's "+" is read along with the next
+    /// packet.
+    fn write_packet(&mut self, data: &[u8]) -> Option<()> {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(data);
+        packet.extend_from_slice(format!("#{:02x}", checksum(data)).as_bytes());
+        self.writer.write_all(&packet).ok()
+    }
+}
+
+/// What to do after handling a packet.
+enum Reply {
+    Send(Vec<u8>),
+    /// Send the reply, if any, and end the session
+    Close(Option<Vec<u8>>),
+}
+
+/// Waits for a gdb client to connect at address (e.g. ":1234" or "0.0.0.0:1234") and lets it
+/// drive the debugger's inferior until it kills or detaches from it, or disconnects.
+pub fn serve(debugger: &mut Debugger, address: &str) -> Result<(), String> {
+    // Like gdbserver, ":PORT" means this machine, but only local clients are let in
+    let address = match address.strip_prefix(':') {
+        Some(port) => format!("127.0.0.1:{}", port),
+        None => address.to_string(),
+    };
+    let listener = TcpListener::bind(&address).map_err(|err| format!("Can't bind address {}: {}", address, err))?;
+    if let Ok(local) = listener.local_addr() {
+        println!("Listening on port {}", local.port());
+    }
+    let (stream, peer) = listener.accept().map_err(|err| format!("Accept failed: {}", err))?;
+    println!("Remote debugging from host {}, port {}", peer.ip(), peer.port());
+    let _ = stream.set_nodelay(true);
+    let writer = stream.try_clone().map_err(|err| err.to_string())?;
+    let mut connection = Connection {
+        reader: BufReader::new(stream),
+        writer,
+        ack: true,
+    };
+    while let Some(packet) = connection.read_packet() {
+        match handle_packet(debugger, &packet) {
+            Reply::Send(reply) => {
+                if connection.write_packet(&reply).is_none() {
+                    break;
+                }
+                if packet == "QStartNoAckMode" {
+                    connection.ack = false;
+                }
+            }
+            Reply::Close(reply) => {
+                if let Some(reply) = reply {
+                    let _ = connection.write_packet(&reply);
+                }
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The reply to `?` and to the packets that resume the inferior: why it last stopped, or how it
+/// ended.
+fn stop_reply(debugger: &mut Debugger) -> String {
+    match debugger.last_status() {
+        Some(Status::Exited(code)) => format!("W{:02x}", code as u8),
+        Some(Status::Signaled(sig)) => format!("X{:02x}", gdb_signal(sig)),
+        status => match debugger.inferior_mut() {
+            Some(inferior) => {
+                let sig = match status {
+                    Some(Status::Stopped(sig, _)) => sig,
+                    _ => Signal::SIGTRAP,
+                };
+                format!("T{:02x}thread:{:x};", gdb_signal(sig), inferior.tid().as_raw())
+            }
+            None => "W00".to_string(),
+        },
+    }
+}
+
+/// Carries out a packet that resumes the inferior: `c`, `C`, `s` or `S`, optionally followed by a
+/// signal and an address to resume at.
+fn resume(debugger: &mut Debugger, action: char, args: &str) -> String {
+    let inferior = match debugger.inferior_mut() {
+        Some(inferior) => inferior,
+        None => return "E01".to_string(),
+    };
+    let (sig, addr) = match action {
+        'C' | 'S' => match args.split_once(';') {
+            Some((sig, addr)) => (sig, addr),
+            None => (args, ""),
+        },
+        _ => ("", args),
+    };
+    if let Some(addr) = parse_hex(addr) {
+        if inferior.set_register("rip", addr as u64).is_err() {
+            return "E01".to_string();
+        }
+    }
+    let sig = u8::from_str_radix(sig, 16).ok().and_then(linux_signal);
+    match action {
+        'c' => debugger.execute(DebuggerCommand::Continue),
+        'C' => {
+            let name = sig.map_or("0", |sig| sig.as_str());
+            debugger.execute(DebuggerCommand::Signal(name.to_string()));
+        }
+        _ => {
+            inferior.set_signal(sig);
+            debugger.execute(DebuggerCommand::StepInstruction);
+        }
+    }
+    stop_reply(debugger)
+}
+
+/// Handles a packet from the client.
+fn handle_packet(debugger: &mut Debugger, packet: &str) -> Reply {
+    let command = packet.chars().next().unwrap_or_default();
+    let args = packet.get(1..).unwrap_or_default();
+    let reply = match command {
+        '?' => stop_reply(debugger),
+        'c' | 'C' | 's' | 'S' => resume(debugger, command, args),
+        'g' => match debugger.inferior_mut() {
+            Some(inferior) => match (inferior.innermost_registers(), inferior.fp_registers()) {
+                (Ok(regs), Ok(fpregs)) => register_layout()
+                    .map(|(name, size)| to_hex(&read_register(&regs, &fpregs, name, size)))
+                    .collect(),
+                _ => "E01".to_string(),
+            },
+            None => "E01".to_string(),
+        },
+        'G' => match (debugger.inferior_mut(), from_hex(args)) {
+            (Some(inferior), Some(bytes)) => match (inferior.innermost_registers(), inferior.fp_registers()) {
+                (Ok(mut regs), Ok(mut fpregs)) => {
+                    let mut offset = 0;
+                    for (name, size) in register_layout() {
+                        if let Some(bytes) = bytes.get(offset..offset + size) {
+                            write_register(&mut regs, &mut fpregs, name, bytes);
+                        }
+                        offset += size;
+                    }
+                    match (inferior.set_registers(regs), inferior.set_fp_registers(fpregs)) {
+                        (Ok(()), Ok(())) => "OK".to_string(),
+                        _ => "E01".to_string(),
+                    }
+                }
+                _ => "E01".to_string(),
+            },
+            _ => "E01".to_string(),
+        },
+        'm' => match (debugger.inferior_mut(), parse_range(args)) {
+            // gdb's own breakpoints are ours, so it mustn't see their 0xcc bytes
+            (Some(inferior), Some((addr, len))) => match inferior.read_code(addr, len) {
+                Ok(bytes) if !bytes.is_empty() || len == 0 => to_hex(&bytes),
+                _ => "E01".to_string(),
+            },
+            _ => "E01".to_string(),
+        },
+        'M' => {
+            let write = args.split_once(':').and_then(|(range, data)| Some((parse_range(range)?, from_hex(data)?)));
+            match (debugger.inferior_mut(), write) {
+                (Some(inferior), Some(((addr, len), bytes))) if bytes.len() == len => {
+                    match inferior.write_memory(addr, &bytes) {
+                        Ok(()) => "OK".to_string(),
+                        Err(_) => "E01".to_string(),
+                    }
+                }
+                _ => "E01".to_string(),
+            }
+        }
+        'Z' | 'z' if args.starts_with("0,") => {
+            let addr = match args[2..].split_once(',').and_then(|(addr, _)| parse_hex(addr)) {
+                Some(addr) => addr,
+                None => return Reply::Send(b"E01".to_vec()),
+            };
+            match (command, debugger.breakpoint_at(addr)) {
+                ('Z', None) => debugger.execute(DebuggerCommand::Break(format!("*{:#x}", addr), None)),
+                ('z', Some(id)) => debugger.execute(DebuggerCommand::Delete(vec![id])),
+                _ => {}
+            }
+            match (command, debugger.breakpoint_at(addr)) {
+                ('Z', None) => "E01".to_string(),
+                _ => "OK".to_string(),
+            }
+        }
+        // Only one thread is reported, so it is always the one selected
+        'H' | 'T' => "OK".to_string(),
+        // The inferior is killed once the session ends
+        'k' => return Reply::Close(None),
+        'v' if packet.starts_with("vKill") => return Reply::Close(Some(b"OK".to_vec())),
+        'D' => {
+            debugger.execute(DebuggerCommand::Detach);
+            return Reply::Close(Some(b"OK".to_vec()));
+        }
+        _ => return Reply::Send(handle_query(debugger, packet)),
+    };
+    Reply::Send(reply.into_bytes())
+}
+
+/// Handles the packets with names: queries, settings and vCont. Unsupported packets get an empty
+/// reply. The qXfer replies hold binary data, so replies are bytes rather than text.
+fn handle_query(debugger: &mut Debugger, packet: &str) -> Vec<u8> {
+    if packet.starts_with("qSupported") {
+        return format!(
+            "PacketSize={:x};qXfer:features:read+;qXfer:auxv:read+;QStartNoAckMode+;vContSupported+",
+            PACKET_SIZE
+        )
+        .into_bytes();
+    }
+    if let Some(request) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+        return xfer_chunk(target_xml().as_bytes(), request);
+    }
+    if let Some(request) = packet.strip_prefix("qXfer:auxv:read::") {
+        // Tells gdb where a position-independent executable was loaded
+        let auxv = debugger
+            .inferior_mut()
+            .and_then(|inferior| std::fs::read(format!("/proc/{}/auxv", inferior.pid())).ok());
+        return match auxv {
+            Some(auxv) => xfer_chunk(&auxv, request),
+            None => b"E01".to_vec(),
+        };
+    }
+    if let Some(actions) = packet.strip_prefix("vCont") {
+        if actions == "?" {
+            return b"vCont;c;C;s;S".to_vec();
+        }
+        // Only one thread is reported, so the first action is the one for it
+        let action = actions.trim_start_matches(';').split(';').next().unwrap_or_default();
+        let action = action.split(':').next().unwrap_or_default();
+        let command = action.chars().next().unwrap_or_default();
+        return match command {
+            'c' | 'C' | 's' | 'S' => resume(debugger, command, &action[1..]).into_bytes(),
+            _ => b"E01".to_vec(),
+        };
+    }
+    let tid = debugger.inferior_mut().map(|inferior| inferior.tid().as_raw());
+    let reply = match (packet, tid) {
+        ("QStartNoAckMode", _) => "OK".to_string(),
+        // We started the process, so quitting kills it
+        ("qAttached", _) => "0".to_string(),
+        ("qC", Some(tid)) => format!("QC{:x}", tid),
+        ("qfThreadInfo", Some(tid)) => format!("m{:x}", tid),
+        ("qsThreadInfo", _) => "l".to_string(),
+        _ => String::new(),
+    };
+    reply.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn connection(input: &[u8]) -> Connection<Cursor<Vec<u8>>, Vec<u8>> {
+        Connection {
+            reader: Cursor::new(input.to_vec()),
+            writer: Vec::new(),
+            ack: true,
+        }
+    }
+
+    #[test]
+    fn hex_decoding() {
+        assert_eq!(from_hex("00ff7f"), Some(vec![0x00, 0xff, 0x7f]));
+        assert_eq!(from_hex(""), Some(vec![]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+        assert_eq!(to_hex(&[0x00, 0xab]), "00ab");
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("401136,8"), Some((0x401136, 8)));
+        assert_eq!(parse_range("0,fff"), Some((0, 0xfff)));
+        assert_eq!(parse_range("401136"), None);
+        assert_eq!(parse_range("x,1"), None);
+        assert_eq!(parse_range("1,"), None);
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(checksum(b""), 0);
+        assert_eq!(checksum(b"OK"), 0x9a);
+        // Sums wrap around
+        assert_eq!(checksum(&[0xff, 0x02]), 0x01);
+    }
+
+    #[test]
+    fn reads_packets_and_acknowledges_them() {
+        let mut conn = connection(b"+$g#67$m0,1#fa");
+        assert_eq!(conn.read_packet().as_deref(), Some("g"));
+        assert_eq!(conn.read_packet().as_deref(), Some("m0,1"));
+        assert_eq!(conn.read_packet(), None);
+        assert_eq!(conn.writer, b"++");
+    }
+
+    #[test]
+    fn rejects_bad_checksums() {
+        let mut conn = connection(b"$g#00$g#67");
+        assert_eq!(conn.read_packet().as_deref(), Some("g"));
+        assert_eq!(conn.writer, b"-+");
+
+        let mut conn = connection(b"$g#00$g#67");
+        conn.ack = false;
+        assert_eq!(conn.read_packet().as_deref(), Some("g"));
+        assert!(conn.writer.is_empty());
+    }
+
+    #[test]
+    fn stops_at_truncated_packets() {
+        assert_eq!(connection(b"$g").read_packet(), None);
+        assert_eq!(connection(b"$g#6").read_packet(), None);
+        assert_eq!(connection(b"+++").read_packet(), None);
+    }
+
+    #[test]
+    fn writes_binary_packets() {
+        let mut conn = connection(b"");
+        conn.write_packet(b"OK").unwrap();
+        assert_eq!(conn.writer, b"$OK#9a");
+
+        // Bytes above 0x7f go out as they are, not UTF-8 encoded
+        let mut conn = connection(b"");
+        conn.write_packet(&[0x80, 0xfc]).unwrap();
+        assert_eq!(conn.writer, b"$\x80\xfc#7c");
+    }
+
+    #[test]
+    fn xfer_chunks() {
+        let document = b"abcdef";
+        assert_eq!(xfer_chunk(document, "0,4"), b"mabcd");
+        assert_eq!(xfer_chunk(document, "4,4"), b"lef");
+        assert_eq!(xfer_chunk(document, "0,6"), b"labcdef");
+        assert_eq!(xfer_chunk(document, "10,4"), b"l");
+        assert_eq!(xfer_chunk(document, "bad"), b"E00");
+        // A huge length mustn't overflow
+        assert_eq!(xfer_chunk(document, "2,ffffffffffffffff"), b"lcdef");
+    }
+
+    #[test]
+    fn xfer_escapes_binary_data() {
+        assert_eq!(xfer_chunk(b"#$}*", "0,10"), b"l}\x03}\x04}]}\x0a");
+        assert_eq!(xfer_chunk(&[0x80, 0xfc, 0xf7, 0xff, 0x7f], "0,10"), b"l\x80\xfc\xf7\xff\x7f");
+    }
+
+    #[test]
+    fn tag_words_round_trip() {
+        // All registers empty, and all in use
+        assert_eq!(full_tag_word(0x00), 0xffff);
+        assert_eq!(full_tag_word(0xff), 0x0000);
+        // st0 in use, the rest empty
+        assert_eq!(full_tag_word(0x01), 0xfffc);
+        for abridged in 0..=0xffu16 {
+            assert_eq!(abridged_tag_word(full_tag_word(abridged)), abridged);
+        }
+        // Zero and special registers still count as in use
+        assert_eq!(abridged_tag_word(0xfff9), 0x03);
+    }
+}
@@ -5,18 +5,25 @@
 //!
 //! This code is a huge mess. Please don't read it unless you're trying to do an extension :)
 
-use gimli;
 use gimli::{UnitOffset, UnitSectionOffset};
 use object::{Object, ObjectSection};
 use std::borrow;
 //use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use crate::dwarf_data::{
+    CallSite, DwarfExpression, Encoding, File, Function, Language, Line, Location, Member, Type,
+    TypeKind, Variable, Variant,
+};
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Write;
 use std::{io, path};
 
-pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<Vec<File>, Error> {
+/// Loads the compilation units of an executable, along with every type they declare keyed by
+/// section offset.
+pub fn load_file(
+    object: &object::File,
+    endian: gimli::RunTimeEndian,
+) -> Result<(Vec<File>, HashMap<usize, Type>), Error> {
     // Load a section and return as `Cow<[u8]>`.
     let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
         match object.section_by_name(id.name()) {
@@ -37,7 +44,7 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
     let borrow_section: &dyn for<'a> Fn(
         &'a borrow::Cow<[u8]>,
     ) -> gimli::EndianSlice<'a, gimli::RunTimeEndian> =
-        &|section| gimli::EndianSlice::new(&*section, endian);
+        &|section| gimli::EndianSlice::new(section, endian);
 
     // Create `EndianSlice`s for all of the sections.
     let dwarf = dwarf_cow.borrow(&borrow_section);
@@ -51,12 +58,25 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
     let mut iter = dwarf.units();
     while let Some(header) = iter.next()? {
         let unit = dwarf.unit(header)?;
+        let language = unit_language(&unit);
+
+        // Types can be referenced before they are declared, so collect them all up front
+        load_types(&unit, &dwarf, language, &mut offset_to_type)?;
 
         // Iterate over the Debugging Information Entries (DIEs) in the unit.
         let mut depth = 0;
+        // Depth of the function inlined into the current one that we're inside of, if any. The
+        // variables of inlined functions are left out, since they would be mistaken for the
+        // variables of the function they were inlined into.
+        let mut inlined_depth = None;
+        // The register the current function's DW_OP_fbreg locations are relative to, if any
+        let mut frame_base = None;
         let mut entries = unit.entries();
         while let Some((delta_depth, entry)) = entries.next_dfs()? {
             depth += delta_depth;
+            if inlined_depth.is_some_and(|inlined_depth| depth <= inlined_depth) {
+                inlined_depth = None;
+            }
             // Update the offset_to_type mapping for types
             // Update the variable list for formal params/variables
             match entry.tag() {
@@ -70,6 +90,12 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                     } else {
                         "<unknown>".to_string()
                     };
+                    // rustc names units like "src/main.rs/@/crate.1a2b3c-cgu.0", after the crate
+                    // root and the codegen unit, which the line table doesn't mention
+                    let name = match language {
+                        Language::Rust => name.split("/@/").next().unwrap_or_default().to_string(),
+                        Language::C => name,
+                    };
                     compilation_units.push(File {
                         name,
                         global_variables: Vec::new(),
@@ -77,35 +103,9 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                         lines: Vec::new(),
                     });
                 }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
-                        }
-                    } else {
-                        // TODO: report error?
-                        0
-                    };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
-                }
                 gimli::DW_TAG_subprogram => {
                     let mut func: Function = Default::default();
+                    frame_base = None;
                     let mut attrs = entry.attrs();
                     while let Some(attr) = attrs.next()? {
                         let val = get_attr_value(&attr, &unit, &dwarf);
@@ -132,15 +132,89 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                                     func.line_number = line_number.try_into().unwrap();
                                 }
                             }
+                            gimli::DW_AT_type => {
+                                if let Ok(DebugValue::Size(offset)) = val {
+                                    func.return_type = offset_to_type.get(&offset).cloned();
+                                }
+                            }
+                            gimli::DW_AT_frame_base => frame_base = frame_base_register(&attr, &unit),
                             _ => {}
                         }
                     }
+                    // Out-of-line copies of functions that are also inlined refer to the abstract
+                    // instance of the function for their name and return type
+                    if func.name.is_empty() {
+                        if let Some(origin) = origin(entry, &unit, &dwarf) {
+                            let declaration = declaration(origin, &unit, &dwarf);
+                            func.name = declaration.name.unwrap_or_default();
+                            func.line_number = declaration.line_number.unwrap_or(0) as usize;
+                            func.return_type = declaration
+                                .type_offset
+                                .and_then(|offset| offset_to_type.get(&offset).cloned());
+                        }
+                    }
                     compilation_units.last_mut().unwrap().functions.push(func);
                 }
+                gimli::DW_TAG_inlined_subroutine if inlined_depth.is_none() => {
+                    inlined_depth = Some(depth);
+                }
+                gimli::DW_TAG_call_site | gimli::DW_TAG_GNU_call_site => {
+                    // DWARF 5 gives the return address as DW_AT_call_return_pc, the GNU extension
+                    // to DWARF 4 as DW_AT_low_pc
+                    let return_addr = [gimli::DW_AT_call_return_pc, gimli::DW_AT_low_pc]
+                        .iter()
+                        .find_map(|name| match entry.attr_value(*name) {
+                            Ok(Some(gimli::AttributeValue::Addr(addr))) => Some(addr as usize),
+                            _ => None,
+                        });
+                    if let (Some(return_addr), Some(func)) = (
+                        return_addr,
+                        compilation_units.last_mut().unwrap().functions.last_mut(),
+                    ) {
+                        func.call_sites.push(CallSite {
+                            return_addr,
+                            parameters: Vec::new(),
+                        });
+                    }
+                }
+                gimli::DW_TAG_call_site_parameter | gimli::DW_TAG_GNU_call_site_parameter => {
+                    let register = match entry.attr_value(gimli::DW_AT_location)? {
+                        Some(gimli::AttributeValue::Exprloc(expr)) => {
+                            match gimli::Operation::parse(&mut expr.0.clone(), unit.encoding()) {
+                                Ok(gimli::Operation::Register { register }) => Some(register.0),
+                                _ => None,
+                            }
+                        }
+                        _ => None,
+                    };
+                    let value = [gimli::DW_AT_call_value, gimli::DW_AT_GNU_call_site_value]
+                        .iter()
+                        .find_map(|name| match entry.attr_value(*name) {
+                            Ok(Some(gimli::AttributeValue::Exprloc(expr))) => {
+                                dwarf_expression(&expr, &unit)
+                            }
+                            _ => None,
+                        });
+                    let call_site = compilation_units
+                        .last_mut()
+                        .unwrap()
+                        .functions
+                        .last_mut()
+                        .and_then(|func| func.call_sites.last_mut());
+                    if let (Some(register), Some(value), Some(call_site)) = (register, value, call_site) {
+                        call_site.parameters.push((register, value));
+                    }
+                }
                 gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
+                    // Declarations of variables defined elsewhere, like extern globals, have no
+                    // location of their own
+                    if inlined_depth.is_some() || entry.attr(gimli::DW_AT_declaration)?.is_some() {
+                        continue;
+                    }
                     let mut name = String::new();
                     let mut entity_type: Option<Type> = None;
                     let mut location: Option<Location> = None;
+                    let mut constant: Option<Vec<u8>> = None;
                     let mut line_number = 0;
                     let mut attrs = entry.attrs();
                     while let Some(attr) = attrs.next()? {
@@ -154,16 +228,17 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                             }
                             gimli::DW_AT_type => {
                                 if let Ok(DebugValue::Size(offset)) = val {
-                                    if let Some(dtype) = offset_to_type.get(&offset).clone() {
+                                    if let Some(dtype) = offset_to_type.get(&offset) {
                                         entity_type = Some(dtype.clone());
                                     }
                                 }
                             }
                             gimli::DW_AT_location => {
-                                if let Some(loc) = get_location(&attr, &unit) {
+                                if let Some(loc) = get_location(&attr, &unit, &dwarf, frame_base) {
                                     location = Some(loc);
                                 }
                             }
+                            gimli::DW_AT_const_value => constant = get_constant(&attr),
                             gimli::DW_AT_decl_line => {
                                 if let Ok(DebugValue::Uint(num)) = val {
                                     line_number = num;
@@ -172,11 +247,32 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                             _ => {}
                         }
                     }
-                    if entity_type.is_some() && location.is_some() {
+                    // The concrete instance of a variable in an out-of-line copy of an inlined
+                    // function, or the definition of a global that was declared earlier, gets
+                    // its name and type from the DIE it refers to
+                    if let Some(origin) = origin(entry, &unit, &dwarf) {
+                        let declaration = declaration(origin, &unit, &dwarf);
+                        if name.is_empty() {
+                            name = declaration.name.unwrap_or_default();
+                        }
+                        if entity_type.is_none() {
+                            entity_type = declaration
+                                .type_offset
+                                .and_then(|offset| offset_to_type.get(&offset).cloned());
+                        }
+                        if line_number == 0 {
+                            line_number = declaration.line_number.unwrap_or(0);
+                        }
+                    }
+                    let location = location.or_else(|| constant.map(Location::Constant));
+                    if name.is_empty() {
+                        continue;
+                    }
+                    if let (Some(entity_type), location) = (entity_type, location.unwrap_or(Location::OptimizedOut)) {
                         let var = Variable {
                             name,
-                            entity_type: entity_type.unwrap(),
-                            location: location.unwrap(),
+                            entity_type,
+                            location,
                             line_number: line_number.try_into().unwrap(),
                         };
                         if depth == 1 {
@@ -204,11 +300,17 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
         }
 
         // Get line numbers
+        let comp_dir = unit
+            .comp_dir
+            .as_ref()
+            .map(|dir| path::PathBuf::from(dir.to_string_lossy().as_ref()));
         if let Some(program) = unit.line_program.clone() {
             // Iterate over the line program rows.
             let mut rows = program.rows();
             while let Some((header, row)) = rows.next_row()? {
-                if !row.end_sequence() {
+                // Rows that aren't statement boundaries are in the middle of a line's code, and
+                // not places to stop at
+                if !row.end_sequence() && row.is_stmt() {
                     // Determine the path. Real applications should cache this for performance.
                     let mut path = path::PathBuf::new();
                     if let Some(file) = row.file(header) {
@@ -223,10 +325,14 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                         );
                     }
 
-                    // Get the File
-                    let file = compilation_units
-                        .iter_mut()
-                        .find(|f| f.name == path.as_os_str().to_str().unwrap());
+                    // Get the File. Units are usually named the way the line table names their
+                    // source file, but rustc names them relative to the compilation directory
+                    // while the line table has the full path.
+                    let relative = comp_dir.as_ref().and_then(|dir| path.strip_prefix(dir).ok());
+                    let file = compilation_units.iter_mut().find(|f| {
+                        f.name == path.as_os_str().to_str().unwrap()
+                            || relative.is_some_and(|relative| f.name == relative.to_string_lossy())
+                    });
 
                     // Determine line/column. DWARF line/column is never 0, so we use that
                     // but other applications may want to display this differently.
@@ -247,19 +353,535 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
             }
         }
     }
-    Ok(compilation_units)
+    Ok((compilation_units, offset_to_type))
+}
+
+/// A type as it is described by one DIE, before the types it refers to are resolved. References
+/// to other types are section offsets.
+enum RawType {
+    Base {
+        name: String,
+        size: usize,
+        encoding: Encoding,
+    },
+    Pointer {
+        /// Rust names its pointer types, e.g. "&str" or "alloc::boxed::Box<i32>"
+        name: Option<String>,
+        target: Option<usize>,
+    },
+    Array {
+        element: Option<usize>,
+        /// Number of elements in each dimension, outermost first
+        counts: Vec<usize>,
+    },
+    Struct {
+        name: Option<String>,
+        size: usize,
+        members: Vec<RawMember>,
+        union: bool,
+        /// The variants of a Rust enum
+        variant_part: Option<RawVariantPart>,
+        /// The type parameters of a generic Rust type
+        template_params: Vec<(String, Option<usize>)>,
+    },
+    Enum {
+        name: Option<String>,
+        size: usize,
+        values: Vec<(String, i64)>,
+    },
+    Typedef {
+        name: String,
+        target: Option<usize>,
+    },
+    /// const, volatile or restrict
+    Qualified {
+        qualifier: &'static str,
+        target: Option<usize>,
+    },
+    Function {
+        return_type: Option<usize>,
+    },
+}
+
+struct RawMember {
+    name: String,
+    offset: usize,
+    bits: Option<(usize, usize)>,
+    member_type: Option<usize>,
+}
+
+/// A DW_TAG_variant_part: the discriminant member, which DW_AT_discr refers to, and the member of
+/// each variant along with its DW_AT_discr_value.
+#[derive(Default)]
+struct RawVariantPart {
+    discr: Option<usize>,
+    discriminant: Option<RawMember>,
+    variants: Vec<(Option<u64>, RawMember)>,
+}
+
+/// Returns the language of a unit, from the DW_AT_language of its root DIE.
+fn unit_language<R: Reader>(unit: &gimli::Unit<R>) -> Language {
+    let mut entries = unit.entries();
+    match entries.next_dfs() {
+        Ok(Some((_, entry))) => match entry.attr_value(gimli::DW_AT_language) {
+            Ok(Some(gimli::AttributeValue::Language(gimli::DW_LANG_Rust))) => Language::Rust,
+            _ => Language::C,
+        },
+        _ => Language::C,
+    }
+}
+
+/// Records the types declared in a unit into offset_to_type, keyed by section offset.
+fn load_types<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    language: Language,
+    offset_to_type: &mut HashMap<usize, Type>,
+) -> Result<(), Error> {
+    let mut raw_types: HashMap<usize, RawType> = HashMap::new();
+    // Types whose children (members, enumerators and array bounds) we may be reading, along with
+    // their depth in the tree of DIEs
+    let mut parents: Vec<(isize, usize)> = Vec::new();
+    // The Rust modules and types we are inside of, by depth, whose path is part of the name of
+    // the types declared in them
+    let mut scopes: Vec<(isize, String)> = Vec::new();
+    // Depth of the DW_TAG_variant_part we are inside of, and the depth and DW_AT_discr_value of
+    // the DW_TAG_variant
+    let mut variant_part: Option<isize> = None;
+    let mut variant: Option<(isize, Option<u64>)> = None;
+
+    let mut depth = 0;
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        while parents.last().is_some_and(|(parent_depth, _)| *parent_depth >= depth) {
+            parents.pop();
+        }
+        while scopes.last().is_some_and(|(scope_depth, _)| *scope_depth >= depth) {
+            scopes.pop();
+        }
+        if variant_part.is_some_and(|part_depth| part_depth >= depth) {
+            variant_part = None;
+        }
+        if variant.is_some_and(|(variant_depth, _)| variant_depth >= depth) {
+            variant = None;
+        }
+        let parent = parents.last().and_then(|(_, offset)| raw_types.get_mut(offset));
+        let offset = section_offset(entry.offset(), unit);
+        let name = attr_string(entry, unit, dwarf, gimli::DW_AT_name);
+        let size = attr_udata(entry, gimli::DW_AT_byte_size).unwrap_or(0) as usize;
+        let target = attr_ref(entry, unit, dwarf, gimli::DW_AT_type);
+        // Rust types are named by their path, like "alloc::string::String"
+        let path = match (&name, scopes.last()) {
+            (Some(name), Some((_, scope))) => Some(format!("{}::{}", scope, name)),
+            _ => name.clone(),
+        };
+        let raw = match entry.tag() {
+            gimli::DW_TAG_namespace => {
+                if let Some(path) = path {
+                    scopes.push((depth, path));
+                }
+                continue;
+            }
+            gimli::DW_TAG_base_type => {
+                let encoding = match entry.attr_value(gimli::DW_AT_encoding) {
+                    Ok(Some(gimli::AttributeValue::Encoding(encoding))) => match encoding {
+                        gimli::DW_ATE_unsigned => Encoding::Unsigned,
+                        gimli::DW_ATE_signed_char => Encoding::SignedChar,
+                        gimli::DW_ATE_unsigned_char => Encoding::UnsignedChar,
+                        gimli::DW_ATE_boolean => Encoding::Bool,
+                        gimli::DW_ATE_float => Encoding::Float,
+                        _ => Encoding::Signed,
+                    },
+                    _ => Encoding::Signed,
+                };
+                RawType::Base {
+                    name: name.unwrap_or_else(|| "<unknown>".to_string()),
+                    size,
+                    encoding,
+                }
+            }
+            gimli::DW_TAG_pointer_type => RawType::Pointer { name, target },
+            gimli::DW_TAG_array_type => RawType::Array {
+                element: target,
+                counts: Vec::new(),
+            },
+            gimli::DW_TAG_structure_type | gimli::DW_TAG_union_type => RawType::Struct {
+                name: if language == Language::Rust { path } else { name },
+                size,
+                members: Vec::new(),
+                union: entry.tag() == gimli::DW_TAG_union_type,
+                variant_part: None,
+                template_params: Vec::new(),
+            },
+            gimli::DW_TAG_enumeration_type => RawType::Enum {
+                name: if language == Language::Rust { path } else { name },
+                size,
+                values: Vec::new(),
+            },
+            gimli::DW_TAG_typedef => RawType::Typedef {
+                name: name.unwrap_or_default(),
+                target,
+            },
+            gimli::DW_TAG_const_type => RawType::Qualified {
+                qualifier: "const",
+                target,
+            },
+            gimli::DW_TAG_volatile_type => RawType::Qualified {
+                qualifier: "volatile",
+                target,
+            },
+            gimli::DW_TAG_restrict_type => RawType::Qualified {
+                qualifier: "restrict",
+                target,
+            },
+            gimli::DW_TAG_subroutine_type => RawType::Function {
+                return_type: target,
+            },
+            gimli::DW_TAG_member => {
+                if let Some(RawType::Struct {
+                    members,
+                    variant_part: part,
+                    ..
+                }) = parent
+                {
+                    let location = attr_udata(entry, gimli::DW_AT_data_member_location);
+                    let bit_size = attr_udata(entry, gimli::DW_AT_bit_size);
+                    let data_bit_offset = attr_udata(entry, gimli::DW_AT_data_bit_offset);
+                    let (member_offset, bits) = match (bit_size, data_bit_offset) {
+                        (Some(bit_size), Some(bit_offset)) => (
+                            (bit_offset / 8) as usize,
+                            Some(((bit_offset % 8) as usize, bit_size as usize)),
+                        ),
+                        // DWARF 2 style bit fields count their offset from the most significant
+                        // bit of a storage unit of DW_AT_byte_size bytes
+                        (Some(bit_size), None) => {
+                            let bit_offset =
+                                attr_udata(entry, gimli::DW_AT_bit_offset).unwrap_or(0);
+                            let from_lsb = (size as u64 * 8).saturating_sub(bit_offset + bit_size);
+                            (
+                                location.unwrap_or(0) as usize,
+                                Some((from_lsb as usize, bit_size as usize)),
+                            )
+                        }
+                        _ => (location.unwrap_or(0) as usize, None),
+                    };
+                    let member = RawMember {
+                        name: name.unwrap_or_default(),
+                        offset: member_offset,
+                        bits,
+                        member_type: target,
+                    };
+                    match (part.as_mut(), variant_part, variant) {
+                        (Some(part), Some(_), Some((_, discr_value))) => {
+                            part.variants.push((discr_value, member))
+                        }
+                        (Some(part), Some(_), None) if part.discr == Some(offset) => {
+                            part.discriminant = Some(member)
+                        }
+                        _ => members.push(member),
+                    }
+                }
+                continue;
+            }
+            gimli::DW_TAG_variant_part => {
+                if let Some(RawType::Struct {
+                    variant_part: part, ..
+                }) = parent
+                {
+                    variant_part = Some(depth);
+                    *part = Some(RawVariantPart {
+                        discr: attr_ref(entry, unit, dwarf, gimli::DW_AT_discr),
+                        ..RawVariantPart::default()
+                    });
+                }
+                continue;
+            }
+            gimli::DW_TAG_variant => {
+                if variant_part.is_some() {
+                    let discr_value = match entry.attr_value(gimli::DW_AT_discr_value) {
+                        Ok(Some(gimli::AttributeValue::Sdata(value))) => Some(value as u64),
+                        Ok(Some(value)) => value.udata_value(),
+                        _ => None,
+                    };
+                    variant = Some((depth, discr_value));
+                }
+                continue;
+            }
+            gimli::DW_TAG_template_type_parameter => {
+                if let Some(RawType::Struct {
+                    template_params, ..
+                }) = parent
+                {
+                    template_params.push((name.unwrap_or_default(), target));
+                }
+                continue;
+            }
+            gimli::DW_TAG_enumerator => {
+                if let Some(RawType::Enum { values, .. }) = parent {
+                    let value = match entry.attr_value(gimli::DW_AT_const_value) {
+                        Ok(Some(gimli::AttributeValue::Sdata(value))) => value,
+                        Ok(Some(value)) => value.udata_value().unwrap_or(0) as i64,
+                        _ => 0,
+                    };
+                    values.push((name.unwrap_or_default(), value));
+                }
+                continue;
+            }
+            gimli::DW_TAG_subrange_type => {
+                if let Some(RawType::Array { counts, .. }) = parent {
+                    let count = attr_udata(entry, gimli::DW_AT_count).or_else(|| {
+                        attr_udata(entry, gimli::DW_AT_upper_bound).map(|bound| bound + 1)
+                    });
+                    // Arrays without bounds, like flexible array members, have no elements
+                    counts.push(count.unwrap_or(0) as usize);
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        if let (Language::Rust, RawType::Struct { name: Some(name), .. }) = (language, &raw) {
+            // Enum variants are declared inside the enum
+            scopes.push((depth, name.clone()));
+        }
+        if matches!(
+            raw,
+            RawType::Struct { .. } | RawType::Enum { .. } | RawType::Array { .. }
+        ) {
+            parents.push((depth, offset));
+        }
+        raw_types.insert(offset, raw);
+    }
+
+    let offsets: Vec<usize> = raw_types.keys().copied().collect();
+    for offset in offsets {
+        resolve_type(offset, &raw_types, language, offset_to_type);
+    }
+    Ok(())
+}
+
+/// Builds the Type for the DIE at offset, and the types it contains, caching them in
+/// offset_to_type. Pointers refer to their target by offset, which is what keeps recursive types
+/// like linked lists finite. Returns None for void and for types we can't make sense of.
+fn resolve_type(
+    offset: usize,
+    raw_types: &HashMap<usize, RawType>,
+    language: Language,
+    offset_to_type: &mut HashMap<usize, Type>,
+) -> Option<Type> {
+    if let Some(resolved) = offset_to_type.get(&offset) {
+        return Some(resolved.clone());
+    }
+    let mut template_params = Vec::new();
+    let (size, kind) = match raw_types.get(&offset)? {
+        RawType::Base { size, encoding, .. } => (*size, TypeKind::Base(*encoding)),
+        RawType::Pointer { target, .. } => (
+            std::mem::size_of::<usize>(),
+            TypeKind::Pointer(target.filter(|target| raw_types.contains_key(target))),
+        ),
+        RawType::Array { element, counts } => {
+            let mut element = resolve_type((*element)?, raw_types, language, offset_to_type)?;
+            let element_name = element.name.clone();
+            let counts = if counts.is_empty() { vec![0] } else { counts.clone() };
+            // int a[2][3] is an array of 2 arrays of 3 ints
+            for dimension in (1..counts.len()).rev() {
+                element = Type {
+                    name: array_type_name(&element_name, &counts[dimension..], language),
+                    size: element.size * counts[dimension],
+                    kind: TypeKind::Array(Box::new(element), counts[dimension]),
+                    id: None,
+                    language,
+                    template_params: Vec::new(),
+                };
+            }
+            (element.size * counts[0], TypeKind::Array(Box::new(element), counts[0]))
+        }
+        RawType::Struct {
+            size,
+            members,
+            union,
+            variant_part,
+            template_params: params,
+            ..
+        } => {
+            // Type parameters are kept by id, like pointers, since a type can be a parameter of
+            // one of its own members, e.g. a tree node with a Vec of nodes
+            template_params = params
+                .iter()
+                .filter_map(|(name, id)| Some((name.clone(), id.filter(|id| raw_types.contains_key(id))?)))
+                .collect();
+            let members = members
+                .iter()
+                .filter_map(|member| resolve_member(member, raw_types, language, offset_to_type))
+                .collect();
+            match variant_part {
+                Some(part) => {
+                    let discriminant = part.discriminant.as_ref().and_then(|member| {
+                        resolve_member(member, raw_types, language, offset_to_type)
+                    });
+                    let variants = part
+                        .variants
+                        .iter()
+                        .filter_map(|(discr_value, member)| {
+                            Some(Variant {
+                                discr_value: *discr_value,
+                                member: resolve_member(member, raw_types, language, offset_to_type)?,
+                            })
+                        })
+                        .collect();
+                    let kind = TypeKind::Variants {
+                        discriminant: discriminant.map(Box::new),
+                        variants,
+                    };
+                    (*size, kind)
+                }
+                None if *union => (*size, TypeKind::Union(members)),
+                None => (*size, TypeKind::Struct(members)),
+            }
+        }
+        RawType::Enum { size, values, .. } => (*size, TypeKind::Enum(values.clone())),
+        RawType::Typedef { target, .. } | RawType::Qualified { target, .. } => {
+            let target = resolve_type((*target)?, raw_types, language, offset_to_type)?;
+            (target.size, target.kind)
+        }
+        RawType::Function { .. } => (1, TypeKind::Function),
+    };
+    let resolved = Type {
+        name: type_name(Some(offset), raw_types, language),
+        size,
+        kind,
+        id: Some(offset),
+        language,
+        template_params,
+    };
+    offset_to_type.insert(offset, resolved.clone());
+    Some(resolved)
+}
+
+fn resolve_member(
+    member: &RawMember,
+    raw_types: &HashMap<usize, RawType>,
+    language: Language,
+    offset_to_type: &mut HashMap<usize, Type>,
+) -> Option<Member> {
+    Some(Member {
+        name: member.name.clone(),
+        offset: member.offset,
+        bits: member.bits,
+        member_type: resolve_type(member.member_type?, raw_types, language, offset_to_type)?,
+    })
+}
+
+/// Names a type the way its language would write it, e.g. "struct node *" or "const char *" in
+/// C and "*const u8" or "[i32; 3]" in Rust. Types we don't know about are named "void", or "()"
+/// in Rust.
+fn type_name(offset: Option<usize>, raw_types: &HashMap<usize, RawType>, language: Language) -> String {
+    let raw = match offset.and_then(|offset| raw_types.get(&offset)) {
+        Some(raw) => raw,
+        None if language == Language::Rust => return "()".to_string(),
+        None => return "void".to_string(),
+    };
+    match raw {
+        RawType::Base { name, .. } | RawType::Typedef { name, .. } => name.clone(),
+        RawType::Pointer {
+            name: Some(name), ..
+        } if language == Language::Rust => name.clone(),
+        RawType::Pointer { target, .. } if language == Language::Rust => {
+            format!("*const {}", type_name(*target, raw_types, language))
+        }
+        RawType::Pointer { target, .. } => {
+            let pointee = type_name(*target, raw_types, language);
+            if let Some(return_type) = pointee.strip_suffix(" (void)") {
+                format!("{} (*)(void)", return_type)
+            } else if pointee.ends_with('*') {
+                format!("{}*", pointee)
+            } else {
+                format!("{} *", pointee)
+            }
+        }
+        RawType::Array { element, counts } => {
+            array_type_name(&type_name(*element, raw_types, language), counts, language)
+        }
+        RawType::Struct { name, .. } if language == Language::Rust => {
+            name.clone().unwrap_or_else(|| "{...}".to_string())
+        }
+        RawType::Struct { name, union, .. } => format!(
+            "{} {}",
+            if *union { "union" } else { "struct" },
+            name.as_deref().unwrap_or("{...}")
+        ),
+        RawType::Enum { name, .. } if language == Language::Rust => {
+            name.clone().unwrap_or_else(|| "{...}".to_string())
+        }
+        RawType::Enum { name, .. } => format!("enum {}", name.as_deref().unwrap_or("{...}")),
+        RawType::Qualified { qualifier, target } => {
+            let target = type_name(*target, raw_types, language);
+            if target.ends_with('*') {
+                format!("{} {}", target, qualifier)
+            } else {
+                format!("{} {}", qualifier, target)
+            }
+        }
+        RawType::Function { return_type } => {
+            format!("{} (void)", type_name(*return_type, raw_types, language))
+        }
+    }
+}
+
+/// Names an array type, e.g. "int [2][3]" in C or "[[i32; 3]; 2]" in Rust.
+fn array_type_name(element: &str, counts: &[usize], language: Language) -> String {
+    if language == Language::Rust {
+        return counts
+            .iter()
+            .rev()
+            .fold(element.to_string(), |name, count| format!("[{}; {}]", name, count));
+    }
+    let dimensions: String = counts.iter().map(|count| format!("[{}]", count)).collect();
+    format!("{} {}", element, dimensions)
+}
+
+fn attr_string<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    name: gimli::DwAt,
+) -> Option<String> {
+    match get_attr_value(&entry.attr(name).ok()??, unit, dwarf) {
+        Ok(DebugValue::Str(value)) => Some(value),
+        _ => None,
+    }
+}
+
+fn attr_udata<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    name: gimli::DwAt,
+) -> Option<u64> {
+    entry.attr(name).ok()??.udata_value()
+}
+
+/// Returns the section offset of the DIE an attribute such as DW_AT_type refers to.
+fn attr_ref<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    name: gimli::DwAt,
+) -> Option<usize> {
+    match get_attr_value(&entry.attr(name).ok()??, unit, dwarf) {
+        Ok(DebugValue::Size(offset)) => Some(offset),
+        _ => None,
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum DebugValue {
     Str(String),
     Uint(u64),
-    Int(i64),
     Size(usize),
     NoVal,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     GimliError(gimli::Error),
     // Addr2lineError(addr2line::gimli::Error),
@@ -298,25 +920,133 @@ impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where
 
 trait Reader: gimli::Reader<Offset = usize> + Send + Sync {}
 
-fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<Location> {
+/// Works out where a variable lives from its DW_AT_location, which is either a single expression
+/// or a list of expressions for different ranges of code.
+///
+/// frame_base is the register the enclosing function's DW_AT_frame_base names, if any.
+fn get_location<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    frame_base: Option<u16>,
+) -> Option<Location> {
     if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
-        let encoding = unit.encoding();
         let mut pc = data.0.clone();
-        if pc.len() > 0 {
-            if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
-                match op {
-                    gimli::Operation::FrameOffset { offset } => {
-                        return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
-                    }
-                    gimli::Operation::Address { address } => {
-                        return Some(Location::Address(address.try_into().unwrap()));
-                    }
-                    _ => {}
-                }
+        // A lone DW_OP_fbreg or DW_OP_addr, which is all unoptimized code uses, doesn't need to
+        // be evaluated
+        match gimli::Operation::parse(&mut pc, unit.encoding()) {
+            Ok(gimli::Operation::FrameOffset { offset }) if pc.is_empty() && frame_base.is_none() => {
+                return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
+            }
+            Ok(gimli::Operation::Address { address }) if pc.is_empty() => {
+                return Some(Location::Address(address.try_into().unwrap()));
             }
+            _ => {}
+        }
+        let expr = dwarf_expression(data, unit)?;
+        return Some(Location::Expression(DwarfExpression { frame_base, ..expr }));
+    }
+    let mut locations = dwarf.attr_locations(unit, attr.value()).ok()??;
+    let mut ranges = Vec::new();
+    while let Ok(Some(entry)) = locations.next() {
+        let expr = dwarf_expression(&entry.data, unit)?;
+        ranges.push((
+            entry.range.begin as usize,
+            entry.range.end as usize,
+            DwarfExpression { frame_base, ..expr },
+        ));
+    }
+    Some(Location::List(ranges))
+}
+
+/// Returns the register a DW_AT_frame_base names, or None if it is anything else, such as
+/// DW_OP_call_frame_cfa.
+fn frame_base_register<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<u16> {
+    let data = match attr.value() {
+        gimli::AttributeValue::Exprloc(data) => data,
+        _ => return None,
+    };
+    let mut pc = data.0;
+    match gimli::Operation::parse(&mut pc, unit.encoding()) {
+        Ok(gimli::Operation::Register { register }) if pc.is_empty() => Some(register.0),
+        _ => None,
+    }
+}
+
+fn dwarf_expression<R: Reader>(
+    data: &gimli::Expression<R>,
+    unit: &gimli::Unit<R>,
+) -> Option<DwarfExpression> {
+    Some(DwarfExpression {
+        bytes: data.0.to_slice().ok()?.into_owned(),
+        encoding: unit.encoding(),
+        unit_offset: unit.header.offset().as_debug_info_offset()?.0,
+        bias: 0,
+        frame_base: None,
+    })
+}
+
+/// Returns the little-endian bytes of a DW_AT_const_value.
+fn get_constant<R: Reader>(attr: &gimli::Attribute<R>) -> Option<Vec<u8>> {
+    match attr.value() {
+        gimli::AttributeValue::Block(data) => Some(data.to_slice().ok()?.into_owned()),
+        gimli::AttributeValue::Sdata(value) => Some(value.to_le_bytes().to_vec()),
+        value => Some(value.udata_value()?.to_le_bytes().to_vec()),
+    }
+}
+
+/// Returns the section offset of the DIE that a concrete instance of a function or variable gets
+/// its name and type from (DW_AT_abstract_origin or DW_AT_specification).
+fn origin<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<usize> {
+    attr_ref(entry, unit, dwarf, gimli::DW_AT_abstract_origin)
+        .or_else(|| attr_ref(entry, unit, dwarf, gimli::DW_AT_specification))
+}
+
+/// The name, type and line of declaration of a function or variable.
+#[derive(Default)]
+struct Declaration {
+    name: Option<String>,
+    type_offset: Option<usize>,
+    line_number: Option<u64>,
+}
+
+/// Reads the declaration at a section offset, following it further if it refers to yet another
+/// DIE.
+fn declaration<R: Reader>(offset: usize, unit: &gimli::Unit<R>, dwarf: &gimli::Dwarf<R>) -> Declaration {
+    let entry = match gimli::DebugInfoOffset(offset)
+        .to_unit_offset(&unit.header)
+        .and_then(|offset| unit.entry(offset).ok())
+    {
+        Some(entry) => entry,
+        None => return Declaration::default(),
+    };
+    let mut declaration = Declaration {
+        name: attr_string(&entry, unit, dwarf, gimli::DW_AT_name),
+        type_offset: attr_ref(&entry, unit, dwarf, gimli::DW_AT_type),
+        line_number: attr_udata(&entry, gimli::DW_AT_decl_line),
+    };
+    if declaration.name.is_none() {
+        if let Some(origin) = origin(&entry, unit, dwarf).filter(|origin| *origin != offset) {
+            let further = self::declaration(origin, unit, dwarf);
+            declaration.name = further.name;
+            declaration.type_offset = declaration.type_offset.or(further.type_offset);
+            declaration.line_number = declaration.line_number.or(further.line_number);
         }
     }
-    None
+    declaration
+}
+
+/// Converts a DIE's offset within its unit to an offset within the section, which is how
+/// DW_AT_type references are reported by get_attr_value.
+fn section_offset<R: Reader>(offset: UnitOffset, unit: &gimli::Unit<R>) -> usize {
+    match offset.to_unit_section_offset(unit) {
+        UnitSectionOffset::DebugInfoOffset(goff) => goff.0,
+        UnitSectionOffset::DebugTypesOffset(goff) => goff.0,
+    }
 }
 
 // based on dwarf_dump.rs
@@ -345,7 +1075,6 @@ fn get_attr_value<R: Reader>(
                 Ok(DebugValue::Str(format!("<.debug_str+0x{:08x}>", offset.0)))
             }
         }
-        gimli::AttributeValue::Sdata(data) => Ok(DebugValue::Int(data)),
         gimli::AttributeValue::Addr(data) => Ok(DebugValue::Uint(data)),
         gimli::AttributeValue::Udata(data) => Ok(DebugValue::Uint(data)),
 
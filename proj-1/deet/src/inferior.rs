@@ -1,13 +1,23 @@
+use nix::sys::personality;
 use nix::sys::ptrace;
 use nix::sys::signal;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
+use std::fs::File;
 use std::mem::size_of;
-use std::process::{Child, Command};
+use std::os::unix::fs::FileExt;
+use std::process::Command;
 use std::os::unix::process::CommandExt;
 use std::collections::HashMap;
+use crate::breakpoint::{BreakpointTable, WatchKind, MAX_WATCHPOINTS};
 use crate::dwarf_data::DwarfData;
+use crate::registers;
+use crate::signals::{self as signal_policy, SignalTable};
+use crate::target::{read_maps, Region, Target};
+use crate::unwind::Unwinder;
+use std::cell::{OnceCell, RefCell};
 
+#[derive(Clone, Copy)]
 pub enum Status {
     /// Indicates inferior stopped. Contains the signal that stopped the process, as well as the
     /// current instruction pointer that it is stopped at.
@@ -24,36 +34,151 @@ pub enum Status {
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
 /// pre_exec with Command to call this in the child process.
 fn child_traceme() -> Result<(), std::io::Error> {
-    ptrace::traceme().or(Err(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        "ptrace TRACEME failed",
-    )))
+    ptrace::traceme().or(Err(std::io::Error::other("ptrace TRACEME failed")))
+}
+
+/// Turns off address space randomization for the child, like gdb does, so that position
+/// independent code is loaded at the same addresses on every run.
+fn child_disable_aslr() -> Result<(), std::io::Error> {
+    let persona = personality::get()?;
+    personality::set(persona | personality::Persona::ADDR_NO_RANDOMIZE)?;
+    Ok(())
+}
+
+/// Events we ask ptrace to report: new threads, fork, vfork and exec.
+fn trace_options() -> ptrace::Options {
+    ptrace::Options::PTRACE_O_TRACECLONE
+        | ptrace::Options::PTRACE_O_TRACEFORK
+        | ptrace::Options::PTRACE_O_TRACEVFORK
+        | ptrace::Options::PTRACE_O_TRACEEXEC
+}
+
+fn is_event(event: i32, expected: ptrace::Event) -> bool {
+    event == expected as i32
 }
 
 fn align_addr_to_word(addr: usize) -> usize {
     addr & (-(size_of::<usize>() as isize) as usize)
 }
 
+/// Offset of debug register n within the user area (struct user), which is where
+/// PTRACE_PEEKUSER/PTRACE_POKEUSER access it.
+fn debug_register_offset(n: usize) -> usize {
+    std::mem::offset_of!(libc::user, u_debugreg) + n * size_of::<u64>()
+}
+
+fn write_debug_register(tid: Pid, n: usize, value: u64) -> Result<(), nix::Error> {
+    unsafe {
+        ptrace::write_user(
+            tid,
+            debug_register_offset(n) as ptrace::AddressType,
+            value as *mut std::ffi::c_void,
+        )
+    }
+}
+
+/// A watchpoint that triggered, along with the watched memory before and after.
+pub struct WatchpointHit {
+    pub id: usize,
+    pub old_value: Option<Vec<u8>>,
+    pub new_value: Vec<u8>,
+}
+
 #[derive(Clone)]
 struct Breakpoint {
-    addr: usize,
     orig_byte: u8,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum ThreadState {
+    Stopped,
+    Running,
+    Stepping,
+}
+
+struct Thread {
+    /// Small number shown to the user, starting at 1 for the main thread
+    id: usize,
+    tid: Pid,
+    state: ThreadState,
+    /// We sent the thread a SIGSTOP that it hasn't reported yet
+    pending_sigstop: bool,
+    /// Signal the thread received while we were stopping all threads, delivered when it resumes
+    pending_signal: Option<signal::Signal>,
+}
+
+/// What to do when the inferior forks, set with `set follow-fork-mode` and `set detach-on-fork`.
+#[derive(Clone, Copy)]
+pub struct ForkPolicy {
+    /// Keep debugging the child instead of the parent
+    pub follow_child: bool,
+    /// Let the process we don't follow run on untraced, instead of holding it stopped
+    pub detach_on_fork: bool,
+}
+
+impl Default for ForkPolicy {
+    fn default() -> ForkPolicy {
+        ForkPolicy {
+            follow_child: false,
+            detach_on_fork: true,
+        }
+    }
+}
+
 pub struct Inferior {
-    child: Child,
+    pid: Pid,
+    /// Whether we attached to a process that was already running, rather than spawning it
+    attached: bool,
+    /// Every thread of the process. The process is all-stop: whenever one thread stops, the
+    /// others are stopped too before control returns to the user.
+    threads: Vec<Thread>,
+    next_thread_id: usize,
+    /// Thread that registers are read from and that stepping commands act on
+    current: Pid,
+    /// Whether the last stop happened in a different thread than the one that was selected
+    thread_switched: bool,
+    /// Level of the stack frame of the selected thread that variables are looked up in, where 0
+    /// is the innermost frame. Reset whenever a thread runs.
+    selected_frame: usize,
+    /// New threads whose initial SIGSTOP arrived before their creator reported the clone
+    early_threads: Vec<Pid>,
+    /// Addresses where we have replaced an instruction byte with 0xcc
     breakpoints: HashMap<usize, Breakpoint>,
+    /// Ids of the user breakpoints that caused the last stop
+    breakpoints_hit: Vec<usize>,
+    /// Return address planted by run_until_return, which should always stop the inferior when the
+    /// given thread reaches it
+    temporary_breakpoint: Option<(usize, Pid)>,
+    /// Address of the function the dynamic linker calls when it loads or unloads libraries
+    solib_breakpoint: Option<usize>,
+    /// The dynamic linker has changed the list of loaded libraries since this was last asked
+    libraries_changed: bool,
+    /// Id of the watchpoint loaded into each of the debug registers DR0-DR3
+    watch_slots: [Option<usize>; MAX_WATCHPOINTS],
+    /// Watchpoints that triggered the last stop
+    watchpoints_hit: Vec<WatchpointHit>,
+    /// Values of DR0-DR3 and DR7, which every thread needs its own copy of
+    debug_registers: [u64; MAX_WATCHPOINTS],
+    dr7: u64,
+    fork_policy: ForkPolicy,
+    /// What to do with signals the process receives
+    signals: SignalTable,
+    /// Processes that forked off and were kept stopped because detach-on-fork is off
+    held: Vec<Inferior>,
+    /// Program the process started running with exec, which the debugger still has to load
+    exec_path: Option<String>,
+    unwinder: RefCell<Unwinder>,
 }
 
 impl Inferior {
     /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
-    /// an error is encountered.
-    pub fn new(target: &str, args: &Vec<String>, breakpoints: &Vec<usize>) -> Option<Inferior> {
-        
+    /// an error is encountered. The process is stopped before its first instruction, and
+    /// breakpoints have to be inserted once the debugger knows where the code is loaded.
+    pub fn new(target: &str, args: &[String]) -> Option<Inferior> {
         let mut binding = Command::new(target);
         let mut cmd = binding.args(args);
         unsafe {
-            cmd = cmd.pre_exec(|| child_traceme());
+            cmd = cmd.pre_exec(child_disable_aslr).pre_exec(child_traceme);
         }
         let ch = cmd.spawn().ok()?;
 
@@ -61,114 +186,1097 @@ impl Inferior {
         // https://linux.die.net/man/2/waitpid
         let pid = Pid::from_raw(ch.id() as i32); 
         waitpid(pid, Some(WaitPidFlag::WSTOPPED)).ok()?;
+        ptrace::setoptions(pid, trace_options()).ok()?;
 
-        let mut inf = Inferior { child: ch, breakpoints: HashMap::new() };
+        Some(Inferior::bare(pid, false))
+    }
+
+    /// Attaches to a process that is already running with PTRACE_ATTACH, which stops it with
+    /// SIGSTOP. Every thread of the process is attached to. The process is left stopped, without
+    /// any breakpoints inserted.
+    pub fn attach(pid: Pid) -> Result<Inferior, nix::Error> {
+        ptrace::attach(pid)?;
+        waitpid(pid, Some(WaitPidFlag::__WALL))?;
+        ptrace::setoptions(pid, trace_options())?;
+        let mut inf = Inferior::bare(pid, true);
 
-        // set all the breakpoints that were passed in
-        for addr in breakpoints {
-            let orig_byte = inf.write_byte(*addr, 0xcc).expect("Failed to set breakpoint");
-            inf.breakpoints.insert(*addr, Breakpoint { addr: *addr, orig_byte: orig_byte as u8 });
+        // Threads may be created while we attach to the others, so keep looking until there are
+        // no new ones
+        let mut seen: Vec<Pid> = vec![pid];
+        loop {
+            let tids: Vec<Pid> = std::fs::read_dir(format!("/proc/{}/task", pid))
+                .map_err(|_| nix::Error::ESRCH)?
+                .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+                .map(Pid::from_raw)
+                .filter(|tid| !seen.contains(tid))
+                .collect();
+            if tids.is_empty() {
+                break;
+            }
+            for tid in tids {
+                seen.push(tid);
+                // The thread may have exited in the meantime
+                if ptrace::attach(tid).is_ok() {
+                    waitpid(tid, Some(WaitPidFlag::__WALL))?;
+                    ptrace::setoptions(tid, trace_options())?;
+                    inf.add_thread(tid);
+                }
+            }
         }
+        Ok(inf)
+    }
 
-        Some(inf)
+    /// Creates the state for a process with a single thread, none of our breakpoints inserted yet
+    /// and the default fork policy.
+    fn bare(pid: Pid, attached: bool) -> Inferior {
+        Inferior {
+            pid,
+            attached,
+            threads: vec![Thread {
+                id: 1,
+                tid: pid,
+                state: ThreadState::Stopped,
+                pending_sigstop: false,
+                pending_signal: None,
+            }],
+            next_thread_id: 2,
+            current: pid,
+            thread_switched: false,
+            selected_frame: 0,
+            early_threads: Vec::new(),
+            breakpoints: HashMap::new(),
+            breakpoints_hit: Vec::new(),
+            temporary_breakpoint: None,
+            solib_breakpoint: None,
+            libraries_changed: false,
+            watch_slots: [None; MAX_WATCHPOINTS],
+            watchpoints_hit: Vec::new(),
+            debug_registers: [0; MAX_WATCHPOINTS],
+            dr7: 0,
+            fork_policy: ForkPolicy::default(),
+            signals: SignalTable::new(),
+            held: Vec::new(),
+            exec_path: None,
+            unwinder: RefCell::new(Unwinder::new()),
+        }
     }
 
     /// Returns the pid of this inferior.
     pub fn pid(&self) -> Pid {
-        nix::unistd::Pid::from_raw(self.child.id() as i32)
+        self.pid
+    }
+
+    pub fn set_fork_policy(&mut self, fork_policy: ForkPolicy) {
+        self.fork_policy = fork_policy;
+    }
+
+    pub fn set_signal_policy(&mut self, signals: &SignalTable) {
+        self.signals = signals.clone();
+    }
+
+    /// Replaces the signal the selected thread will receive when it resumes, e.g. the one it
+    /// stopped with. None resumes it without a signal.
+    pub fn set_signal(&mut self, sig: Option<signal::Signal>) {
+        let tid = self.tid();
+        if let Some(thread) = self.thread_mut(tid) {
+            thread.pending_signal = sig;
+        }
+    }
+
+    /// Hands over the processes that forked off and are being held stopped.
+    pub fn take_held(&mut self) -> Vec<Inferior> {
+        std::mem::take(&mut self.held)
+    }
+
+    /// Forgets the call frame information read so far, after libraries were loaded or unloaded.
+    pub fn forget_unwind_info(&mut self) {
+        self.unwinder.get_mut().clear();
+    }
+
+    /// Returns the path of the new program if the process called exec since the last time this
+    /// was asked. All of our breakpoints are gone from its memory at that point.
+    pub fn take_exec(&mut self) -> Option<String> {
+        self.exec_path.take()
+    }
+
+    /// Plants the breakpoint that tells us when the dynamic linker has loaded or unloaded
+    /// libraries, at the function it calls each time.
+    pub fn set_solib_breakpoint(&mut self, addr: usize) -> Result<(), nix::Error> {
+        if self.solib_breakpoint != Some(addr) {
+            self.set_breakpoint(addr)?;
+            self.solib_breakpoint = Some(addr);
+        }
+        Ok(())
+    }
+
+    pub fn solib_breakpoint(&self) -> Option<usize> {
+        self.solib_breakpoint
+    }
+
+    /// Returns true if the list of loaded libraries changed since the last time this was asked.
+    pub fn take_libraries_changed(&mut self) -> bool {
+        std::mem::take(&mut self.libraries_changed)
+    }
+
+    /// Returns the thread id of the selected thread.
+    pub fn tid(&self) -> Pid {
+        self.current
+    }
+
+    /// Returns true if we attached to this process instead of starting it ourselves.
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
+    fn thread_mut(&mut self, tid: Pid) -> Option<&mut Thread> {
+        self.threads.iter_mut().find(|thread| thread.tid == tid)
+    }
+
+    fn thread_state(&self, tid: Pid) -> ThreadState {
+        self.threads
+            .iter()
+            .find(|thread| thread.tid == tid)
+            .map_or(ThreadState::Stopped, |thread| thread.state)
     }
 
-    /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
-    /// after the waitpid call.
-    pub fn wait(&self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
-        Ok(match waitpid(self.pid(), options)? {
-            WaitStatus::Exited(_pid, exit_code) => Status::Exited(exit_code),
-            WaitStatus::Signaled(_pid, signal, _core_dumped) => Status::Signaled(signal),
-            WaitStatus::Stopped(_pid, signal) => {
-                let regs = ptrace::getregs(self.pid())?;
-                Status::Stopped(signal, regs.rip as usize)
+    /// Starts tracking a new thread, which must be in a ptrace stop.
+    fn add_thread(&mut self, tid: Pid) {
+        let id = self.next_thread_id;
+        self.next_thread_id += 1;
+        self.threads.push(Thread {
+            id,
+            tid,
+            state: ThreadState::Stopped,
+            pending_sigstop: false,
+            pending_signal: None,
+        });
+        println!("[New thread {} (LWP {})]", id, tid);
+        // Debug registers aren't inherited by new threads
+        if self.dr7 != 0 {
+            if let Err(err) = self.load_debug_registers(tid) {
+                println!("Failed to set watchpoints in thread {}: {}", id, err);
             }
-            other => panic!("waitpid returned unexpected status: {:?}", other),
-        })
+        }
+    }
+
+    /// Handles a PTRACE_EVENT_CLONE stop of parent. The new thread starts out stopped, and is set
+    /// running if its creator was running freely.
+    fn handle_new_thread(&mut self, parent: Pid) -> Result<(), nix::Error> {
+        let tid = Pid::from_raw(ptrace::getevent(parent)? as i32);
+        match self.early_threads.iter().position(|early| *early == tid) {
+            Some(pos) => {
+                self.early_threads.remove(pos);
+            }
+            // Wait for the SIGSTOP every new thread starts with
+            None => {
+                waitpid(tid, Some(WaitPidFlag::__WALL))?;
+            }
+        }
+        self.add_thread(tid);
+        if self.thread_state(parent) == ThreadState::Running {
+            self.resume(tid, ThreadState::Running)?;
+        }
+        Ok(())
+    }
+
+    /// Handles a PTRACE_EVENT_FORK or PTRACE_EVENT_VFORK stop of parent according to the fork
+    /// policy. The new process is a copy of ours, including the 0xcc bytes of our breakpoints, and
+    /// starts out stopped. Returns the stop to report if we were single-stepping parent and
+    /// switched over to the child.
+    fn handle_fork(&mut self, parent: Pid) -> Result<Option<(Pid, Status)>, nix::Error> {
+        let child = self.fork_child(parent)?;
+        let child_pid = child.pid;
+        let state = self.thread_state(parent);
+        if !self.fork_policy.follow_child {
+            self.release_fork_child(child)?;
+            self.resume(parent, state)?;
+            return Ok(None);
+        }
+
+        println!("[Attaching after process {} fork to child process {}]", self.pid, child_pid);
+        self.thread_mut(parent).unwrap().state = ThreadState::Stopped;
+        self.stop_all_threads()?;
+        let mut parent_inferior = std::mem::replace(self, child);
+        self.held = parent_inferior.take_held();
+        if self.fork_policy.detach_on_fork {
+            println!("[Detaching after fork from parent process {}]", parent_inferior.pid);
+            parent_inferior.detach()?;
+        } else {
+            println!("[Parent process {} is held stopped after fork]", parent_inferior.pid);
+            self.held.push(parent_inferior);
+        }
+        self.thread_switched = true;
+        match state {
+            ThreadState::Running => {
+                self.resume(child_pid, ThreadState::Running)?;
+                Ok(None)
+            }
+            _ => {
+                let regs = ptrace::getregs(child_pid)?;
+                Ok(Some((child_pid, Status::Stopped(signal::Signal::SIGTRAP, regs.rip as usize))))
+            }
+        }
     }
 
-    pub fn print_backtrace(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
-        let regs = ptrace::getregs(self.pid())?;
-        let mut rip = regs.rip as usize;
-        let mut rbp = regs.rbp as usize;
+    /// Sets up the state for the process that parent just created with fork or vfork, once it has
+    /// reported its initial stop.
+    fn fork_child(&mut self, parent: Pid) -> Result<Inferior, nix::Error> {
+        let child_pid = Pid::from_raw(ptrace::getevent(parent)? as i32);
+        match self.early_threads.iter().position(|early| *early == child_pid) {
+            Some(pos) => {
+                self.early_threads.remove(pos);
+            }
+            None => {
+                waitpid(child_pid, Some(WaitPidFlag::__WALL))?;
+            }
+        }
+        let mut child = Inferior::bare(child_pid, self.attached);
+        child.breakpoints = self.breakpoints.clone();
+        child.temporary_breakpoint = self.temporary_breakpoint.map(|(addr, _)| (addr, child_pid));
+        child.solib_breakpoint = self.solib_breakpoint;
+        child.watch_slots = self.watch_slots;
+        child.debug_registers = self.debug_registers;
+        child.dr7 = self.dr7;
+        child.fork_policy = self.fork_policy;
+        child.signals = self.signals.clone();
+        // Debug registers aren't inherited across fork
+        if child.dr7 != 0 {
+            child.load_debug_registers(child_pid)?;
+        }
+        Ok(child)
+    }
+
+    /// Lets go of a child process we aren't following, or holds it stopped.
+    fn release_fork_child(&mut self, mut child: Inferior) -> Result<(), nix::Error> {
+        if self.fork_policy.detach_on_fork {
+            println!("[Detaching after fork from child process {}]", child.pid);
+            child.detach()
+        } else {
+            println!("[Child process {} is held stopped after fork]", child.pid);
+            self.held.push(child);
+            Ok(())
+        }
+    }
 
+    /// Handles a PTRACE_EVENT_EXEC stop. The process now runs a different program: the other
+    /// threads are gone, and so are our breakpoints and watchpoints.
+    fn handle_exec(&mut self) -> Result<(Pid, Status), nix::Error> {
+        let pid = self.pid;
+        self.threads.retain(|thread| thread.tid == pid);
+        if self.threads.is_empty() {
+            // The main thread had already exited, and the thread that called exec took its place
+            self.threads.push(Thread {
+                id: self.next_thread_id,
+                tid: pid,
+                state: ThreadState::Stopped,
+                pending_sigstop: false,
+                pending_signal: None,
+            });
+            self.next_thread_id += 1;
+        }
+        self.current = pid;
+        self.breakpoints.clear();
+        self.temporary_breakpoint = None;
+        self.solib_breakpoint = None;
+        self.watch_slots = [None; MAX_WATCHPOINTS];
+        self.debug_registers = [0; MAX_WATCHPOINTS];
+        self.dr7 = 0;
+        self.unwinder.get_mut().clear();
+        let path = std::fs::read_link(format!("/proc/{}/exe", self.pid))
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        println!("process {} is executing new program: {}", self.pid, path);
+        self.exec_path = Some(path);
+        self.threads[0].state = ThreadState::Stopped;
+        let regs = ptrace::getregs(self.pid)?;
+        Ok((self.pid, Status::Stopped(signal::Signal::SIGTRAP, regs.rip as usize)))
+    }
+
+    /// Forgets about a thread that exited. Returns the stop to report if it was the thread being
+    /// single-stepped and the rest of the process is still around.
+    fn thread_exited(&mut self, tid: Pid) -> Option<(Pid, Status)> {
+        let pos = self.threads.iter().position(|thread| thread.tid == tid)?;
+        let thread = self.threads.remove(pos);
+        println!("[Thread {} (LWP {}) exited]", thread.id, tid);
+        if self.current == tid {
+            self.current = self.pid;
+            self.thread_switched = true;
+        }
+        if thread.state == ThreadState::Stepping {
+            // If the whole process is exiting, the other threads are no longer in a ptrace stop
+            // and we'll see the main thread exit next
+            if let Ok(regs) = ptrace::getregs(self.pid) {
+                return Some((self.pid, Status::Stopped(signal::Signal::SIGTRAP, regs.rip as usize)));
+            }
+        }
+        None
+    }
+
+    /// Lets a stopped thread run freely or execute a single instruction, delivering any signal
+    /// that was held back for it.
+    fn resume(&mut self, tid: Pid, state: ThreadState) -> Result<(), nix::Error> {
+        let thread = match self.thread_mut(tid) {
+            Some(thread) => thread,
+            None => return Ok(()),
+        };
+        let sig = thread.pending_signal.take();
+        thread.state = state;
+        self.selected_frame = 0;
+        self.unwinder.get_mut().forget_memory_map();
+        match state {
+            ThreadState::Running => ptrace::cont(tid, sig),
+            ThreadState::Stepping => ptrace::step(tid, sig),
+            ThreadState::Stopped => Ok(()),
+        }
+    }
+
+    fn resume_all(&mut self) -> Result<(), nix::Error> {
+        let stopped: Vec<Pid> = self
+            .threads
+            .iter()
+            .filter(|thread| thread.state == ThreadState::Stopped)
+            .map(|thread| thread.tid)
+            .collect();
+        for tid in stopped {
+            self.resume(tid, ThreadState::Running)?;
+        }
+        Ok(())
+    }
+
+    /// Waits until some thread stops for a reason the user should know about, or the process
+    /// exits. New threads, threads exiting and the SIGSTOPs we sent ourselves are handled here.
+    /// Returns the thread that stopped along with the status.
+    fn wait_event(&mut self) -> Result<(Pid, Status), nix::Error> {
         loop {
-            let line_number = debug_data.get_line_from_addr(rip).expect("unable to find line number from rip");
-            let function_name = debug_data.get_function_from_addr(rip).expect("unable to find function name from rip");
-            println!("{} ({})", function_name, line_number);
-            if function_name == "main" {
-                break;
+            let status = waitpid(None, Some(WaitPidFlag::__WALL))?;
+            let tid = match status.pid() {
+                Some(tid) => tid,
+                None => continue,
+            };
+            let known = self.threads.iter().any(|thread| thread.tid == tid);
+            match status {
+                WaitStatus::PtraceEvent(_, _, event) if is_event(event, ptrace::Event::PTRACE_EVENT_CLONE) => {
+                    self.handle_new_thread(tid)?;
+                    self.resume(tid, self.thread_state(tid))?;
+                }
+                WaitStatus::PtraceEvent(_, _, event)
+                    if is_event(event, ptrace::Event::PTRACE_EVENT_FORK)
+                        || is_event(event, ptrace::Event::PTRACE_EVENT_VFORK) =>
+                {
+                    if let Some(stop) = self.handle_fork(tid)? {
+                        return Ok(stop);
+                    }
+                }
+                WaitStatus::PtraceEvent(_, _, event) if is_event(event, ptrace::Event::PTRACE_EVENT_EXEC) => {
+                    return self.handle_exec();
+                }
+                WaitStatus::Stopped(_, signal::Signal::SIGSTOP) if !known => {
+                    self.early_threads.push(tid);
+                }
+                WaitStatus::Stopped(_, signal::Signal::SIGSTOP)
+                    if self.thread_mut(tid).is_some_and(|thread| thread.pending_sigstop) =>
+                {
+                    self.thread_mut(tid).unwrap().pending_sigstop = false;
+                    self.resume(tid, self.thread_state(tid))?;
+                }
+                WaitStatus::Stopped(_, sig) => {
+                    if let Some(thread) = self.thread_mut(tid) {
+                        thread.state = ThreadState::Stopped;
+                    }
+                    let regs = ptrace::getregs(tid)?;
+                    return Ok((tid, Status::Stopped(sig, regs.rip as usize)));
+                }
+                WaitStatus::Exited(_, exit_code) if tid == self.pid => {
+                    return Ok((tid, Status::Exited(exit_code)));
+                }
+                WaitStatus::Signaled(_, sig, _core_dumped) if tid == self.pid => {
+                    return Ok((tid, Status::Signaled(sig)));
+                }
+                WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                    if let Some(stop) = self.thread_exited(tid) {
+                        return Ok(stop);
+                    }
+                }
+                // Other ptrace events don't concern us
+                _ => self.resume(tid, self.thread_state(tid))?,
             }
-            // read next rip and rbp from stack
-            rip = ptrace::read(self.pid(), (rbp + 8) as ptrace::AddressType)? as usize;
-            rbp = ptrace::read(self.pid(), rbp as ptrace::AddressType)? as usize;
         }
-        return Ok(());
     }
-    
-    pub fn print_stopped_location(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
-        let regs = ptrace::getregs(self.pid())?;
-        let rip = regs.rip as usize;
-        let line_number = debug_data.get_line_from_addr(rip).expect("unable to find line number from rip");
-        println!("Stopped at {}", line_number);
-        return Ok(());
+
+    /// Stops every thread that is still running, after one of them stopped. A thread that hits a
+    /// breakpoint in the meantime has its rip rewound, so it hits the breakpoint again when it is
+    /// resumed; other signals are held back and delivered when it resumes.
+    fn stop_all_threads(&mut self) -> Result<(), nix::Error> {
+        for thread in self.threads.iter_mut() {
+            if thread.state != ThreadState::Stopped && !thread.pending_sigstop {
+                unsafe {
+                    libc::syscall(
+                        libc::SYS_tgkill,
+                        self.pid.as_raw(),
+                        thread.tid.as_raw(),
+                        libc::SIGSTOP,
+                    );
+                }
+                thread.pending_sigstop = true;
+            }
+        }
+        while self.threads.iter().any(|thread| thread.state != ThreadState::Stopped) {
+            let status = waitpid(None, Some(WaitPidFlag::__WALL))?;
+            let tid = match status.pid() {
+                Some(tid) => tid,
+                None => continue,
+            };
+            if let WaitStatus::Exited(..) | WaitStatus::Signaled(..) = status {
+                self.thread_exited(tid);
+                continue;
+            }
+            let thread = match self.thread_mut(tid) {
+                Some(thread) => thread,
+                None => {
+                    if let WaitStatus::Stopped(_, signal::Signal::SIGSTOP) = status {
+                        self.early_threads.push(tid);
+                    }
+                    continue;
+                }
+            };
+            thread.state = ThreadState::Stopped;
+            match status {
+                WaitStatus::Stopped(_, signal::Signal::SIGSTOP) if thread.pending_sigstop => {
+                    thread.pending_sigstop = false;
+                }
+                WaitStatus::Stopped(_, signal::Signal::SIGTRAP) => {
+                    let mut regs = ptrace::getregs(tid)?;
+                    if self.breakpoints.contains_key(&(regs.rip as usize - 1)) {
+                        regs.rip -= 1;
+                        ptrace::setregs(tid, regs)?;
+                    }
+                }
+                WaitStatus::Stopped(_, sig) => thread.pending_signal = Some(sig),
+                WaitStatus::PtraceEvent(_, _, event) if is_event(event, ptrace::Event::PTRACE_EVENT_CLONE) => {
+                    self.handle_new_thread(tid)?;
+                }
+                // We're in the middle of stopping, so the parent is followed regardless of the
+                // fork policy
+                WaitStatus::PtraceEvent(_, _, event)
+                    if is_event(event, ptrace::Event::PTRACE_EVENT_FORK)
+                        || is_event(event, ptrace::Event::PTRACE_EVENT_VFORK) =>
+                {
+                    let child = self.fork_child(tid)?;
+                    self.release_fork_child(child)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
     }
 
-    // Continues the inferior process.
-    pub fn cont(&mut self) -> Result<Status, nix::Error> {
+    /// Applies the signal policy to a thread that stopped with a signal: the signal is kept to
+    /// be delivered when the thread resumes if it should be passed on, and announced if it should
+    /// be printed without stopping. Returns whether the user should get control.
+    fn handle_signal(&mut self, tid: Pid, sig: signal::Signal) -> bool {
+        let policy = self.signals.get(sig);
+        if let Some(thread) = self.thread_mut(tid) {
+            thread.pending_signal = if policy.pass { Some(sig) } else { None };
+        }
+        if !policy.stop && policy.print {
+            println!("Child received signal {}, {}", sig, signal_policy::describe(sig));
+        }
+        policy.stop
+    }
+
+    /// Executes a single instruction in the selected thread, with the other threads stopped.
+    fn single_step(&mut self) -> Result<Status, nix::Error> {
+        loop {
+            self.resume(self.tid(), ThreadState::Stepping)?;
+            let (tid, status) = self.wait_event()?;
+            // As in cont, signals that don't stop the program are dealt with and the step carries
+            // on
+            if let Status::Stopped(sig, _) = status {
+                if sig != signal::Signal::SIGTRAP && !self.handle_signal(tid, sig) {
+                    continue;
+                }
+            }
+            return Ok(status);
+        }
+    }
+
+    /// Returns the id and thread id of the thread the inferior stopped in, if that isn't the
+    /// thread that was selected before it was resumed.
+    pub fn switched_thread(&self) -> Option<(usize, Pid)> {
+        if !self.thread_switched {
+            return None;
+        }
+        let thread = self.threads.iter().find(|thread| thread.tid == self.current)?;
+        Some((thread.id, thread.tid))
+    }
 
-        let mut regs = ptrace::getregs(self.pid())?;
+    /// Makes the thread with the given id the one that registers are read from and that stepping
+    /// commands act on. Returns its thread id.
+    pub fn select_thread(&mut self, id: usize) -> Option<Pid> {
+        let tid = self.threads.iter().find(|thread| thread.id == id)?.tid;
+        self.current = tid;
+        self.selected_frame = 0;
+        Some(tid)
+    }
+
+    /// Returns the id and thread id of the selected thread.
+    pub fn current_thread(&self) -> (usize, Pid) {
+        let id = self
+            .threads
+            .iter()
+            .find(|thread| thread.tid == self.current)
+            .map_or(0, |thread| thread.id);
+        (id, self.current)
+    }
+
+    pub fn print_threads(&self, debug_data: &DwarfData) {
+        println!("  Id   Target Id         Frame");
+        for thread in &self.threads {
+            let marker = if thread.tid == self.current { "*" } else { " " };
+            let frame = match ptrace::getregs(thread.tid) {
+                Ok(regs) => {
+                    let rip = regs.rip as usize;
+                    match (debug_data.get_function_from_addr(rip), debug_data.get_line_from_addr(rip)) {
+                        (Some(func), Some(line)) => format!("{} () at {}", func, line),
+                        (Some(func), None) => format!("{:#x} in {} ()", rip, func),
+                        _ => format!("{:#x}", rip),
+                    }
+                }
+                Err(err) => format!("({})", err),
+            };
+            println!("{} {:<4} {:<17} {}", marker, thread.id, format!("LWP {}", thread.tid), frame);
+        }
+    }
+
+    /// Changes a general purpose, flags or segment register of the selected thread.
+    pub fn set_register(&mut self, name: &str, value: u64) -> Result<(), String> {
+        if self.selected_frame != 0 {
+            return Err("Registers can only be changed in the innermost frame".to_string());
+        }
+        let mut regs = ptrace::getregs(self.tid()).map_err(|err| err.to_string())?;
+        if !registers::set_register(&mut regs, name, value) {
+            return Err(format!("Invalid register \"${}\"", name));
+        }
+        ptrace::setregs(self.tid(), regs).map_err(|err| err.to_string())
+    }
+
+    /// Replaces all of the general purpose registers of the selected thread.
+    pub fn set_registers(&mut self, regs: libc::user_regs_struct) -> Result<(), nix::Error> {
+        ptrace::setregs(self.tid(), regs)
+    }
+
+    /// Replaces the x87 and SSE registers of the selected thread.
+    pub fn set_fp_registers(&mut self, mut fpregs: libc::user_fpregs_struct) -> Result<(), nix::Error> {
+        let res = unsafe {
+            libc::ptrace(
+                libc::PTRACE_SETFPREGS,
+                self.tid().as_raw(),
+                std::ptr::null_mut::<libc::c_void>(),
+                &mut fpregs as *mut libc::user_fpregs_struct,
+            )
+        };
+        nix::Error::result(res).map(drop)
+    }
+
+    /// Writes bytes to the inferior's memory at addr. Where one of our breakpoints is inserted,
+    /// the byte it restores is changed instead, so the 0xcc stays in place.
+    pub fn write_memory(&mut self, addr: usize, bytes: &[u8]) -> Result<(), nix::Error> {
+        for (offset, byte) in bytes.iter().enumerate() {
+            match self.breakpoints.get_mut(&(addr + offset)) {
+                Some(bp) => bp.orig_byte = *byte,
+                None => {
+                    self.write_byte(addr + offset, *byte)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// If the inferior is sitting on one of our breakpoints, temporarily restores the original
+    /// instruction, executes it, and puts the breakpoint back. Returns the status after the single
+    /// step, or None if there was no breakpoint to step over.
+    fn step_over_breakpoint(&mut self) -> Result<Option<Status>, nix::Error> {
+        let regs = ptrace::getregs(self.tid())?;
         let rip = regs.rip as usize;
-        if let Some(bp) = self.breakpoints.get(& (rip - 1)) {
-            let addr = bp.addr;
-            // we are at a breakpoint, need to step over it
-            // restore original byte
-            self.write_byte(bp.addr, bp.orig_byte).expect("Failed to restore original byte at breakpoint");
-            // set rip back to original instruction
+        let orig_byte = match self.breakpoints.get(&rip) {
+            Some(bp) => bp.orig_byte,
+            None => return Ok(None),
+        };
+        self.write_byte(rip, orig_byte)?;
+        let status = self.single_step()?;
+        if let Status::Stopped(_, _) = status {
+            self.write_byte(rip, 0xcc)?;
+        }
+        Ok(Some(status))
+    }
+
+    /// Returns the ids of the user breakpoints that made the inferior stop when it was last
+    /// continued. This is empty if it stopped for some other reason (e.g. a single step, a signal,
+    /// or one of our internal breakpoints).
+    pub fn breakpoints_hit(&self) -> &[usize] {
+        &self.breakpoints_hit
+    }
+
+    /// Returns the watchpoints that made the inferior stop when it was last resumed.
+    pub fn watchpoints_hit(&self) -> &[WatchpointHit] {
+        &self.watchpoints_hit
+    }
+
+    /// Continues the inferior process. Breakpoints whose condition is false, or that are being
+    /// ignored, are resumed from transparently, as are write watchpoints whose value didn't
+    /// change.
+    pub fn cont(
+        &mut self,
+        breakpoints: &mut BreakpointTable,
+        debug_data: &DwarfData,
+    ) -> Result<Status, nix::Error> {
+        self.breakpoints_hit.clear();
+        self.watchpoints_hit.clear();
+        self.thread_switched = false;
+        loop {
+            // we are at a breakpoint, need to step over it before continuing
+            match self.step_over_breakpoint()? {
+                Some(status @ Status::Exited(_)) | Some(status @ Status::Signaled(_)) => {
+                    return Ok(status);
+                }
+                // The instruction we stepped over may have triggered a watchpoint
+                Some(status) if self.check_watchpoints(breakpoints)? == Some(true) => {
+                    return Ok(status);
+                }
+                _ => {}
+            }
+
+            self.resume_all()?;
+            let (tid, status) = self.wait_event()?;
+            if let Status::Stopped(_, _) = status {
+                self.stop_all_threads()?;
+                if tid != self.current {
+                    self.current = tid;
+                    self.thread_switched = true;
+                }
+            }
+            if let Status::Stopped(sig, _) = status {
+                // Signals the user doesn't want to stop for, like a forked child exiting, are
+                // passed on (or not) without bothering them
+                if sig != signal::Signal::SIGTRAP && !self.handle_signal(tid, sig) {
+                    continue;
+                }
+            }
+            let addr = match status {
+                Status::Stopped(signal::Signal::SIGTRAP, rip) if self.breakpoints.contains_key(&(rip - 1)) => rip - 1,
+                Status::Stopped(signal::Signal::SIGTRAP, _) => match self.check_watchpoints(breakpoints)? {
+                    Some(false) => continue,
+                    _ => return Ok(status),
+                },
+                _ => return Ok(status),
+            };
+            // We hit a breakpoint. Rewind rip so that it points at the breakpoint address (i.e. at
+            // the instruction we replaced) instead of just past the 0xcc.
+            let mut regs = ptrace::getregs(self.tid())?;
             regs.rip = addr as u64;
-            ptrace::setregs(self.pid(), regs).expect("Failed to set registers");
-            // single step
-            let _ = ptrace::step(self.pid(), None);
-            let _ = self.wait(None);
+            ptrace::setregs(self.tid(), regs)?;
 
-            // reset the breakpoint
-            let _ = self.write_byte(addr, 0xcc).expect("Failed to set breakpoint");
+            if self.solib_breakpoint == Some(addr) {
+                // Stop so that the debugger can load the libraries' symbols and insert breakpoints
+                // in them, unless we are in the middle of `next` or `finish`
+                self.libraries_changed = true;
+                if self.temporary_breakpoint.is_none() {
+                    return Ok(Status::Stopped(signal::Signal::SIGTRAP, addr));
+                }
+                continue;
+            }
+            // Conditions are all evaluated in the innermost frame, so unwind it once for this trap
+            let frame = OnceCell::new();
+            let ids = breakpoints.check_hit(addr, |expr| {
+                let frame = frame.get_or_init(|| self.unwound_frame()).as_ref()?;
+                self.evaluate_in_frame(expr, debug_data, frame)
+            });
+            // The temporary breakpoint only counts in the thread it was planted for
+            let internal = match self.temporary_breakpoint {
+                Some((temporary, tid)) if temporary == addr => tid == self.current,
+                _ => !breakpoints.is_enabled_at(addr),
+            };
+            if !ids.is_empty() || internal {
+                self.breakpoints_hit = ids;
+                return Ok(Status::Stopped(signal::Signal::SIGTRAP, addr));
+            }
         }
+    }
+
+    /// Executes a single machine instruction.
+    pub fn step_instruction(&mut self, breakpoints: &mut BreakpointTable) -> Result<Status, nix::Error> {
+        self.breakpoints_hit.clear();
+        self.watchpoints_hit.clear();
+        self.thread_switched = false;
+        let status = match self.step_over_breakpoint()? {
+            Some(status) => status,
+            None => self.single_step()?,
+        };
+        if let Status::Stopped(signal::Signal::SIGTRAP, _) = status {
+            self.check_watchpoints(breakpoints)?;
+        }
+        Ok(status)
+    }
 
-        let _ = ptrace::cont(self.pid(), None);
-        self.wait(None)
+    /// Steps until the inferior reaches a different source line, entering any called functions
+    /// that have debug info. Calls into code without line information (e.g. libc) are stepped
+    /// over.
+    pub fn step_line(
+        &mut self,
+        breakpoints: &mut BreakpointTable,
+        debug_data: &DwarfData,
+    ) -> Result<Status, nix::Error> {
+        self.step_line_impl(breakpoints, debug_data, true)
+    }
+
+    /// Steps until the inferior reaches a different source line in the current function, running
+    /// any called functions to completion.
+    pub fn next_line(
+        &mut self,
+        breakpoints: &mut BreakpointTable,
+        debug_data: &DwarfData,
+    ) -> Result<Status, nix::Error> {
+        self.step_line_impl(breakpoints, debug_data, false)
+    }
+
+    fn step_line_impl(
+        &mut self,
+        breakpoints: &mut BreakpointTable,
+        debug_data: &DwarfData,
+        step_into: bool,
+    ) -> Result<Status, nix::Error> {
+        let regs = ptrace::getregs(self.tid())?;
+        let start_line = debug_data.get_line_from_addr(regs.rip as usize);
+        if start_line.is_none() {
+            // Without line information there is no line to step to, and code without debug info
+            // (e.g. libc) usually doesn't keep frame pointers we could use to find the caller.
+            return self.cont(breakpoints, debug_data);
+        }
+        loop {
+            let regs = ptrace::getregs(self.tid())?;
+            let (prev_rip, prev_rsp) = (regs.rip as usize, regs.rsp as usize);
+            let status = self.step_instruction(breakpoints)?;
+            let rip = match status {
+                Status::Stopped(signal::Signal::SIGTRAP, rip) if self.watchpoints_hit.is_empty() => rip,
+                _ => return Ok(status),
+            };
+            let mut line = debug_data.get_line_from_addr(rip);
+            if self.just_called(prev_rip, prev_rsp)? {
+                if step_into && line.is_some() {
+                    // Stop where the function body starts, past the prologue. Comparing lines
+                    // would run one-line functions to completion.
+                    return match debug_data.get_body_addr_containing(rip) {
+                        Some(body) if body != rip => self.run_to(body, breakpoints, debug_data),
+                        _ => Ok(status),
+                    };
+                }
+                let status = self.run_until_return(prev_rsp, breakpoints, debug_data)?;
+                // A user breakpoint at the return address stops the step there, like gdb does
+                if !self.breakpoints_hit.is_empty() || !self.returned_to(&status, prev_rsp)? {
+                    return Ok(status);
+                }
+                // The call may return to the first instruction of the next line, e.g. when its
+                // result is unused
+                let ret_addr = ptrace::getregs(self.tid())?.rip as usize;
+                line = debug_data.get_line_from_addr(ret_addr);
+            }
+            match (line, start_line.as_ref()) {
+                (Some(line), Some(start)) if line.number != start.number || line.file != start.file => {
+                    return Ok(status);
+                }
+                (Some(_), _) => {}
+                (None, _) => {
+                    // We returned into code without debug info (e.g. main returned into libc).
+                    return self.cont(breakpoints, debug_data);
+                }
+            }
+        }
+    }
+
+    /// Returns true if the last instruction executed (which was at prev_rip, with the stack
+    /// pointer at prev_rsp) was a call, i.e. it pushed a return address just past itself.
+    fn just_called(&self, prev_rip: usize, prev_rsp: usize) -> Result<bool, nix::Error> {
+        let regs = ptrace::getregs(self.tid())?;
+        let rsp = regs.rsp as usize;
+        if rsp != prev_rsp - size_of::<usize>() {
+            return Ok(false);
+        }
+        let ret_addr = ptrace::read(self.tid(), rsp as ptrace::AddressType)? as usize;
+        Ok(ret_addr > prev_rip && ret_addr <= prev_rip + 16 && regs.rip as usize != ret_addr)
+    }
+
+    /// Runs until the selected thread reaches addr, with a temporary breakpoint there. Stops
+    /// earlier for breakpoints, watchpoints and signals.
+    fn run_to(
+        &mut self,
+        addr: usize,
+        breakpoints: &mut BreakpointTable,
+        debug_data: &DwarfData,
+    ) -> Result<Status, nix::Error> {
+        // A user breakpoint may already be planted there
+        let inserted = !self.breakpoints.contains_key(&addr);
+        if inserted {
+            self.set_breakpoint(addr)?;
+        }
+        self.temporary_breakpoint = Some((addr, self.tid()));
+        let result = self.cont(breakpoints, debug_data);
+        self.temporary_breakpoint = None;
+        if inserted {
+            if let Ok(Status::Stopped(_, _)) = result {
+                self.remove_breakpoint(addr)?;
+            } else {
+                self.breakpoints.remove(&addr);
+            }
+        }
+        result
+    }
+
+    /// Runs until the function that was just called returns to the frame whose stack pointer was
+    /// caller_rsp (before the call pushed its return address). A temporary breakpoint is planted at
+    /// the return address; hits from deeper (recursive) frames are ignored.
+    fn run_until_return(
+        &mut self,
+        caller_rsp: usize,
+        breakpoints: &mut BreakpointTable,
+        debug_data: &DwarfData,
+    ) -> Result<Status, nix::Error> {
+        let ret_addr_location = caller_rsp - size_of::<usize>();
+        let ret_addr = ptrace::read(self.tid(), ret_addr_location as ptrace::AddressType)? as usize;
+        // A user breakpoint may already be planted at the return address
+        let inserted = !self.breakpoints.contains_key(&ret_addr);
+        if inserted {
+            self.set_breakpoint(ret_addr)?;
+        }
+        self.temporary_breakpoint = Some((ret_addr, self.tid()));
+        let result = loop {
+            let status = match self.cont(breakpoints, debug_data) {
+                Ok(status) => status,
+                Err(err) => break Err(err),
+            };
+            match status {
+                Status::Stopped(signal::Signal::SIGTRAP, rip)
+                    if rip == ret_addr && self.breakpoints_hit.is_empty() =>
+                {
+                    let regs = ptrace::getregs(self.tid())?;
+                    if (regs.rsp as usize) < caller_rsp {
+                        continue;
+                    }
+                    break Ok(status);
+                }
+                _ => break Ok(status),
+            }
+        };
+        self.temporary_breakpoint = None;
+        if inserted {
+            if let Ok(Status::Stopped(_, _)) = result {
+                self.remove_breakpoint(ret_addr)?;
+            } else {
+                self.breakpoints.remove(&ret_addr);
+            }
+        }
+        result
+    }
+
+    /// Runs until the function of the selected frame returns to its caller, and prints the value
+    /// it returned.
+    pub fn finish(
+        &mut self,
+        breakpoints: &mut BreakpointTable,
+        debug_data: &DwarfData,
+    ) -> Result<Status, nix::Error> {
+        let level = self.selected_frame();
+        let frame = self.frame_at(level)?;
+        let addr = frame.lookup_addr(level);
+        let function_name = debug_data.get_function_from_addr(addr);
+        // The unwinder gives the caller's rsp as the frame's CFA, or 0 if it has no caller
+        if function_name.is_none() || function_name.as_deref() == Some("main") || frame.cfa == 0 {
+            println!("\"finish\" not meaningful in the outermost frame.");
+            let rip = ptrace::getregs(self.tid())?.rip as usize;
+            return Ok(Status::Stopped(signal::Signal::SIGTRAP, rip));
+        }
+        let caller_rsp = frame.cfa;
+        println!("Run till exit from {}", function_name.unwrap());
+        let status = self.run_until_return(caller_rsp, breakpoints, debug_data)?;
+        let return_type = debug_data
+            .get_function_containing(addr)
+            .and_then(|func| func.return_type.clone());
+        if let (true, Some(return_type)) = (self.returned_to(&status, caller_rsp)?, return_type) {
+            match self.return_value(return_type) {
+                Ok(value) => println!("Value returned: {}", self.format_value(&value, debug_data)),
+                Err(err) => println!("{}", err),
+            }
+        }
+        Ok(status)
+    }
+
+    /// Returns true if status is the stop at the end of run_until_return(caller_rsp), as opposed
+    /// to some other breakpoint, signal or exit that interrupted it.
+    fn returned_to(&self, status: &Status, caller_rsp: usize) -> Result<bool, nix::Error> {
+        match status {
+            Status::Stopped(signal::Signal::SIGTRAP, _) => {
+                let regs = ptrace::getregs(self.tid())?;
+                Ok(regs.rsp as usize >= caller_rsp)
+            }
+            _ => Ok(false),
+        }
     }
 
     pub fn kill(&mut self) -> Result<Status, nix::Error> {
-        let _ = self.child.kill();
-        self.wait(None)
+        signal::kill(self.pid(), signal::Signal::SIGKILL)?;
+        // Reap the other threads until the main thread reports the end of the process
+        loop {
+            match waitpid(None, Some(WaitPidFlag::__WALL))? {
+                WaitStatus::Exited(tid, exit_code) if tid == self.pid => return Ok(Status::Exited(exit_code)),
+                WaitStatus::Signaled(tid, sig, _) if tid == self.pid => return Ok(Status::Signaled(sig)),
+                _ => {}
+            }
+        }
     }
 
-    pub fn set_breakpoint(&mut self, addr: usize) -> Result<u8, nix::Error> {
-        let orig_byte = self.write_byte(addr, 0xcc).expect("Failed to set breakpoint");
-        self.breakpoints.insert(addr, Breakpoint { addr: addr, orig_byte: orig_byte as u8 });
-        Ok(orig_byte as u8)
+    /// Removes every 0xcc byte and watchpoint we put into the process and lets it continue running
+    /// untraced.
+    pub fn detach(&mut self) -> Result<(), nix::Error> {
+        self.solib_breakpoint = None;
+        let addrs: Vec<usize> = self.breakpoints.keys().copied().collect();
+        for addr in addrs {
+            self.remove_breakpoint(addr)?;
+        }
+        for thread in self.threads.iter_mut() {
+            write_debug_register(thread.tid, 7, 0)?;
+            if thread.pending_sigstop {
+                // Swallow the SIGSTOP we sent, which would otherwise stop the process once we're
+                // gone
+                ptrace::cont(thread.tid, None)?;
+                waitpid(thread.tid, Some(WaitPidFlag::__WALL))?;
+            }
+            ptrace::detach(thread.tid, thread.pending_signal.take())?;
+        }
+        Ok(())
+    }
+
+    fn read_debug_register(&self, n: usize) -> Result<u64, nix::Error> {
+        Ok(ptrace::read_user(self.tid(), debug_register_offset(n) as ptrace::AddressType)? as u64)
+    }
+
+    /// Writes our copy of DR0-DR3 and DR7 into a thread's debug registers.
+    fn load_debug_registers(&self, tid: Pid) -> Result<(), nix::Error> {
+        // Disable all slots first, so the kernel never sees a half-updated configuration
+        write_debug_register(tid, 7, 0)?;
+        for (slot, addr) in self.debug_registers.iter().enumerate() {
+            write_debug_register(tid, slot, *addr)?;
+        }
+        write_debug_register(tid, 7, self.dr7)
+    }
+
+    /// Loads the enabled watchpoints into the debug registers: DR0-DR3 hold the watched
+    /// addresses, and DR7 enables each of them with its access type and length. Also records the
+    /// current contents of the watched memory, so we can tell later whether it changed.
+    pub fn update_watchpoints(&mut self, breakpoints: &mut BreakpointTable) -> Result<(), nix::Error> {
+        self.watch_slots = [None; MAX_WATCHPOINTS];
+        self.debug_registers = [0; MAX_WATCHPOINTS];
+        let mut dr7 = 0;
+        for (slot, wp) in breakpoints.enabled_watchpoints_mut().take(MAX_WATCHPOINTS).enumerate() {
+            self.debug_registers[slot] = wp.addr as u64;
+            let rw: u64 = match wp.kind {
+                WatchKind::Write => 0b01,
+                WatchKind::Read | WatchKind::Access => 0b11,
+            };
+            let len: u64 = match wp.len {
+                1 => 0b00,
+                2 => 0b01,
+                8 => 0b10,
+                _ => 0b11,
+            };
+            // Local enable bit, then the RW and LEN fields of this slot
+            dr7 |= 1 << (2 * slot);
+            dr7 |= (rw | (len << 2)) << (16 + 4 * slot);
+            self.watch_slots[slot] = Some(wp.id);
+            wp.value = self.read_memory(wp.addr, wp.len).ok();
+        }
+        self.dr7 = dr7;
+        for thread in &self.threads {
+            self.load_debug_registers(thread.tid)?;
+        }
+        Ok(())
+    }
+
+    /// Checks DR6 after a SIGTRAP to see whether it was caused by our watchpoints. Returns None if
+    /// no watchpoint triggered, Some(true) if the inferior should stop (the hits are recorded in
+    /// watchpoints_hit), or Some(false) if only write watchpoints whose value didn't change
+    /// triggered.
+    fn check_watchpoints(&mut self, breakpoints: &mut BreakpointTable) -> Result<Option<bool>, nix::Error> {
+        let dr6 = self.read_debug_register(6)?;
+        if dr6 & 0xf == 0 {
+            return Ok(None);
+        }
+        // The processor never clears DR6 by itself
+        write_debug_register(self.tid(), 6, 0)?;
+        for slot in 0..MAX_WATCHPOINTS {
+            if dr6 & (1 << slot) == 0 {
+                continue;
+            }
+            let wp = match self.watch_slots[slot].and_then(|id| breakpoints.watchpoint_mut(id)) {
+                Some(wp) => wp,
+                None => continue,
+            };
+            let new_value = self.read_memory(wp.addr, wp.len)?;
+            let changed = wp.value.as_ref() != Some(&new_value);
+            match wp.kind {
+                WatchKind::Write if !changed => continue,
+                // x86 can't trap on reads alone, so like gdb take an access that changed the
+                // value for a write, which a read watchpoint doesn't stop for
+                WatchKind::Read if changed => {
+                    wp.value = Some(new_value);
+                    continue;
+                }
+                _ => {}
+            }
+            wp.hit_count += 1;
+            if wp.ignore_count > 0 {
+                wp.ignore_count -= 1;
+                wp.value = Some(new_value);
+                continue;
+            }
+            self.watchpoints_hit.push(WatchpointHit {
+                id: wp.id,
+                old_value: wp.value.replace(new_value.clone()),
+                new_value,
+            });
+        }
+        Ok(Some(!self.watchpoints_hit.is_empty()))
+    }
+
+    /// Replaces the instruction byte at addr with 0xcc, unless a breakpoint is already there.
+    pub fn set_breakpoint(&mut self, addr: usize) -> Result<(), nix::Error> {
+        if !self.breakpoints.contains_key(&addr) {
+            let orig_byte = self.write_byte(addr, 0xcc)?;
+            self.breakpoints.insert(addr, Breakpoint { orig_byte });
+        }
+        Ok(())
     }
 
-    pub fn get_breakpoints_count(&mut self) -> usize {
-        self.breakpoints.len()
+    /// Restores the original instruction byte at addr.
+    pub fn remove_breakpoint(&mut self, addr: usize) -> Result<(), nix::Error> {
+        // A user breakpoint may share its address with the one we need to follow the libraries
+        if self.solib_breakpoint == Some(addr) {
+            return Ok(());
+        }
+        if let Some(bp) = self.breakpoints.remove(&addr) {
+            self.write_byte(addr, bp.orig_byte)?;
+        }
+        Ok(())
     }
 
     fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, nix::Error> {
         let aligned_addr = align_addr_to_word(addr);
         let byte_offset = addr - aligned_addr;
-        let word = ptrace::read(self.pid(), aligned_addr as ptrace::AddressType)? as u64;
-        let orig_byte = (word >> 8 * byte_offset) & 0xff;
-        let masked_word = word & !(0xff << 8 * byte_offset);
-        let updated_word = masked_word | ((val as u64) << 8 * byte_offset);
+        let word = ptrace::read(self.tid(), aligned_addr as ptrace::AddressType)? as u64;
+        let orig_byte = (word >> (8 * byte_offset)) & 0xff;
+        let masked_word = word & !(0xff << (8 * byte_offset));
+        let updated_word = masked_word | ((val as u64) << (8 * byte_offset));
         unsafe {
             ptrace::write(
-                self.pid(),
+                self.tid(),
                 aligned_addr as ptrace::AddressType,
                 updated_word as *mut std::ffi::c_void,
             )?;
@@ -176,3 +1284,77 @@ impl Inferior {
         Ok(orig_byte as u8)
     }
 }
+
+impl Target for Inferior {
+    /// Returns the registers of the selected thread in its innermost frame.
+    fn innermost_registers(&self) -> Result<libc::user_regs_struct, nix::Error> {
+        ptrace::getregs(self.tid())
+    }
+
+    /// Returns the x87 and SSE registers of the selected thread.
+    fn fp_registers(&self) -> Result<libc::user_fpregs_struct, nix::Error> {
+        let mut fpregs = std::mem::MaybeUninit::<libc::user_fpregs_struct>::uninit();
+        let res = unsafe {
+            libc::ptrace(
+                libc::PTRACE_GETFPREGS,
+                self.tid().as_raw(),
+                std::ptr::null_mut::<libc::c_void>(),
+                fpregs.as_mut_ptr(),
+            )
+        };
+        nix::Error::result(res)?;
+        Ok(unsafe { fpregs.assume_init() })
+    }
+
+    /// Reads the inferior's memory through /proc/<pid>/mem, which takes a
+    /// single system call for the whole range instead of one ptrace call per word.
+    fn read_memory_partial(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mem = File::open(format!("/proc/{}/mem", self.tid()))
+            .map_err(|err| nix::Error::from_i32(err.raw_os_error().unwrap_or(0)))?;
+        let mut bytes = vec![0u8; len];
+        let mut done = 0;
+        while done < len {
+            match mem.read_at(&mut bytes[done..], (addr + done) as u64) {
+                Ok(0) | Err(_) => break,
+                Ok(count) => done += count,
+            }
+        }
+        bytes.truncate(done);
+        Ok(bytes)
+    }
+
+    /// Reads up to len bytes of the inferior's code like read_memory_partial, but with the
+    /// original instructions in place of the 0xcc bytes of our breakpoints.
+    fn read_code(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = self.read_memory_partial(addr, len)?;
+        for (bp_addr, bp) in &self.breakpoints {
+            if (addr..addr + bytes.len()).contains(bp_addr) {
+                bytes[bp_addr - addr] = bp.orig_byte;
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn memory_map(&self) -> Vec<Region> {
+        read_maps(self.pid)
+    }
+
+    /// Returns the details the kernel recorded about the signal the selected thread stopped with,
+    /// such as the address that caused a fault.
+    fn signal_info(&self) -> Result<libc::siginfo_t, nix::Error> {
+        ptrace::getsiginfo(self.tid())
+    }
+
+    /// Returns the level of the selected stack frame.
+    fn selected_frame(&self) -> usize {
+        self.selected_frame
+    }
+
+    fn set_selected_frame(&mut self, level: usize) {
+        self.selected_frame = level;
+    }
+
+    fn unwinder(&self) -> &RefCell<Unwinder> {
+        &self.unwinder
+    }
+}
@@ -0,0 +1,92 @@
+//! Where the program's code is loaded. Position-independent executables and shared libraries are
+//! loaded at an offset (the load bias) from the addresses they were linked for. The executable's
+//! bias comes from the memory map, and the libraries are found in the dynamic linker's list of
+//! loaded objects (struct r_debug and struct link_map in <link.h>).
+
+use crate::target::{Region, Target};
+use object::{Object, ObjectSection, ObjectSegment, ObjectSymbol};
+use std::convert::TryInto;
+use std::fs;
+
+/// Offset in struct r_debug of r_map, the first struct link_map.
+const R_MAP: usize = 8;
+/// Offsets in struct link_map of l_addr (the load bias), l_name and l_next.
+const L_ADDR: usize = 0;
+const L_NAME: usize = 8;
+const L_NEXT: usize = 24;
+
+/// Gives up on the list of loaded objects after this many, in case it is corrupted into a cycle.
+const MAX_LIBRARIES: usize = 1024;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Where the dynamic linker keeps its list of loaded objects, and the function it calls whenever
+/// it changes the list.
+pub struct DynamicLinker {
+    pub r_debug: usize,
+    pub breakpoint: usize,
+}
+
+/// Returns true if a region of the memory map is the file at path, which may be a symlink.
+fn maps_file(region: &Region, path: &str) -> bool {
+    match (fs::canonicalize(&region.path), fs::canonicalize(path)) {
+        (Ok(mapped), Ok(path)) => mapped == path,
+        _ => false,
+    }
+}
+
+/// Works out the load bias of an ELF file from the memory map: the first page of the file is
+/// mapped at the lowest address, which was linked for the address of its first segment.
+pub fn load_bias(regions: &[Region], path: &str) -> Option<usize> {
+    let data = fs::read(path).ok()?;
+    let file = object::File::parse(&*data).ok()?;
+    let linked: usize = file.segments().map(|segment| segment.address()).min()?.try_into().ok()?;
+    let start = regions
+        .iter()
+        .filter(|region| maps_file(region, path))
+        .map(|region| region.start)
+        .min()?;
+    Some(start.wrapping_sub(linked & !(PAGE_SIZE - 1)))
+}
+
+/// Finds the dynamic linker named in the executable's PT_INTERP and the addresses of its
+/// `_r_debug` and `_dl_debug_state` symbols. Returns None for statically linked programs, or if
+/// the dynamic linker isn't mapped yet.
+pub fn find_dynamic_linker(program: &str, regions: &[Region]) -> Option<DynamicLinker> {
+    let data = fs::read(program).ok()?;
+    let file = object::File::parse(&*data).ok()?;
+    let interp = file.section_by_name(".interp")?.data().ok()?;
+    let interp = String::from_utf8_lossy(interp).trim_end_matches('\0').to_string();
+    let bias = load_bias(regions, &interp)?;
+
+    let data = fs::read(&interp).ok()?;
+    let linker = object::File::parse(&*data).ok()?;
+    let symbol = |name: &str| -> Option<usize> {
+        let symbol = linker.dynamic_symbols().find(|symbol| symbol.name() == Ok(name))?;
+        Some(bias.wrapping_add(symbol.address().try_into().ok()?))
+    };
+    Some(DynamicLinker {
+        r_debug: symbol("_r_debug")?,
+        breakpoint: symbol("_dl_debug_state")?,
+    })
+}
+
+/// Walks the dynamic linker's list of loaded objects, returning the path and load bias of each
+/// shared library. The executable and the vDSO don't have a path in the list and are left out.
+/// The list is empty until the dynamic linker has run. Returns None if it can't be read.
+pub fn loaded_libraries<T: Target + ?Sized>(target: &T, r_debug: usize) -> Option<Vec<(String, usize)>> {
+    let mut libraries = Vec::new();
+    let mut link_map = target.read_word(r_debug + R_MAP)?;
+    while link_map != 0 && libraries.len() < MAX_LIBRARIES {
+        let bias = target.read_word(link_map + L_ADDR)?;
+        let name = target
+            .read_word(link_map + L_NAME)
+            .and_then(|addr| target.read_c_string(addr, libc::PATH_MAX as usize).ok())
+            .unwrap_or_default();
+        if name.starts_with('/') {
+            libraries.push((name, bias));
+        }
+        link_map = target.read_word(link_map + L_NEXT)?;
+    }
+    Some(libraries)
+}
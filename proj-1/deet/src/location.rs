@@ -0,0 +1,276 @@
+//! Evaluation of DWARF location expressions, which say where a variable is at a particular point
+//! in an optimized program: in memory, in a register, split into pieces, or nowhere at all.
+
+use crate::dwarf_data::{DwarfData, DwarfExpression, Encoding, Location, TypeKind};
+use crate::target::Target;
+use crate::unwind::Frame;
+use gimli::{EndianSlice, EvaluationResult, LittleEndian, Register, Value, ValueType};
+
+/// Names of the general purpose registers by DWARF register number.
+const DWARF_REGISTERS: [&str; 17] = [
+    "rax", "rdx", "rcx", "rbx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip",
+];
+
+/// DWARF register number of xmm0; xmm1-xmm15 follow it.
+const XMM0: u16 = 17;
+
+/// Where a variable's value can be found.
+pub enum Place {
+    Memory(usize),
+    /// The value isn't stored in memory as a whole, e.g. because it's in a register or is a
+    /// constant. These are its bytes.
+    Computed(Vec<u8>),
+    OptimizedOut,
+}
+
+/// Finds a variable of `size` bytes with the given location in `frame`, the stack frame at
+/// `level`.
+pub fn locate<T: Target + ?Sized>(
+    target: &T,
+    debug_data: &DwarfData,
+    location: &Location,
+    frame: &Frame,
+    level: usize,
+    size: usize,
+) -> Result<Place, String> {
+    Ok(match location {
+        Location::Address(addr) => Place::Memory(*addr),
+        Location::FramePointerOffset(offset) => {
+            // The frame base is the canonical frame address, i.e. the value of rsp before the
+            // call instruction pushed the return address.
+            Place::Memory((frame.frame_base() as isize + offset) as usize)
+        }
+        Location::Expression(expr) => evaluate(target, debug_data, expr, frame, level, size)?,
+        Location::List(ranges) => {
+            let pc = frame.lookup_addr(level);
+            match ranges.iter().find(|(start, end, _)| *start <= pc && pc < *end) {
+                Some((_, _, expr)) => evaluate(target, debug_data, expr, frame, level, size)?,
+                None => Place::OptimizedOut,
+            }
+        }
+        Location::Constant(bytes) => Place::Computed(resized(bytes.clone(), size)),
+        Location::OptimizedOut => Place::OptimizedOut,
+    })
+}
+
+fn resized(mut bytes: Vec<u8>, size: usize) -> Vec<u8> {
+    bytes.resize(size, 0);
+    bytes
+}
+
+fn cannot_access(addr: u64) -> String {
+    format!("Cannot access memory at address {:#x}", addr)
+}
+
+fn word(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    let len = bytes.len().min(8);
+    buf[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(buf)
+}
+
+/// Runs a DWARF expression in `frame`, the stack frame at `level`, and works out where its
+/// result is.
+fn evaluate<T: Target + ?Sized>(
+    target: &T,
+    debug_data: &DwarfData,
+    expr: &DwarfExpression,
+    frame: &Frame,
+    level: usize,
+    size: usize,
+) -> Result<Place, String> {
+    let unsupported = |err: gimli::Error| format!("Unsupported DWARF location expression: {}", err);
+    let mut evaluation =
+        gimli::Expression(EndianSlice::new(&expr.bytes, LittleEndian)).evaluation(expr.encoding);
+    let mut result = evaluation.evaluate().map_err(unsupported)?;
+    loop {
+        result = match result {
+            EvaluationResult::Complete => break,
+            EvaluationResult::RequiresMemory { address, size, .. } => {
+                let bytes = target
+                    .read_memory(address as usize, size as usize)
+                    .map_err(|_| cannot_access(address))?;
+                evaluation.resume_with_memory(Value::Generic(word(&bytes)))
+            }
+            EvaluationResult::RequiresRegister { register, .. } => {
+                match register_bytes(target, frame, level, register)? {
+                    Some(bytes) => evaluation.resume_with_register(Value::Generic(word(&bytes))),
+                    None => return Ok(Place::OptimizedOut),
+                }
+            }
+            // gcc makes the frame base of every function its CFA, LLVM a register
+            EvaluationResult::RequiresFrameBase => match expr.frame_base {
+                Some(register) => match register_bytes(target, frame, level, Register(register))? {
+                    Some(bytes) => evaluation.resume_with_frame_base(word(&bytes)),
+                    None => return Ok(Place::OptimizedOut),
+                },
+                None => evaluation.resume_with_frame_base(frame.frame_base() as u64),
+            },
+            EvaluationResult::RequiresCallFrameCfa => {
+                evaluation.resume_with_call_frame_cfa(frame.frame_base() as u64)
+            }
+            EvaluationResult::RequiresEntryValue(entry) => {
+                match entry_value(target, debug_data, entry.0, expr, frame, level)? {
+                    Some(value) => evaluation.resume_with_entry_value(Value::Generic(value)),
+                    None => return Ok(Place::OptimizedOut),
+                }
+            }
+            EvaluationResult::RequiresRelocatedAddress(addr) => {
+                evaluation.resume_with_relocated_address(addr.wrapping_add(expr.bias as u64))
+            }
+            EvaluationResult::RequiresBaseType(offset) => {
+                let value_type = value_type(debug_data, expr.unit_offset + offset.0);
+                evaluation.resume_with_base_type(value_type)
+            }
+            _ => return Err("Unsupported DWARF location expression".to_string()),
+        }
+        .map_err(unsupported)?;
+    }
+
+    let pieces = evaluation.result();
+    if let [piece] = pieces.as_slice() {
+        if let gimli::Location::Address { address } = piece.location {
+            return Ok(Place::Memory(address as usize));
+        }
+    }
+    // Put the value together from its pieces
+    let mut bytes = Vec::new();
+    for piece in &pieces {
+        let len = match piece.size_in_bits {
+            Some(bits) => (bits as usize).div_ceil(8),
+            None => size.saturating_sub(bytes.len()),
+        };
+        let piece_bytes = match &piece.location {
+            gimli::Location::Register { register } => {
+                match register_bytes(target, frame, level, *register)? {
+                    Some(bytes) => bytes,
+                    None => return Ok(Place::OptimizedOut),
+                }
+            }
+            gimli::Location::Address { address } => target
+                .read_memory(*address as usize, len)
+                .map_err(|_| cannot_access(*address))?,
+            gimli::Location::Value { value } => value_bytes(*value),
+            gimli::Location::Bytes { value } => value.to_vec(),
+            // Pointers to values that were optimized away also count as optimized out
+            gimli::Location::Empty | gimli::Location::ImplicitPointer { .. } => {
+                return Ok(Place::OptimizedOut)
+            }
+        };
+        bytes.extend(resized(piece_bytes, len));
+    }
+    Ok(Place::Computed(resized(bytes, size)))
+}
+
+/// Returns the contents of a register in the frame at `level`, or None if it can't be known.
+/// Only the innermost frame has all of its registers; in its callers, only those that functions
+/// must preserve can be recovered.
+fn register_bytes<T: Target + ?Sized>(
+    target: &T,
+    frame: &Frame,
+    level: usize,
+    register: Register,
+) -> Result<Option<Vec<u8>>, String> {
+    if level > 0 {
+        return Ok(frame.register(register).map(|value| value.to_le_bytes().to_vec()));
+    }
+    if let Some(name) = DWARF_REGISTERS.get(register.0 as usize) {
+        let regs = target.innermost_registers().map_err(|err| err.to_string())?;
+        let value = crate::registers::get_register(&regs, name).unwrap_or(0);
+        return Ok(Some(value.to_le_bytes().to_vec()));
+    }
+    if (XMM0..XMM0 + 16).contains(&register.0) {
+        let fpregs = target.fp_registers().map_err(|err| err.to_string())?;
+        let n = (register.0 - XMM0) as usize;
+        let words = &fpregs.xmm_space[n * 4..n * 4 + 4];
+        return Ok(Some(words.iter().flat_map(|word| word.to_le_bytes()).collect()));
+    }
+    Ok(None)
+}
+
+fn value_bytes(value: Value) -> Vec<u8> {
+    match value {
+        Value::F32(value) => value.to_bits().to_le_bytes().to_vec(),
+        Value::F64(value) => value.to_bits().to_le_bytes().to_vec(),
+        value => value.to_u64(!0).unwrap_or(0).to_le_bytes().to_vec(),
+    }
+}
+
+/// Describes the base type at a section offset to the evaluator, for typed operations like
+/// DW_OP_convert.
+fn value_type(debug_data: &DwarfData, offset: usize) -> ValueType {
+    let base = match debug_data.get_type(offset) {
+        Some(base) => base,
+        None => return ValueType::Generic,
+    };
+    let signed = match base.kind {
+        TypeKind::Base(Encoding::Float) => {
+            return match base.size {
+                4 => ValueType::F32,
+                8 => ValueType::F64,
+                _ => ValueType::Generic,
+            }
+        }
+        TypeKind::Base(Encoding::Signed) | TypeKind::Base(Encoding::SignedChar) => true,
+        _ => false,
+    };
+    match (base.size, signed) {
+        (1, true) => ValueType::I8,
+        (1, false) => ValueType::U8,
+        (2, true) => ValueType::I16,
+        (2, false) => ValueType::U16,
+        (4, true) => ValueType::I32,
+        (4, false) => ValueType::U32,
+        (8, true) => ValueType::I64,
+        (8, false) => ValueType::U64,
+        _ => ValueType::Generic,
+    }
+}
+
+/// Works out DW_OP_entry_value: the value a register had when the function in `frame`, the stack
+/// frame at `level`, was called. If it hasn't run past its first instruction, that is the
+/// register's current value. Otherwise the caller's debug info for the call may say what it passed
+/// in the register. Returns None if the value can't be known.
+fn entry_value<T: Target + ?Sized>(
+    target: &T,
+    debug_data: &DwarfData,
+    entry: EndianSlice<LittleEndian>,
+    expr: &DwarfExpression,
+    frame: &Frame,
+    level: usize,
+) -> Result<Option<u64>, String> {
+    // Only the common form, DW_OP_entry_value(DW_OP_regN), is supported
+    let mut ops = entry;
+    let register = match gimli::Operation::parse(&mut ops, expr.encoding) {
+        Ok(gimli::Operation::Register { register }) if ops.is_empty() => register,
+        _ => return Ok(None),
+    };
+    let at_entry = debug_data
+        .get_function_containing(frame.lookup_addr(level))
+        .is_some_and(|func| func.address == frame.rip);
+    if at_entry {
+        let bytes = register_bytes(target, frame, level, register)?;
+        return Ok(bytes.map(|bytes| word(&bytes)));
+    }
+    let caller = match target.frame_at(level + 1) {
+        Ok(caller) => caller,
+        Err(_) => return Ok(None),
+    };
+    // The caller's return address identifies the call
+    let value = debug_data
+        .get_function_containing(caller.lookup_addr(level + 1))
+        .and_then(|func| func.call_sites.iter().find(|site| site.return_addr == caller.rip))
+        .and_then(|site| site.parameters.iter().find(|(reg, _)| *reg == register.0))
+        .map(|(_, value)| value);
+    let value = match value {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    // The expression computes the value itself, which the evaluator reports as an address
+    Ok(match evaluate(target, debug_data, value, &caller, level + 1, 8)? {
+        Place::Memory(value) => Some(value as u64),
+        Place::Computed(bytes) => Some(word(&bytes)),
+        Place::OptimizedOut => None,
+    })
+}
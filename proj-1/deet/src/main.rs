@@ -1,24 +1,122 @@
+mod breakpoint;
+mod core_file;
+mod crash;
 mod debugger;
 mod debugger_command;
 mod inferior;
 mod dwarf_data;
+mod disassemble;
+mod examine;
+mod expr;
+mod gdbserver;
 mod gimli_wrapper;
+mod libraries;
+mod location;
+mod registers;
+mod signals;
+mod source;
+mod target;
+mod unwind;
 
 use crate::debugger::Debugger;
+use crate::debugger_command::DebuggerCommand;
 use nix::sys::signal::{signal, SigHandler, Signal};
+use nix::unistd::Pid;
 use std::env;
 
+/// Takes the options that script the session out of the command line: commands from
+/// "-ex COMMAND" and "-x FILE", in the order given, and whether to quit after them ("-batch").
+fn parse_script_options(args: Vec<String>) -> (Vec<String>, Vec<String>, bool) {
+    let mut rest = Vec::new();
+    let mut commands = Vec::new();
+    let mut batch = false;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-ex" | "-x" => match args.next() {
+                Some(file) if arg == "-x" => commands.push(format!("source {}", file)),
+                Some(command) => commands.push(command),
+                None => {
+                    println!("option '{}' requires an argument", arg);
+                    std::process::exit(1);
+                }
+            },
+            "-batch" => batch = true,
+            _ => rest.push(arg),
+        }
+    }
+    (rest, commands, batch)
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <target program>", args[0]);
-        std::process::exit(1);
+    if args.len() >= 4 && args[1] == "--gdbserver" {
+        return serve(&args[2], &args[3], &args[4..]);
     }
-    let target = &args[1];
+    let (args, commands, batch) = parse_script_options(args);
+    // Either a program to start later with "run", "-p <pid>" to attach to a running process, or
+    // a program and its core dump
+    let (target, pid, core) = match args.len() {
+        2 => (args[1].clone(), None, None),
+        3 if args[1] == "-p" => match args[2].parse() {
+            Ok(pid) => {
+                let pid = Pid::from_raw(pid);
+                match std::fs::read_link(format!("/proc/{}/exe", pid)) {
+                    Ok(exe) => (exe.to_string_lossy().into_owned(), Some(pid), None),
+                    Err(err) => {
+                        println!("Could not find the program run by process {}: {}", pid, err);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            Err(_) => {
+                println!("Invalid pid {}", args[2]);
+                std::process::exit(1);
+            }
+        },
+        4 if args[2] == "--core" => (args[1].clone(), None, Some(args[3].clone())),
+        _ => {
+            println!("Usage: {} [options] <target program>", args[0]);
+            println!("       {} [options] -p <pid>", args[0]);
+            println!("       {} [options] <target program> --core <core file>", args[0]);
+            println!("       {} --gdbserver [host]:<port> <target program> [args...]", args[0]);
+            println!("Options: -ex <command>, -x <command file>, -batch");
+            std::process::exit(1);
+        }
+    };
 
     // Disable handling of ctrl+c in this process (so that ctrl+c only gets delivered to child
     // processes)
     unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
 
-    Debugger::new(target).run();
+    let mut debugger = Debugger::new(&target);
+    if !batch {
+        debugger.print_debug_data();
+    }
+    debugger.queue_commands(commands, batch);
+    if let Some(pid) = pid {
+        debugger.attach(pid);
+    }
+    if let Some(core) = core {
+        debugger.load_core(&core);
+    }
+    debugger.run();
+}
+
+/// Starts the program stopped at its first instruction and lets a gdb client drive it, like
+/// gdbserver. Exits once the client is done with it.
+fn serve(address: &str, target: &str, args: &[String]) {
+    let mut debugger = Debugger::new(target);
+    debugger.print_debug_data();
+    if !debugger.start(args) {
+        std::process::exit(1);
+    }
+    if let Some(inferior) = debugger.inferior_mut() {
+        println!("Process {} created; pid = {}", target, inferior.pid());
+    }
+    if let Err(err) = gdbserver::serve(&mut debugger, address) {
+        println!("{}", err);
+    }
+    // Kills the inferior if the client left it running
+    debugger.execute(DebuggerCommand::Quit);
 }
@@ -0,0 +1,226 @@
+//! Names and formatting of the x86-64 registers, as read with PTRACE_GETREGS and
+//! PTRACE_GETFPREGS.
+
+use libc::{user_fpregs_struct, user_regs_struct};
+use std::fmt;
+
+/// Registers shown by `info registers`, in the order gdb shows them.
+pub const GENERAL_REGISTERS: [&str; 26] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip", "eflags", "cs", "ss", "ds", "es", "fs", "gs", "fs_base", "gs_base",
+];
+
+/// Names of the bits of eflags that gdb shows, by bit number.
+const EFLAGS: [(u32, &str); 9] = [
+    (0, "CF"),
+    (2, "PF"),
+    (4, "AF"),
+    (6, "ZF"),
+    (7, "SF"),
+    (8, "TF"),
+    (9, "IF"),
+    (10, "DF"),
+    (11, "OF"),
+];
+
+/// Names of the exception flag and mask bits of mxcsr, by bit number.
+const MXCSR_FLAGS: [(u32, &str); 16] = [
+    (0, "IE"),
+    (1, "DE"),
+    (2, "ZE"),
+    (3, "OE"),
+    (4, "UE"),
+    (5, "PE"),
+    (6, "DAZ"),
+    (7, "IM"),
+    (8, "DM"),
+    (9, "ZM"),
+    (10, "OM"),
+    (11, "UM"),
+    (12, "PM"),
+    (13, "R-"),
+    (14, "R+"),
+    (15, "FZ"),
+];
+
+/// Returns the field of a general purpose, flags or segment register, given its name without the
+/// leading "$".
+fn register_mut<'a>(regs: &'a mut user_regs_struct, name: &str) -> Option<&'a mut u64> {
+    Some(match name {
+        "rax" => &mut regs.rax,
+        "rbx" => &mut regs.rbx,
+        "rcx" => &mut regs.rcx,
+        "rdx" => &mut regs.rdx,
+        "rsi" => &mut regs.rsi,
+        "rdi" => &mut regs.rdi,
+        "rbp" | "fp" => &mut regs.rbp,
+        "rsp" | "sp" => &mut regs.rsp,
+        "r8" => &mut regs.r8,
+        "r9" => &mut regs.r9,
+        "r10" => &mut regs.r10,
+        "r11" => &mut regs.r11,
+        "r12" => &mut regs.r12,
+        "r13" => &mut regs.r13,
+        "r14" => &mut regs.r14,
+        "r15" => &mut regs.r15,
+        "rip" | "pc" => &mut regs.rip,
+        "eflags" => &mut regs.eflags,
+        "cs" => &mut regs.cs,
+        "ss" => &mut regs.ss,
+        "ds" => &mut regs.ds,
+        "es" => &mut regs.es,
+        "fs" => &mut regs.fs,
+        "gs" => &mut regs.gs,
+        "fs_base" => &mut regs.fs_base,
+        "gs_base" => &mut regs.gs_base,
+        "orig_rax" => &mut regs.orig_rax,
+        _ => return None,
+    })
+}
+
+/// Returns the value of a general purpose, flags or segment register.
+pub fn get_register(regs: &user_regs_struct, name: &str) -> Option<u64> {
+    let mut regs = *regs;
+    register_mut(&mut regs, name).copied()
+}
+
+/// Changes the value of a general purpose, flags or segment register. Returns false if there is
+/// no such register.
+pub fn set_register(regs: &mut user_regs_struct, name: &str, value: u64) -> bool {
+    match register_mut(regs, name) {
+        Some(register) => {
+            *register = value;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Lists the names of the bits that are set, e.g. "[ ZF PF IF ]".
+fn format_flags(value: u64, names: &[(u32, &str)]) -> String {
+    let set: Vec<&str> = names
+        .iter()
+        .filter(|(bit, _)| value & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    format!("[ {} ]", set.join(" "))
+}
+
+/// Formats the value of a register read with get_register the way `info registers` shows it:
+/// hex followed by a more natural representation. `symbolize` turns a code address into
+/// "<func+offset>".
+pub fn format_register(name: &str, value: u64, symbolize: &dyn Fn(u64) -> Option<String>) -> String {
+    let natural = match name {
+        "rip" | "pc" => match symbolize(value) {
+            Some(symbol) => format!("{:#x} {}", value, symbol),
+            None => format!("{:#x}", value),
+        },
+        "rbp" | "fp" | "rsp" | "sp" | "fs_base" | "gs_base" => format!("{:#x}", value),
+        "eflags" => format_flags(value, &EFLAGS),
+        _ => (value as i64).to_string(),
+    };
+    format!("{:<15}{:<18} {}", name, format!("{:#x}", value), natural)
+}
+
+/// Converts an 80-bit x87 extended precision number, stored little-endian, to the nearest f64.
+pub fn extended_to_f64(bytes: &[u8]) -> f64 {
+    let mut mantissa = [0u8; 8];
+    mantissa.copy_from_slice(&bytes[..8]);
+    let mantissa = u64::from_le_bytes(mantissa);
+    let sign_exponent = u16::from_le_bytes([bytes[8], bytes[9]]);
+    let sign = if sign_exponent & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (sign_exponent & 0x7fff) as i32;
+    let value = match exponent {
+        0 if mantissa == 0 => 0.0,
+        0x7fff if mantissa << 1 == 0 => f64::INFINITY,
+        0x7fff => f64::NAN,
+        // The mantissa has an explicit integer bit, so it is mantissa / 2^63 * 2^(exp - bias).
+        // Denormals have an exponent of 1 - bias rather than 0 - bias.
+        _ => (mantissa as f64) * 2f64.powi(exponent.max(1) - 16383 - 63),
+    };
+    sign * value
+}
+
+/// Formats a floating point number like C's %g: very large and very small numbers are shown in
+/// scientific notation rather than with all their digits.
+pub fn format_float<T: Into<f64> + fmt::Display + fmt::LowerExp + Copy>(value: T) -> String {
+    let magnitude = value.into().abs();
+    if magnitude == 0.0 || !magnitude.is_finite() || (1e-4..1e16).contains(&magnitude) {
+        value.to_string()
+    } else {
+        format!("{:e}", value)
+    }
+}
+
+/// Returns the bytes of the FXSAVE area that holds st0-st7 or xmm0-xmm15.
+pub fn fp_bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+/// Names of the x87 and SSE registers that `info all-registers` shows after the general ones.
+pub fn fp_register_names() -> Vec<String> {
+    let mut names: Vec<String> = (0..8).map(|n| format!("st{}", n)).collect();
+    for name in ["fctrl", "fstat", "ftag", "fop", "fioff", "fooff"] {
+        names.push(name.to_string());
+    }
+    names.extend((0..16).map(|n| format!("xmm{}", n)));
+    names.push("mxcsr".to_string());
+    names
+}
+
+/// Formats an x87 or SSE register the way `info registers` shows it, or returns None if there is
+/// no such register.
+pub fn format_fp_register(fpregs: &user_fpregs_struct, name: &str) -> Option<String> {
+    let hex = |value: u64| format!("{:#x}", value);
+    let (raw, natural) = if let Some(n) = name.strip_prefix("st") {
+        let n: usize = n.parse().ok().filter(|n| *n < 8)?;
+        // Each register occupies 16 bytes, of which the first 10 are used
+        let bytes = fp_bytes(&fpregs.st_space)[n * 16..n * 16 + 10].to_vec();
+        let raw: String = bytes.iter().rev().map(|byte| format!("{:02x}", byte)).collect();
+        (format!("0x{}", raw.trim_start_matches('0')), format_float(extended_to_f64(&bytes)))
+    } else if let Some(n) = name.strip_prefix("xmm") {
+        let n: usize = n.parse().ok().filter(|n| *n < 16)?;
+        let bytes = fp_bytes(&fpregs.xmm_space)[n * 16..n * 16 + 16].to_vec();
+        let floats: Vec<String> = bytes
+            .chunks(4)
+            .map(|chunk| format_float(f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])))
+            .collect();
+        let doubles: Vec<String> = bytes
+            .chunks(8)
+            .map(|chunk| {
+                let mut double = [0u8; 8];
+                double.copy_from_slice(chunk);
+                format_float(f64::from_le_bytes(double))
+            })
+            .collect();
+        let raw: String = bytes.iter().rev().map(|byte| format!("{:02x}", byte)).collect();
+        let natural = format!(
+            "{{v4_float = {{{}}}, v2_double = {{{}}}}}",
+            floats.join(", "),
+            doubles.join(", ")
+        );
+        (format!("0x{}", raw.trim_start_matches('0')), natural)
+    } else {
+        let value = match name {
+            "fctrl" => fpregs.cwd as u64,
+            "fstat" => fpregs.swd as u64,
+            "ftag" => fpregs.ftw as u64,
+            "fop" => fpregs.fop as u64,
+            "fioff" => fpregs.rip,
+            "fooff" => fpregs.rdp,
+            "mxcsr" => {
+                let value = fpregs.mxcsr as u64;
+                return Some(format!(
+                    "{:<15}{:<18} {}",
+                    name,
+                    hex(value),
+                    format_flags(value, &MXCSR_FLAGS)
+                ));
+            }
+            _ => return None,
+        };
+        (hex(value), value.to_string())
+    };
+    let raw = if raw == "0x" { "0x0".to_string() } else { raw };
+    Some(format!("{:<15}{:<18} {}", name, raw, natural))
+}
@@ -0,0 +1,163 @@
+//! What happens when the inferior receives a signal, set with `handle SIGNAL KEYWORDS...`.
+
+use nix::sys::signal::Signal;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::str::FromStr;
+
+/// How the debugger treats one signal.
+#[derive(Clone, Copy)]
+pub struct SignalPolicy {
+    /// Stop the inferior and give control back to the user
+    pub stop: bool,
+    /// Mention the signal when it arrives
+    pub print: bool,
+    /// Deliver the signal to the inferior when it resumes, rather than discarding it
+    pub pass: bool,
+}
+
+impl Default for SignalPolicy {
+    fn default() -> SignalPolicy {
+        SignalPolicy {
+            stop: true,
+            print: true,
+            pass: true,
+        }
+    }
+}
+
+/// Signals programs commonly receive as part of their normal operation, which gdb lets through
+/// silently.
+const QUIET_SIGNALS: [Signal; 7] = [
+    Signal::SIGALRM,
+    Signal::SIGURG,
+    Signal::SIGCHLD,
+    Signal::SIGWINCH,
+    Signal::SIGIO,
+    Signal::SIGVTALRM,
+    Signal::SIGPROF,
+];
+
+/// Signals that mean something to the debugger itself: breakpoints and single steps raise
+/// SIGTRAP, and SIGINT is how ctrl+c interrupts the program. Neither reaches the program by
+/// default.
+const DEBUGGER_SIGNALS: [Signal; 2] = [Signal::SIGTRAP, Signal::SIGINT];
+
+/// Words `handle` accepts after the signals. `ignore` is another name for `nopass`.
+const KEYWORDS: [&str; 8] = [
+    "stop", "nostop", "print", "noprint", "pass", "nopass", "ignore", "noignore",
+];
+
+/// The policy for every signal, starting out with the same defaults as gdb.
+#[derive(Clone)]
+pub struct SignalTable {
+    policies: HashMap<Signal, SignalPolicy>,
+}
+
+impl SignalTable {
+    pub fn new() -> SignalTable {
+        let mut policies = HashMap::new();
+        for sig in QUIET_SIGNALS {
+            let policy = SignalPolicy {
+                stop: false,
+                print: false,
+                pass: true,
+            };
+            policies.insert(sig, policy);
+        }
+        for sig in DEBUGGER_SIGNALS {
+            let policy = SignalPolicy {
+                pass: false,
+                ..SignalPolicy::default()
+            };
+            policies.insert(sig, policy);
+        }
+        SignalTable { policies }
+    }
+
+    pub fn get(&self, sig: Signal) -> SignalPolicy {
+        self.policies.get(&sig).copied().unwrap_or_default()
+    }
+
+    /// Carries out `handle`: the arguments are signal names (or `all`) followed by keywords that
+    /// say what to do with them. Keywords imply each other like in gdb: a signal that stops is
+    /// printed, and one that isn't printed doesn't stop. Returns the signals that were named.
+    pub fn handle(&mut self, args: &[String]) -> Result<Vec<Signal>, String> {
+        let mut signals = Vec::new();
+        let mut keywords = Vec::new();
+        for arg in args {
+            if arg == "all" {
+                // Like gdb, leave the debugger's own signals alone
+                signals.extend(Signal::iterator().filter(|sig| !DEBUGGER_SIGNALS.contains(sig)));
+            } else if let Ok(sig) = parse_signal(arg) {
+                if sig == Signal::SIGTRAP {
+                    return Err("SIGTRAP is used by the debugger.".to_string());
+                }
+                signals.push(sig);
+            } else if KEYWORDS.contains(&arg.as_str()) {
+                keywords.push(arg.as_str());
+            } else {
+                return Err(format!("Unrecognized or ambiguous flag word: \"{}\".", arg));
+            }
+        }
+        if signals.is_empty() {
+            return Err("Argument required (signal to handle).".to_string());
+        }
+        for sig in &signals {
+            let mut policy = self.get(*sig);
+            for keyword in &keywords {
+                match *keyword {
+                    "stop" => {
+                        policy.stop = true;
+                        policy.print = true;
+                    }
+                    "nostop" => policy.stop = false,
+                    "print" => policy.print = true,
+                    "noprint" => {
+                        policy.print = false;
+                        policy.stop = false;
+                    }
+                    "pass" | "noignore" => policy.pass = true,
+                    // nopass or ignore
+                    _ => policy.pass = false,
+                }
+            }
+            self.policies.insert(*sig, policy);
+        }
+        Ok(signals)
+    }
+
+    /// Prints a table of how the given signals are handled, like gdb's `info signals`.
+    pub fn print(&self, signals: &[Signal]) {
+        println!("Signal        Stop\tPrint\tPass to program\tDescription");
+        for sig in signals {
+            let policy = self.get(*sig);
+            let yes_no = |flag: bool| if flag { "Yes" } else { "No" };
+            println!(
+                "{:<14}{}\t{}\t{}\t\t{}",
+                sig.as_str(),
+                yes_no(policy.stop),
+                yes_no(policy.print),
+                yes_no(policy.pass),
+                describe(*sig)
+            );
+        }
+    }
+}
+
+/// Turns a signal name like "SIGUSR1" or a signal number into a signal.
+pub fn parse_signal(text: &str) -> Result<Signal, String> {
+    let invalid = || format!("Invalid signal \"{}\"", text);
+    match text.parse::<i32>() {
+        Ok(number) => Signal::try_from(number).map_err(|_| invalid()),
+        Err(_) => Signal::from_str(text).map_err(|_| invalid()),
+    }
+}
+
+/// The C library's description of a signal, e.g. "Segmentation fault" for SIGSEGV.
+pub fn describe(sig: Signal) -> String {
+    unsafe { CStr::from_ptr(libc::strsignal(sig as i32)) }
+        .to_string_lossy()
+        .into_owned()
+}
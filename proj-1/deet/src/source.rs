@@ -0,0 +1,89 @@
+//! Reading the program's source files, for `list` and for showing where the program stopped.
+
+use std::collections::HashMap;
+use std::fs;
+
+pub struct SourceFiles {
+    /// Rewrites of source directories from `set substitute-path FROM TO`, in the order they
+    /// were added
+    substitutions: Vec<(String, String)>,
+    /// Lines of each file we have read, by path as recorded in the debug info. None if the file
+    /// couldn't be read.
+    cache: HashMap<String, Option<Vec<String>>>,
+}
+
+impl SourceFiles {
+    pub fn new() -> SourceFiles {
+        SourceFiles {
+            substitutions: Vec::new(),
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Makes files recorded under the directory `from` be read from `to` instead, e.g. for a
+    /// program built inside a container that mounts the sources somewhere else.
+    pub fn add_substitution(&mut self, from: &str, to: &str) {
+        let from = from.trim_end_matches('/').to_string();
+        let to = to.trim_end_matches('/').to_string();
+        self.substitutions.retain(|(existing, _)| *existing != from);
+        self.substitutions.push((from, to));
+        // Files that were missing may be found now, and the others may have moved
+        self.cache.clear();
+    }
+
+    /// Applies the first substitution whose directory contains the path.
+    fn resolve(&self, path: &str) -> String {
+        for (from, to) in &self.substitutions {
+            if let Some(rest) = path.strip_prefix(from.as_str()) {
+                if rest.is_empty() || rest.starts_with('/') {
+                    return format!("{}{}", to, rest);
+                }
+            }
+        }
+        path.to_string()
+    }
+
+    fn lines(&mut self, path: &str) -> Option<&Vec<String>> {
+        let resolved = self.resolve(path);
+        self.cache
+            .entry(path.to_string())
+            .or_insert_with(|| {
+                let text = fs::read(resolved).ok()?;
+                Some(String::from_utf8_lossy(&text).lines().map(|line| line.to_string()).collect())
+            })
+            .as_ref()
+    }
+
+    /// Returns the number of lines in a file, or an error message if it can't be read.
+    pub fn line_count(&mut self, path: &str) -> Result<usize, String> {
+        let resolved = self.resolve(path);
+        self.lines(path)
+            .map(|lines| lines.len())
+            .ok_or(format!("{}: No such file or directory.", resolved))
+    }
+
+    /// Prints lines first to last (counting from 1) of a file, marking line `current` with "=>".
+    /// Returns the number of the last line printed.
+    pub fn print(
+        &mut self,
+        path: &str,
+        first: usize,
+        last: usize,
+        current: Option<usize>,
+    ) -> Result<usize, String> {
+        let count = self.line_count(path)?;
+        if first > count {
+            return Err(format!(
+                "Line number {} out of range; \"{}\" has {} lines.",
+                first, path, count
+            ));
+        }
+        let last = last.min(count);
+        let lines = self.lines(path).unwrap();
+        for number in first.max(1)..=last {
+            let marker = if current == Some(number) { "=>" } else { "  " };
+            println!("{} {:<5}{}", marker, number, lines[number - 1]);
+        }
+        Ok(last)
+    }
+}
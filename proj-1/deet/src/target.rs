@@ -0,0 +1,632 @@
+//! Access to the memory and registers of a program, whether it is a live process we are tracing
+//! or a core dump, and the commands built on it: backtraces, printing values and examining memory.
+
+use crate::dwarf_data::{DwarfData, Encoding, Language, Line, Type, TypeId, TypeKind, ValueContext, Variable};
+use crate::expr::{self, Expr};
+use crate::location::{self, Place};
+use crate::registers;
+use crate::unwind::{Frame, Unwinder};
+use nix::unistd::Pid;
+use std::cell::RefCell;
+use std::convert::TryInto;
+use std::mem::size_of;
+
+/// A region of the address space, like a line of /proc/PID/maps.
+pub struct Region {
+    pub start: usize,
+    pub end: usize,
+    /// e.g. "r-xp"
+    pub perms: String,
+    /// The mapped file, a name like "[stack]" or "[heap]", or empty for anonymous memory
+    pub path: String,
+}
+
+/// Reads the memory map of a process. Returns an empty map if it can't be read.
+pub fn read_maps(pid: Pid) -> Vec<Region> {
+    let maps = std::fs::read_to_string(format!("/proc/{}/maps", pid)).unwrap_or_default();
+    maps.lines().filter_map(parse_region).collect()
+}
+
+fn parse_region(line: &str) -> Option<Region> {
+    // start-end perms offset dev inode [path]
+    let mut fields = line.split_whitespace();
+    let (start, end) = fields.next()?.split_once('-')?;
+    let perms = fields.next()?.to_string();
+    let path = fields.nth(3).unwrap_or("").to_string();
+    Some(Region {
+        start: usize::from_str_radix(start, 16).ok()?,
+        end: usize::from_str_radix(end, 16).ok()?,
+        perms,
+        path,
+    })
+}
+
+/// Longest string shown for a char pointer.
+const MAX_STRING_LEN: usize = 200;
+
+/// Looks up the names in an expression in the target's selected stack frame.
+struct FrameContext<'a, T: ?Sized> {
+    target: &'a T,
+    debug_data: &'a DwarfData,
+    /// The selected stack frame, unwound once for the whole expression
+    frame: &'a Frame,
+}
+
+impl<T: Target + ?Sized> expr::Context for FrameContext<'_, T> {
+    fn variable(&self, name: &str) -> Result<i64, String> {
+        self.typed(&Expr::Variable(name.to_string()))
+    }
+
+    fn register(&self, name: &str) -> Result<i64, String> {
+        let regs = self.target.registers().map_err(|err| err.to_string())?;
+        registers::get_register(&regs, name)
+            .map(|value| value as i64)
+            .ok_or(format!("Invalid register \"${}\"", name))
+    }
+
+    fn typed(&self, expr: &Expr) -> Result<i64, String> {
+        self.target
+            .evaluate_value_in_frame(expr, self.debug_data, self.frame)?
+            .int_value()
+    }
+}
+
+/// Reads the memory that values refer to, such as the strings char pointers point to, to format
+/// them.
+struct MemoryContext<'a, T: ?Sized> {
+    target: &'a T,
+    debug_data: &'a DwarfData,
+}
+
+impl<T: Target + ?Sized> ValueContext for MemoryContext<'_, T> {
+    fn read_memory(&self, addr: usize, len: usize) -> Option<Vec<u8>> {
+        self.target.read_memory(addr, len).ok()
+    }
+
+    fn read_string(&self, addr: usize) -> Option<String> {
+        self.target.read_c_string(addr, MAX_STRING_LEN).ok()
+    }
+
+    fn get_type(&self, id: TypeId) -> Option<&Type> {
+        self.debug_data.get_type(id)
+    }
+}
+
+/// The result of evaluating an expression with its type, so that `print` can show whole structs
+/// and arrays.
+pub struct Value {
+    pub value_type: Type,
+    pub bytes: Vec<u8>,
+    /// Where the value lives in the program's memory, or None for computed values like `x + 1`
+    pub addr: Option<usize>,
+    /// The compiler didn't keep the value anywhere at this point in the program
+    pub optimized_out: bool,
+}
+
+impl Value {
+    fn integer(value: i64) -> Value {
+        Value {
+            value_type: Type::new("long".to_string(), size_of::<i64>()),
+            bytes: value.to_le_bytes().to_vec(),
+            addr: None,
+            optimized_out: false,
+        }
+    }
+
+    fn optimized_out(value_type: Type) -> Value {
+        Value {
+            value_type,
+            bytes: Vec::new(),
+            addr: None,
+            optimized_out: true,
+        }
+    }
+
+    /// Converts the value to an integer for arithmetic. Arrays decay to their address, like in C.
+    fn int_value(&self) -> Result<i64, String> {
+        if self.optimized_out {
+            return Err("value has been optimized out".to_string());
+        }
+        match (&self.value_type.kind, self.addr) {
+            (TypeKind::Array(..), Some(addr)) => Ok(addr as i64),
+            (TypeKind::Base(_), _) | (TypeKind::Pointer(_), _) | (TypeKind::Enum(_), _) => {
+                Ok(self.value_type.int_value(&self.bytes))
+            }
+            _ => Err("Argument to arithmetic operation not a number or boolean.".to_string()),
+        }
+    }
+}
+
+/// Prints one line of a backtrace: `#N 0xaddr in func (file:line)`, or `#N 0xaddr in func+0x1a ()`
+/// for code without debug info.
+fn print_frame(debug_data: &DwarfData, index: usize, frame: &Frame) {
+    let addr = frame.lookup_addr(index);
+    match (debug_data.get_function_from_addr(addr), debug_data.get_line_from_addr(addr)) {
+        (Some(function_name), Some(line)) => {
+            println!("#{:<2} {:#018x} in {} ({})", index, frame.rip, function_name, line)
+        }
+        // The offset is from the function's start to the pc, even for callers
+        _ => match debug_data.get_function_offset(addr) {
+            Some((function_name, offset)) if offset + frame.rip - addr == 0 => {
+                println!("#{:<2} {:#018x} in {} ()", index, frame.rip, function_name)
+            }
+            Some((function_name, offset)) => println!(
+                "#{:<2} {:#018x} in {}+{:#x} ()",
+                index,
+                frame.rip,
+                function_name,
+                offset + frame.rip - addr
+            ),
+            None => println!("#{:<2} {:#018x} in ?? ()", index, frame.rip),
+        },
+    }
+}
+
+/// A program whose state we can inspect. Implementors provide the registers of the innermost
+/// frame and raw memory; stack unwinding and evaluating expressions are built on top of those.
+pub trait Target {
+    /// Returns the registers of the selected thread in its innermost frame.
+    fn innermost_registers(&self) -> Result<libc::user_regs_struct, nix::Error>;
+
+    /// Returns the x87 and SSE registers of the selected thread.
+    fn fp_registers(&self) -> Result<libc::user_fpregs_struct, nix::Error>;
+
+    /// Reads up to len bytes of memory, stopping early at the first address that isn't mapped or
+    /// readable.
+    fn read_memory_partial(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error>;
+
+    /// Reads up to len bytes of code, with the original instructions in place of any breakpoints.
+    fn read_code(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        self.read_memory_partial(addr, len)
+    }
+
+    /// Returns the regions of the address space and the files mapped there.
+    fn memory_map(&self) -> Vec<Region>;
+
+    /// Returns the details of the signal the selected thread stopped with.
+    fn signal_info(&self) -> Result<libc::siginfo_t, nix::Error>;
+
+    /// Returns the level of the selected stack frame.
+    fn selected_frame(&self) -> usize;
+
+    fn set_selected_frame(&mut self, level: usize);
+
+    /// Returns the unwinder for the program, which keeps the memory map and call frame
+    /// information it reads while the program stays stopped.
+    fn unwinder(&self) -> &RefCell<Unwinder>;
+
+    /// Unwinds the current thread's stack, innermost frame first.
+    fn backtrace(&self) -> Result<Vec<Frame>, nix::Error> {
+        let regs = self.innermost_registers()?;
+        let mut unwinder = self.unwinder().borrow_mut();
+        unwinder.load_memory_map(|| self.memory_map());
+        Ok(unwinder.backtrace(&|addr| self.read_word(addr), &regs))
+    }
+
+    /// Returns the frames of the selected thread's stack down to main, innermost first.
+    fn frames(&self, debug_data: &DwarfData) -> Result<Vec<Frame>, nix::Error> {
+        let mut frames = self.backtrace()?;
+        // Frames above main belong to the C runtime
+        if let Some(main) = frames.iter().enumerate().position(|(index, frame)| {
+            debug_data.get_function_from_addr(frame.lookup_addr(index)).as_deref() == Some("main")
+        }) {
+            frames.truncate(main + 1);
+        }
+        Ok(frames)
+    }
+
+    /// Returns the selected stack frame of the selected thread.
+    fn frame(&self) -> Result<Frame, nix::Error> {
+        self.frame_at(self.selected_frame())
+    }
+
+    /// Returns the stack frame at the given level of the selected thread.
+    fn frame_at(&self, level: usize) -> Result<Frame, nix::Error> {
+        let regs = self.innermost_registers()?;
+        let mut unwinder = self.unwinder().borrow_mut();
+        unwinder.load_memory_map(|| self.memory_map());
+        unwinder
+            .frame(&|addr| self.read_word(addr), &regs, level)
+            .ok_or(nix::Error::EFAULT)
+    }
+
+    /// Returns the selected stack frame, for evaluating expressions in it.
+    fn unwound_frame(&self) -> Result<Frame, String> {
+        self.frame().map_err(|err| format!("Cannot unwind the stack: {}", err))
+    }
+
+    /// Makes the frame at the given level the one that variables are looked up in. Returns false
+    /// if the stack isn't that deep.
+    fn select_frame(&mut self, debug_data: &DwarfData, level: usize) -> Result<bool, nix::Error> {
+        if level >= self.frames(debug_data)?.len() {
+            return Ok(false);
+        }
+        self.set_selected_frame(level);
+        Ok(true)
+    }
+
+    /// Prints the selected thread's stack, or only its innermost `limit` frames.
+    fn print_backtrace(&self, debug_data: &DwarfData, limit: Option<usize>) -> Result<(), nix::Error> {
+        let frames = self.frames(debug_data)?;
+        let shown = limit.unwrap_or(frames.len()).min(frames.len());
+        for (index, frame) in frames[..shown].iter().enumerate() {
+            print_frame(debug_data, index, frame);
+        }
+        if shown < frames.len() {
+            println!("(More stack frames follow...)");
+        }
+        Ok(())
+    }
+
+    fn print_selected_frame(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
+        print_frame(debug_data, self.selected_frame(), &self.frame()?);
+        Ok(())
+    }
+
+    /// Returns the source line of the selected frame, if there is debug info for it.
+    fn selected_line(&self, debug_data: &DwarfData) -> Option<Line> {
+        let frame = self.frame().ok()?;
+        debug_data.get_line_from_addr(frame.lookup_addr(self.selected_frame()))
+    }
+
+    /// Prints where the selected thread stopped, and returns the source line if it is known.
+    fn print_stopped_location(&self, debug_data: &DwarfData) -> Result<Option<Line>, nix::Error> {
+        let regs = self.innermost_registers()?;
+        let rip = regs.rip as usize;
+        let line = debug_data.get_line_from_addr(rip);
+        match (&line, debug_data.get_symbol_for_addr(rip)) {
+            (Some(line), _) => println!("Stopped at {}", line),
+            (None, Some(symbol)) => println!("Stopped at {:#x} {}", rip, symbol),
+            (None, None) => println!("Stopped at {:#x}", rip),
+        }
+        Ok(line)
+    }
+
+    /// Finds a variable by name in the selected stack frame `frame`, or among the globals.
+    fn lookup_variable<'a>(
+        &self,
+        debug_data: &'a DwarfData,
+        frame: &Frame,
+        name: &str,
+    ) -> Option<&'a Variable> {
+        let local = debug_data
+            .get_function_containing(frame.lookup_addr(self.selected_frame()))
+            .and_then(|func| func.variables.iter().find(|var| var.name == name));
+        local.or_else(|| debug_data.get_global_variable(name))
+    }
+
+    /// Evaluates an expression in the context of the selected stack frame.
+    fn evaluate(&self, expr: &Expr, debug_data: &DwarfData) -> Result<i64, String> {
+        self.evaluate_in_frame(expr, debug_data, &self.unwound_frame()?)
+    }
+
+    /// Evaluates an expression in the context of the selected stack frame, which the caller has
+    /// already unwound to `frame`.
+    fn evaluate_in_frame(
+        &self,
+        expr: &Expr,
+        debug_data: &DwarfData,
+        frame: &Frame,
+    ) -> Result<i64, String> {
+        expr.eval(&FrameContext { target: self, debug_data, frame })
+    }
+
+    /// Evaluates an expression in the context of the selected stack frame, keeping track of the
+    /// type of the result.
+    fn evaluate_value(&self, expr: &Expr, debug_data: &DwarfData) -> Result<Value, String> {
+        self.evaluate_value_in_frame(expr, debug_data, &self.unwound_frame()?)
+    }
+
+    /// Like evaluate_value, in the selected stack frame that the caller has already unwound.
+    fn evaluate_value_in_frame(
+        &self,
+        expr: &Expr,
+        debug_data: &DwarfData,
+        frame: &Frame,
+    ) -> Result<Value, String> {
+        match expr {
+            Expr::Variable(name) => {
+                let var = self
+                    .lookup_variable(debug_data, frame, name)
+                    .ok_or(format!("No symbol \"{}\" in current context.", name))?;
+                match self.variable_place(debug_data, frame, var)? {
+                    Place::Memory(addr) => self.value_at(var.entity_type.clone(), addr),
+                    Place::Computed(bytes) => Ok(Value {
+                        value_type: var.entity_type.clone(),
+                        bytes,
+                        addr: None,
+                        optimized_out: false,
+                    }),
+                    Place::OptimizedOut => Ok(Value::optimized_out(var.entity_type.clone())),
+                }
+            }
+            Expr::Member(base, name) => {
+                let mut base = self.evaluate_value_in_frame(base, debug_data, frame)?;
+                // Like Rust itself, reach through references and boxes to what they point to
+                if let (Language::Rust, TypeKind::Pointer(Some(id))) =
+                    (base.value_type.language, &base.value_type.kind)
+                {
+                    if let Some(target) = debug_data.get_type(*id) {
+                        base = self.value_at(target.clone(), base.int_value()? as usize)?;
+                    }
+                }
+                if !matches!(base.value_type.kind, TypeKind::Struct(_) | TypeKind::Union(_)) {
+                    return Err(
+                        "Attempt to extract a component of a value that is not a structure."
+                            .to_string(),
+                    );
+                }
+                let (offset, member) = base
+                    .value_type
+                    .member(name)
+                    .ok_or(format!("There is no member named {}.", name))?;
+                if base.optimized_out {
+                    return Ok(Value::optimized_out(member.member_type.clone()));
+                }
+                // offset is from the start of base, member.offset from the anonymous struct or
+                // union the member may be in
+                let bytes = member.value_bytes(&base.bytes[offset - member.offset..]);
+                let addr = match member.bits {
+                    Some(_) => None,
+                    None => base.addr.map(|addr| addr + offset),
+                };
+                Ok(Value {
+                    value_type: member.member_type.clone(),
+                    bytes,
+                    addr,
+                    optimized_out: false,
+                })
+            }
+            Expr::Deref(base) => {
+                let base = self.evaluate_value_in_frame(base, debug_data, frame)?;
+                match &base.value_type.kind {
+                    TypeKind::Pointer(Some(id)) => {
+                        let target = debug_data
+                            .get_type(*id)
+                            .ok_or("Attempt to take contents of a non-pointer value.")?;
+                        self.value_at(target.clone(), base.int_value()? as usize)
+                    }
+                    TypeKind::Array(element, _) if base.addr.is_some() => {
+                        self.value_at((**element).clone(), base.addr.unwrap())
+                    }
+                    _ => Err("Attempt to take contents of a non-pointer value.".to_string()),
+                }
+            }
+            Expr::Index(base, index) => {
+                let base = self.evaluate_value_in_frame(base, debug_data, frame)?;
+                let index = self.evaluate_in_frame(index, debug_data, frame)?;
+                let (element, start) = match &base.value_type.kind {
+                    TypeKind::Array(element, _) if base.addr.is_some() => {
+                        ((**element).clone(), base.addr.unwrap())
+                    }
+                    // An array that isn't in memory, e.g. one kept in registers
+                    TypeKind::Array(element, count) => {
+                        if base.optimized_out {
+                            return Ok(Value::optimized_out((**element).clone()));
+                        }
+                        if index < 0 || index as usize >= *count {
+                            return Err("no such vector element".to_string());
+                        }
+                        let start = index as usize * element.size;
+                        return Ok(Value {
+                            value_type: (**element).clone(),
+                            bytes: base.bytes[start..start + element.size].to_vec(),
+                            addr: None,
+                            optimized_out: false,
+                        });
+                    }
+                    TypeKind::Pointer(Some(id)) if debug_data.get_type(*id).is_some() => {
+                        (debug_data.get_type(*id).unwrap().clone(), base.int_value()? as usize)
+                    }
+                    _ => {
+                        return Err(format!(
+                            "cannot subscript something of type `{}'",
+                            base.value_type.name
+                        ))
+                    }
+                };
+                let addr = start.wrapping_add((index as usize).wrapping_mul(element.size));
+                self.value_at(element, addr)
+            }
+            Expr::AddressOf(base) => {
+                let base = self.evaluate_value_in_frame(base, debug_data, frame)?;
+                let addr = base
+                    .addr
+                    .ok_or("Attempt to take address of value not located in memory.")?;
+                let pointee = &base.value_type.name;
+                let name = if base.value_type.language == Language::Rust {
+                    format!("&{}", pointee)
+                } else if pointee.ends_with('*') {
+                    format!("{}*", pointee)
+                } else {
+                    format!("{} *", pointee)
+                };
+                Ok(Value {
+                    value_type: Type {
+                        name,
+                        size: size_of::<usize>(),
+                        kind: TypeKind::Pointer(base.value_type.id),
+                        id: None,
+                        language: base.value_type.language,
+                        template_params: Vec::new(),
+                    },
+                    bytes: addr.to_le_bytes().to_vec(),
+                    addr: None,
+                    optimized_out: false,
+                })
+            }
+            _ => Ok(Value::integer(self.evaluate_in_frame(expr, debug_data, frame)?)),
+        }
+    }
+
+    /// Reads a value of the given type from the program's memory.
+    fn value_at(&self, value_type: Type, addr: usize) -> Result<Value, String> {
+        let bytes = self
+            .read_memory(addr, value_type.size)
+            .map_err(|_| format!("Cannot access memory at address {:#x}", addr))?;
+        Ok(Value {
+            value_type,
+            bytes,
+            addr: Some(addr),
+            optimized_out: false,
+        })
+    }
+
+    /// Reads the value a function of the given return type just returned, following the System V
+    /// ABI: floats come back in xmm0, other values of up to 16 bytes in rax and rdx, and larger
+    /// ones in memory whose address is left in rax. Small structs with float members, which the
+    /// ABI splits across xmm registers, aren't handled.
+    fn return_value(&self, value_type: Type) -> Result<Value, String> {
+        let regs = self.innermost_registers().map_err(|err| err.to_string())?;
+        let bytes = match value_type.kind {
+            TypeKind::Base(Encoding::Float) if value_type.size <= 8 => {
+                let fpregs = self.fp_registers().map_err(|err| err.to_string())?;
+                registers::fp_bytes(&fpregs.xmm_space)[..value_type.size].to_vec()
+            }
+            _ if value_type.size <= 2 * size_of::<u64>() => {
+                let mut bytes = [regs.rax.to_le_bytes(), regs.rdx.to_le_bytes()].concat();
+                bytes.truncate(value_type.size);
+                bytes
+            }
+            _ => return self.value_at(value_type, regs.rax as usize),
+        };
+        Ok(Value {
+            value_type,
+            bytes,
+            addr: None,
+            optimized_out: false,
+        })
+    }
+
+    /// Formats a value the way `print` shows it.
+    fn format_value(&self, value: &Value, debug_data: &DwarfData) -> String {
+        if value.optimized_out {
+            return "<optimized out>".to_string();
+        }
+        let context = MemoryContext { target: self, debug_data };
+        value.value_type.format_value(&value.bytes, &context)
+    }
+
+    /// Prints every variable (including parameters) of the function of the selected stack frame.
+    fn print_locals(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
+        let frame = self.frame()?;
+        match debug_data.get_function_containing(frame.lookup_addr(self.selected_frame())) {
+            Some(func) if !func.variables.is_empty() => {
+                for var in &func.variables {
+                    let value = self
+                        .format_variable(debug_data, &frame, var)
+                        .unwrap_or_else(|err| format!("<error: {}>", err));
+                    println!("{} = {}", var.name, value);
+                }
+            }
+            Some(_) => println!("No locals."),
+            None => println!("No symbol table info available."),
+        }
+        Ok(())
+    }
+
+    /// Returns the registers of the selected thread. In a frame other than the innermost one, rip,
+    /// rsp, rbp and the other callee-saved registers are the values they had in that frame.
+    fn registers(&self) -> Result<libc::user_regs_struct, nix::Error> {
+        let mut regs = self.innermost_registers()?;
+        if self.selected_frame() != 0 {
+            let frame = self.frame()?;
+            regs.rip = frame.rip as u64;
+            regs.rsp = frame.rsp as u64;
+            regs.rbp = frame.rbp as u64;
+            let [rbx, r12, r13, r14, r15] = frame.callee_saved.map(|value| value as u64);
+            regs.rbx = rbx;
+            regs.r12 = r12;
+            regs.r13 = r13;
+            regs.r14 = r14;
+            regs.r15 = r15;
+        }
+        Ok(regs)
+    }
+
+    /// Prints the given registers, or the general purpose, flags and segment registers if no
+    /// names are given. With `all`, the x87 and SSE registers are shown too.
+    fn print_registers(
+        &self,
+        debug_data: &DwarfData,
+        names: &[String],
+        all: bool,
+    ) -> Result<(), nix::Error> {
+        let regs = self.registers()?;
+        let fpregs = self.fp_registers()?;
+        let symbolize = |addr: u64| debug_data.get_symbol_for_addr(addr as usize);
+        let mut names: Vec<String> = names.to_vec();
+        if names.is_empty() {
+            names = registers::GENERAL_REGISTERS.iter().map(|name| name.to_string()).collect();
+            if all {
+                names.extend(registers::fp_register_names());
+            }
+        }
+        for name in names {
+            let name = name.strip_prefix('$').unwrap_or(&name);
+            match registers::get_register(&regs, name) {
+                Some(value) => println!("{}", registers::format_register(name, value, &symbolize)),
+                None => match registers::format_fp_register(&fpregs, name) {
+                    Some(line) => println!("{}", line),
+                    None => println!("Invalid register `{}'", name),
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Works out where a variable is in the selected stack frame `frame`.
+    fn variable_place(&self, debug_data: &DwarfData, frame: &Frame, var: &Variable) -> Result<Place, String> {
+        let level = self.selected_frame();
+        location::locate(self, debug_data, &var.location, frame, level, var.entity_type.size)
+    }
+
+    /// Returns the address of a variable in the selected stack frame `frame`, if it is in memory.
+    fn variable_addr(&self, debug_data: &DwarfData, frame: &Frame, var: &Variable) -> Result<usize, String> {
+        match self.variable_place(debug_data, frame, var)? {
+            Place::Memory(addr) => Ok(addr),
+            _ => Err(format!("Can't take address of \"{}\" which isn't an lvalue.", var.name)),
+        }
+    }
+
+    fn format_variable(&self, debug_data: &DwarfData, frame: &Frame, var: &Variable) -> Result<String, String> {
+        let bytes = match self.variable_place(debug_data, frame, var)? {
+            Place::Memory(addr) => self
+                .read_memory(addr, var.entity_type.size)
+                .map_err(|_| format!("Cannot access memory at address {:#x}", addr))?,
+            Place::Computed(bytes) => bytes,
+            Place::OptimizedOut => return Ok("<optimized out>".to_string()),
+        };
+        let context = MemoryContext { target: self, debug_data };
+        Ok(var.entity_type.format_value(&bytes, &context))
+    }
+
+    /// Reads len bytes of the program's memory, failing if any of them can't be read.
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let bytes = self.read_memory_partial(addr, len)?;
+        if bytes.len() < len {
+            return Err(nix::Error::EIO);
+        }
+        Ok(bytes)
+    }
+
+    /// Reads one word of memory, for the unwinder.
+    fn read_word(&self, addr: usize) -> Option<usize> {
+        let bytes = self.read_memory(addr, size_of::<usize>()).ok()?;
+        Some(usize::from_le_bytes(bytes.try_into().ok()?))
+    }
+
+    /// Reads a NUL-terminated string of at most max_len bytes from the program's memory. A string
+    /// that runs into unmapped memory is cut off there; it is an error only if not even its first
+    /// byte can be read.
+    fn read_c_string(&self, addr: usize, max_len: usize) -> Result<String, nix::Error> {
+        let mut bytes = self.read_memory_partial(addr, max_len)?;
+        if bytes.is_empty() && max_len > 0 {
+            return Err(nix::Error::EFAULT);
+        }
+        if let Some(end) = bytes.iter().position(|b| *b == 0) {
+            bytes.truncate(end);
+        }
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
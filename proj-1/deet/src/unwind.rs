@@ -0,0 +1,340 @@
+use gimli::{
+    BaseAddresses, CfaRule, DebugFrame, EhFrame, LittleEndian, Reader, Register, RegisterRule,
+    UnwindContext, UnwindSection, UnwindTableRow, X86_64,
+};
+use crate::target::Region;
+use libc::user_regs_struct;
+use object::{Object, ObjectSection, ObjectSegment};
+use std::collections::HashMap;
+use std::fs;
+
+/// Reads a word of the program's memory, or returns None if the address isn't readable.
+type Read<'a> = &'a dyn Fn(usize) -> Option<usize>;
+
+/// Give up on stacks deeper than this, in case the unwind info leads us in circles.
+const MAX_FRAMES: usize = 1024;
+
+/// Registers other than rbp that a function must preserve for its caller, and that optimized
+/// code therefore keeps variables in across calls.
+pub const CALLEE_SAVED: [Register; 5] =
+    [X86_64::RBX, X86_64::R12, X86_64::R13, X86_64::R14, X86_64::R15];
+
+/// The registers we track for one frame of the program's stack.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    /// Address of the instruction being executed. In caller frames this is the return address.
+    pub rip: usize,
+    pub rsp: usize,
+    pub rbp: usize,
+    /// Canonical frame address: the value of rsp just before the call into this frame.
+    pub cfa: usize,
+    /// Values of the CALLEE_SAVED registers in this frame
+    pub callee_saved: [usize; 5],
+}
+
+impl Frame {
+    /// Address to use when looking up debug info for this frame. For callers, the return address
+    /// may already belong to the next line (or even the next function), so we back up by one.
+    pub fn lookup_addr(&self, index: usize) -> usize {
+        if index == 0 {
+            self.rip
+        } else {
+            self.rip - 1
+        }
+    }
+
+    /// Returns the value of a register in this frame, given its DWARF register number, or None
+    /// if it isn't known. Only the registers a function preserves for its caller can be recovered
+    /// in frames other than the innermost one.
+    pub fn register(&self, register: Register) -> Option<usize> {
+        match register {
+            X86_64::RA => Some(self.rip),
+            X86_64::RSP => Some(self.rsp),
+            X86_64::RBP => Some(self.rbp),
+            _ => CALLEE_SAVED
+                .iter()
+                .position(|saved| *saved == register)
+                .map(|index| self.callee_saved[index]),
+        }
+    }
+
+    /// The frame base that DW_OP_fbreg locations are relative to, which gcc makes the CFA. For
+    /// the outermost frame we don't know the CFA, so assume the standard prologue was run.
+    pub fn frame_base(&self) -> usize {
+        if self.cfa != 0 {
+            self.cfa
+        } else {
+            self.rbp + 2 * std::mem::size_of::<usize>()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Rule {
+    Undefined,
+    SameValue,
+    Offset(i64),
+    Unsupported,
+}
+
+/// How to recover the caller's registers at a particular address.
+#[derive(Debug, Clone, Copy)]
+struct Row {
+    cfa_register: u16,
+    cfa_offset: i64,
+    ra: Rule,
+    rbp: Rule,
+    callee_saved: [Rule; 5],
+}
+
+/// Call frame information for one file mapped into the program.
+struct Module {
+    /// Difference between the runtime addresses and the addresses in the file.
+    bias: u64,
+    eh_frame: Option<(Vec<u8>, u64)>,
+    debug_frame: Option<Vec<u8>>,
+    text: u64,
+    got: u64,
+}
+
+impl Module {
+    fn load(path: &str, base: u64) -> Option<Module> {
+        let data = fs::read(path).ok()?;
+        let file = object::File::parse(&*data).ok()?;
+        let first_vaddr = file
+            .segments()
+            .find(|segment| segment.file_range().0 == 0)
+            .map(|segment| segment.address() & !0xfff)?;
+        let section = |name| {
+            file.section_by_name(name)
+                .and_then(|section| Some((section.data().ok()?.to_vec(), section.address())))
+        };
+        let address = |name| section(name).map(|(_, address)| address).unwrap_or(0);
+        Some(Module {
+            bias: base.wrapping_sub(first_vaddr),
+            eh_frame: section(".eh_frame"),
+            debug_frame: section(".debug_frame").map(|(data, _)| data),
+            text: address(".text"),
+            got: address(".got"),
+        })
+    }
+
+    fn row_for(&self, addr: u64) -> Option<Row> {
+        let addr = addr.wrapping_sub(self.bias);
+        let mut ctx = UnwindContext::new();
+        if let Some((data, address)) = &self.eh_frame {
+            let eh_frame = EhFrame::new(data, LittleEndian);
+            let bases = BaseAddresses::default()
+                .set_eh_frame(*address)
+                .set_text(self.text)
+                .set_got(self.got);
+            if let Ok(row) =
+                eh_frame.unwind_info_for_address(&bases, &mut ctx, addr, EhFrame::cie_from_offset)
+            {
+                return convert_row(row);
+            }
+        }
+        if let Some(data) = &self.debug_frame {
+            let mut debug_frame = DebugFrame::new(data, LittleEndian);
+            debug_frame.set_address_size(8);
+            let bases = BaseAddresses::default().set_text(self.text);
+            if let Ok(row) = debug_frame.unwind_info_for_address(
+                &bases,
+                &mut ctx,
+                addr,
+                DebugFrame::cie_from_offset,
+            ) {
+                return convert_row(row);
+            }
+        }
+        None
+    }
+}
+
+fn convert_row<R: Reader>(row: &UnwindTableRow<R>) -> Option<Row> {
+    let (cfa_register, cfa_offset) = match row.cfa() {
+        CfaRule::RegisterAndOffset { register, offset } => (register.0, *offset),
+        CfaRule::Expression(_) => return None,
+    };
+    // Callee-saved registers that the CFI says nothing about still hold the caller's values
+    let callee_saved_rule = |register| match convert_rule(row.register(register)) {
+        Rule::Undefined => Rule::SameValue,
+        rule => rule,
+    };
+    Some(Row {
+        cfa_register,
+        cfa_offset,
+        ra: convert_rule(row.register(X86_64::RA)),
+        rbp: callee_saved_rule(X86_64::RBP),
+        callee_saved: CALLEE_SAVED.map(callee_saved_rule),
+    })
+}
+
+fn convert_rule<R: Reader>(rule: RegisterRule<R>) -> Rule {
+    match rule {
+        RegisterRule::Undefined => Rule::Undefined,
+        RegisterRule::SameValue => Rule::SameValue,
+        RegisterRule::Offset(offset) => Rule::Offset(offset),
+        _ => Rule::Unsupported,
+    }
+}
+
+/// A file mapped into the program's address space.
+struct Mapping {
+    start: u64,
+    end: u64,
+    path: String,
+}
+
+/// Walks the program's stack using the call frame information in .eh_frame/.debug_frame of each
+/// mapped file, falling back to the frame pointer chain where there is none. What it reads is
+/// kept between walks: the memory map until the program runs again, and the call frame
+/// information of each file until libraries are loaded or unloaded.
+#[derive(Default)]
+pub struct Unwinder {
+    /// None until the memory map is read for the current stop
+    mappings: Option<Vec<Mapping>>,
+    /// Lowest address each file is mapped at, i.e. where its first segment was loaded
+    bases: HashMap<String, u64>,
+    modules: HashMap<String, Option<Module>>,
+}
+
+impl Unwinder {
+    pub fn new() -> Unwinder {
+        Unwinder::default()
+    }
+
+    /// Reads the memory map with `memory_map` unless it is already known for this stop.
+    pub fn load_memory_map(&mut self, memory_map: impl FnOnce() -> Vec<Region>) {
+        if self.mappings.is_some() {
+            return;
+        }
+        let mut mappings = Vec::new();
+        self.bases.clear();
+        for region in memory_map().iter().filter(|region| region.path.starts_with('/')) {
+            let (start, end) = (region.start as u64, region.end as u64);
+            let base = self.bases.entry(region.path.clone()).or_insert(start);
+            *base = (*base).min(start);
+            mappings.push(Mapping {
+                start,
+                end,
+                path: region.path.clone(),
+            });
+        }
+        self.mappings = Some(mappings);
+    }
+
+    /// Forgets the memory map, which may change once the program runs.
+    pub fn forget_memory_map(&mut self) {
+        self.mappings = None;
+    }
+
+    /// Forgets everything, for when files are mapped or unmapped: libraries are loaded or
+    /// unloaded, or the program calls exec.
+    pub fn clear(&mut self) {
+        self.mappings = None;
+        self.modules.clear();
+    }
+
+    fn row_for(&mut self, addr: u64) -> Option<Row> {
+        let path = &self
+            .mappings
+            .as_ref()?
+            .iter()
+            .find(|mapping| mapping.start <= addr && addr < mapping.end)?
+            .path;
+        let base = self.bases[path];
+        self.modules
+            .entry(path.clone())
+            .or_insert_with(|| Module::load(path, base))
+            .as_ref()?
+            .row_for(addr)
+    }
+
+    /// Computes the frame that called `frame`, or None if `frame` is the outermost one.
+    fn caller(&mut self, read: Read, frame: &Frame, index: usize) -> Option<Frame> {
+        let row = self.row_for(frame.lookup_addr(index) as u64);
+        let caller = match row {
+            Some(row) if !matches!(row.ra, Rule::Unsupported) => {
+                let register = match row.cfa_register {
+                    r if r == X86_64::RSP.0 => frame.rsp,
+                    r if r == X86_64::RBP.0 => frame.rbp,
+                    _ => return None,
+                };
+                let cfa = (register as i64 + row.cfa_offset) as usize;
+                let rip = match row.ra {
+                    Rule::Offset(offset) => read((cfa as i64 + offset) as usize)?,
+                    _ => return None,
+                };
+                let restore = |rule: Rule, value: usize| match rule {
+                    Rule::Offset(offset) => read((cfa as i64 + offset) as usize),
+                    _ => Some(value),
+                };
+                let mut callee_saved = frame.callee_saved;
+                for (value, rule) in callee_saved.iter_mut().zip(row.callee_saved) {
+                    *value = restore(rule, *value)?;
+                }
+                Frame {
+                    rip,
+                    rsp: cfa,
+                    rbp: restore(row.rbp, frame.rbp)?,
+                    cfa: 0,
+                    callee_saved,
+                }
+            }
+            // No usable CFI: assume the standard prologue (push rbp; mov rbp, rsp)
+            _ => {
+                if frame.rbp == 0 {
+                    return None;
+                }
+                let cfa = frame.rbp + 16;
+                Frame {
+                    rip: read(frame.rbp + 8)?,
+                    rsp: cfa,
+                    rbp: read(frame.rbp)?,
+                    cfa: 0,
+                    callee_saved: frame.callee_saved,
+                }
+            }
+        };
+        if caller.rip == 0 || caller.rsp <= frame.rsp {
+            return None;
+        }
+        Some(caller)
+    }
+
+    /// Returns the chain of frames starting at the given registers, innermost first.
+    pub fn backtrace(&mut self, read: Read, regs: &user_regs_struct) -> Vec<Frame> {
+        self.walk(read, regs, MAX_FRAMES)
+    }
+
+    /// Returns the frame `level` calls up from the given registers, if the stack is that deep.
+    pub fn frame(&mut self, read: Read, regs: &user_regs_struct, level: usize) -> Option<Frame> {
+        self.walk(read, regs, level + 1).get(level).copied()
+    }
+
+    fn walk(&mut self, read: Read, regs: &user_regs_struct, limit: usize) -> Vec<Frame> {
+        let mut frame = Frame {
+            rip: regs.rip as usize,
+            rsp: regs.rsp as usize,
+            rbp: regs.rbp as usize,
+            cfa: 0,
+            callee_saved: [regs.rbx, regs.r12, regs.r13, regs.r14, regs.r15]
+                .map(|value| value as usize),
+        };
+        let mut frames = Vec::new();
+        while frames.len() < limit {
+            let caller = self.caller(read, &frame, frames.len());
+            if let Some(caller) = caller {
+                // The caller's stack pointer is the value it had just before calling us
+                frame.cfa = caller.rsp;
+            }
+            frames.push(frame);
+            match caller {
+                Some(caller) => frame = caller,
+                None => break,
+            }
+        }
+        frames
+    }
+}
@@ -0,0 +1,93 @@
+//! Golden-output tests: each script in tests/golden is run against the sample program of the
+//! same name with `deet -batch -x`, and what deet prints must match the .out file next to it.
+//! Run with DEET_BLESS=1 to write the current output as the expected one.
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+/// Serializes building the samples, since make may be run for several tests at once.
+static MAKE: Mutex<()> = Mutex::new(());
+
+/// Runs tests/golden/NAME.deet against samples/NAME, compares its output to tests/golden/NAME.out
+/// and checks that deet exits with the program's exit status.
+fn check(name: &str, exit_status: i32) {
+    let root = env!("CARGO_MANIFEST_DIR");
+    let program = format!("samples/{}", name);
+    {
+        let _lock = MAKE.lock().unwrap_or_else(|err| err.into_inner());
+        let status = Command::new("make")
+            .args(["-s", &program])
+            .current_dir(root)
+            .status()
+            .expect("failed to run make");
+        assert!(status.success(), "failed to build {}", program);
+    }
+
+    let script = format!("tests/golden/{}.deet", name);
+    let output = Command::new(env!("CARGO_BIN_EXE_deet"))
+        .args(["-batch", "-x", &script, &program])
+        .current_dir(root)
+        .env("HOME", std::env::temp_dir())
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .expect("failed to run deet");
+    // Source paths are absolute, so make them relative to where the tests run
+    let stdout = String::from_utf8_lossy(&output.stdout).replace(&format!("{}/", root), "");
+
+    let expected_path = Path::new(root).join(format!("tests/golden/{}.out", name));
+    if std::env::var_os("DEET_BLESS").is_some() {
+        fs::write(&expected_path, &stdout).unwrap();
+    }
+    let expected = fs::read_to_string(&expected_path).unwrap_or_default();
+    assert_eq!(stdout, expected, "output of {} differs from {}", script, expected_path.display());
+    assert_eq!(output.status.code(), Some(exit_status));
+}
+
+#[test]
+fn function_calls() {
+    check("function_calls", 0);
+}
+
+#[test]
+fn structs() {
+    check("structs", 0);
+}
+
+#[test]
+fn segfault() {
+    // Killed by SIGSEGV, which a shell reports as 128 + 11
+    check("segfault", 139);
+}
+
+#[test]
+fn sleepy_print() {
+    check("sleepy_print", 1);
+}
+
+#[test]
+fn shared() {
+    check("shared", 0);
+}
+
+#[test]
+fn rust_types() {
+    check("rust_types", 0);
+}
+
+#[test]
+fn optimized() {
+    check("optimized", 0);
+}
+
+#[test]
+fn signals() {
+    check("signals", 0);
+}
+
+#[test]
+fn step_over() {
+    check("step_over", 0);
+}